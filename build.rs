@@ -0,0 +1,24 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Embed git commit and build time so /version can report exactly what is deployed
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    println!("cargo:rustc-env=ZEPHYR_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=ZEPHYR_BUILD_TIMESTAMP={}", build_timestamp);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+}
@@ -1,9 +1,74 @@
 use aws_config::{meta::region::RegionProviderChain, BehaviorVersion, Region};
-use aws_sdk_bedrockruntime::{Client, primitives::Blob};
+use aws_sdk_bedrockruntime::{Client, error::{ProvideErrorMetadata, SdkError}, primitives::Blob};
+use aws_sdk_bedrockruntime::operation::RequestId;
+use aws_sdk_bedrockruntime::config::http::HttpResponse;
+use aws_sdk_bedrockruntime::operation::invoke_model::InvokeModelError;
+use aws_smithy_types::error::display::DisplayErrorContext;
 use serde::{Deserialize, Serialize};
 use base64::{Engine as _, engine::general_purpose};
+use image::{GenericImageView, ImageFormat, imageops::FilterType};
 use anyhow::Result;
+use std::fmt;
 use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::util::debug_dump::DebugDump;
+use crate::util::image_mask::InvalidOptionError;
+use crate::util::sdxl::{SDXL_SIZES, nearest_sdxl_size};
+
+// Tunable SDXL settings; by default the model picks the size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SdxlParams {
+    size: Option<(u32, u32)>,
+    // Image-to-image only: how much of the init image survives, 0..=1. Higher stays
+    // closer to the input (1 returns it nearly unchanged), lower lets the prompt repaint more.
+    image_strength: f32,
+}
+
+impl Default for SdxlParams {
+    fn default() -> Self {
+        Self { size: None, image_strength: Self::DEFAULT_IMAGE_STRENGTH }
+    }
+}
+
+// An image_strength outside Bedrock's accepted 0..=1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidImageStrength(pub f32);
+
+impl fmt::Display for InvalidImageStrength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "image_strength must be between 0 and 1, got {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidImageStrength {}
+
+impl SdxlParams {
+    // Keeps the bike recognisable while leaving room for the requested change
+    pub const DEFAULT_IMAGE_STRENGTH: f32 = 0.35;
+
+    pub fn with_image_strength(mut self, strength: f32) -> std::result::Result<Self, InvalidImageStrength> {
+        if !(0.0..=1.0).contains(&strength) {
+            return Err(InvalidImageStrength(strength));
+        }
+        self.image_strength = strength;
+        Ok(self)
+    }
+
+    // Request a specific output size, which must be one of `SDXL_SIZES`
+    pub fn with_size(mut self, width: u32, height: u32) -> std::result::Result<Self, InvalidOptionError> {
+        if !SDXL_SIZES.iter().any(|&(w, h, _)| (w, h) == (width, height)) {
+            return Err(InvalidOptionError {
+                value: format!("{}x{}", width, height),
+                expected: SDXL_SIZES.iter().map(|&(_, _, name)| name).collect(),
+            });
+        }
+        self.size = Some((width, height));
+        Ok(self)
+    }
+}
 
 // Stable Diffusion XL request structure
 #[derive(Serialize, Debug)]
@@ -23,6 +88,10 @@ struct StableDiffusionRequest {
     style_preset: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     seed: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
 }
 
 #[derive(Serialize, Debug)]
@@ -44,38 +113,227 @@ struct ImageArtifact {
     finish_reason: String,
 }
 
+// An invoke that got no answer within the generator's budget
+#[derive(Debug)]
+pub struct BedrockTimeout {
+    pub region: String,
+    pub after: Duration,
+}
+
+impl fmt::Display for BedrockTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bedrock did not respond within {}s (region {})", self.after.as_secs_f32(), self.region)
+    }
+}
+
+impl std::error::Error for BedrockTimeout {}
+
+// Whether a failure says Bedrock itself is in trouble: no answer in time, a transport
+// failure or a server-side error. Refused requests (validation, content, credentials)
+// are the caller's problem and shouldn't trip the Bedrock breaker.
+pub fn is_bedrock_outage(error: &anyhow::Error) -> bool {
+    if error.downcast_ref::<BedrockTimeout>().is_some() {
+        return true;
+    }
+    match error.downcast_ref::<SdkError<InvokeModelError, HttpResponse>>() {
+        Some(SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_)) => true,
+        Some(SdkError::ServiceError(e)) => {
+            matches!(
+                e.err(),
+                InvokeModelError::InternalServerException(_)
+                    | InvokeModelError::ServiceUnavailableException(_)
+                    | InvokeModelError::ModelNotReadyException(_)
+                    | InvokeModelError::ModelTimeoutException(_)
+            ) || e.raw().status().is_server_error()
+        }
+        _ => false,
+    }
+}
+
+// Bedrock rejected the session's security token, typically expired SSO or STS credentials.
+// Every region would say the same, so this is never failed over.
+#[derive(Debug)]
+pub struct BedrockCredentialsExpired {
+    pub region: String,
+    // The AWS error code, ExpiredTokenException or UnrecognizedClientException
+    pub code: String,
+}
+
+impl fmt::Display for BedrockCredentialsExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AWS credentials expired — refresh your session ({} in region {})", self.code, self.region)
+    }
+}
+
+impl std::error::Error for BedrockCredentialsExpired {}
+
 pub struct BedrockImageGenerator {
-    client: Client,
+    // Ordered by preference, later regions are only tried when earlier ones fail over
+    clients: Vec<(String, Client)>,
+    debug_dump: Option<DebugDump>,
+    params: SdxlParams,
+    // Per invoke; a hung call would otherwise hold its worker forever
+    timeout: Duration,
+    // Last `model_access` outcome, reused for `MODEL_ACCESS_TTL`
+    model_access: Mutex<Option<(Instant, std::result::Result<(), String>)>>,
 }
 
 impl BedrockImageGenerator {
-    // Initialize the Bedrock client
-    pub async fn new() -> Result<Self> {
-        let region_provider = RegionProviderChain::default_provider()
-            .or_else(Region::new("us-west-2"));
-
-        let config = aws_config::defaults(BehaviorVersion::latest())
-            .region(region_provider)
-            .load()
-            .await;
-        
-        let client = Client::new(&config);
-        
-        Ok(Self { client })
+    const MODEL_ID: &str = "stability.stable-diffusion-xl-v1";
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+    const MODEL_ACCESS_TTL: Duration = Duration::from_secs(30);
+
+    // Initialize the Bedrock client(s)
+    // BEDROCK_REGIONS (e.g. "us-west-2,us-east-1") enables failover across regions
+    // Regions are tried in order; with none given the default region chain is used
+    pub async fn new(regions: &[String]) -> Result<Self> {
+        if regions.is_empty() {
+            let region_provider = RegionProviderChain::default_provider()
+                .or_else(Region::new("us-west-2"));
+
+            let config = aws_config::defaults(BehaviorVersion::latest())
+                .region(region_provider)
+                .load()
+                .await;
+
+            return Ok(Self::from_client(Client::new(&config)));
+        }
+
+        let mut clients = Vec::with_capacity(regions.len());
+        for region in regions.iter().cloned() {
+            let config = aws_config::defaults(BehaviorVersion::latest())
+                .region(Region::new(region.clone()))
+                .load()
+                .await;
+            clients.push((region, Client::new(&config)));
+        }
+
+        info!("Bedrock regions (in failover order): {:?}", clients.iter().map(|(r, _)| r).collect::<Vec<_>>());
+        Ok(Self::from_regional_clients(clients))
+    }
+
+    // Wrap an already configured Bedrock client
+    pub fn from_client(client: Client) -> Self {
+        let region = client.config().region()
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| "default".to_string());
+        Self::from_regional_clients(vec![(region, client)])
+    }
+
+    // Use several (region, client) pairs in failover order
+    pub fn from_regional_clients(clients: Vec<(String, Client)>) -> Self {
+        Self {
+            clients,
+            debug_dump: None,
+            params: SdxlParams::default(),
+            timeout: Self::DEFAULT_TIMEOUT,
+            model_access: Mutex::new(None),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Dump request/response bodies to `dir` when set
+    pub fn with_debug_dump(mut self, dir: Option<std::path::PathBuf>) -> Self {
+        self.debug_dump = dir.map(|dir| DebugDump::new(dir, Vec::new()));
+        self
+    }
+
+    pub fn with_params(mut self, params: SdxlParams) -> Self {
+        self.params = params;
+        self
     }
 
     // Encode image to base64
     fn encode_image(&self, image_path: &str) -> Result<String> {
         let image_data = fs::read(image_path)?;
+        let (width, height) = encoded_dimensions(&image_data)?;
+        let image_data = fit_to_size(&image_data, nearest_sdxl_size(width, height), FilterType::Lanczos3)?;
         Ok(general_purpose::STANDARD.encode(image_data)) // General purpose? Then, is there a special purpose?
     }
 
     /// Generate image from text (Text-to-Image)
+    #[allow(dead_code)]
     pub async fn generate_from_text(
         &self,
         prompt: &str,
         negative_prompt: Option<&str>,
     ) -> Result<Vec<u8>> {
+        let request = Self::text_request(prompt, negative_prompt, &self.params);
+        self.invoke_model(request).await
+    }
+
+    // Touch every regional endpoint with an empty body. Bedrock rejects it with a
+    // ValidationException before running the model, so nothing is billed, but the
+    // credential chain gets resolved and a connection is left in the pool.
+    pub async fn warm_up(&self) -> Result<()> {
+        for (region, client) in &self.clients {
+            self.empty_invoke(client).await.map_err(|e| anyhow::anyhow!("{}: {}", region, e))?;
+        }
+        Ok(())
+    }
+
+    // Whether `MODEL_ID` is invokable with these credentials in at least one region, for
+    // readiness checks. Access is checked before the body is validated, so the same free
+    // empty-body call as `warm_up` tells a denied model apart from an allowed one.
+    // The answer is reused for a short while so frequent probes don't each hit AWS.
+    pub async fn model_access(&self) -> std::result::Result<(), String> {
+        if let Some((checked, result)) = &*self.model_access.lock().unwrap()
+            && checked.elapsed() < Self::MODEL_ACCESS_TTL
+        {
+            return result.clone();
+        }
+
+        let mut failures = Vec::new();
+        for (region, client) in &self.clients {
+            match self.empty_invoke(client).await {
+                Ok(()) => {
+                    failures.clear();
+                    break;
+                }
+                Err(e) => failures.push(format!("{}: {}", region, e)),
+            }
+        }
+        let result = match failures.is_empty() {
+            true => Ok(()),
+            false => Err(format!("{} is not invokable ({})", Self::MODEL_ID, failures.join("; "))),
+        };
+
+        *self.model_access.lock().unwrap() = Some((Instant::now(), result.clone()));
+        result
+    }
+
+    pub fn model_id(&self) -> &'static str {
+        Self::MODEL_ID
+    }
+
+    // Invoke with an empty body; the ValidationException it earns means the call was authorized.
+    // Bounded like a real invoke, so a stalled region fails the readiness probe instead of hanging it.
+    async fn empty_invoke(&self, client: &Client) -> std::result::Result<(), String> {
+        let call = client
+            .invoke_model()
+            .model_id(Self::MODEL_ID)
+            .content_type("application/json")
+            .accept("application/json")
+            .body(Blob::new("{}"))
+            .send();
+        let Ok(result) = tokio::time::timeout(self.timeout, call).await else {
+            return Err(format!("no response within {}s", self.timeout.as_secs_f32()));
+        };
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_validation_exception()) => Ok(()),
+            Err(e) => Err(DisplayErrorContext(&e).to_string()),
+        }
+    }
+
+    // Build the text-to-image request body
+    // Only text-to-image takes a size, image inputs fix the output size themselves
+    fn text_request(prompt: &str, negative_prompt: Option<&str>, params: &SdxlParams) -> StableDiffusionRequest {
         let mut text_prompts = vec![
             TextPrompt {
                 text: prompt.to_string(),
@@ -90,7 +348,7 @@ impl BedrockImageGenerator {
             });
         }
         
-        let request = StableDiffusionRequest {
+        StableDiffusionRequest {
             text_prompts,
             init_image: None,
             mask_source: None,
@@ -100,21 +358,24 @@ impl BedrockImageGenerator {
             steps: 50,
             style_preset: Some("photographic".to_string()),
             seed: None,
-        };
-        
-        self.invoke_model(request).await
+            width: params.size.map(|(width, _)| width),
+            height: params.size.map(|(_, height)| height),
+        }
     }
 
-    // Generate image from image (Image-to-Image)
+    // Generate image from image (Image-to-Image), with the generator's `SdxlParams` strength
+    #[allow(dead_code)]
     pub async fn generate_from_image(
         &self,
         base_image_path: &str,
         prompt: &str,
-        image_strength: f32,
     ) -> Result<Vec<u8>> {
         let base_image = self.encode_image(base_image_path)?;
-        
-        let request = StableDiffusionRequest {
+        self.invoke_model(Self::image_request(base_image, prompt, &self.params)).await
+    }
+
+    fn image_request(base_image: String, prompt: &str, params: &SdxlParams) -> StableDiffusionRequest {
+        StableDiffusionRequest {
             text_prompts: vec![
                 TextPrompt {
                     text: prompt.to_string(),
@@ -125,26 +386,58 @@ impl BedrockImageGenerator {
             mask_source: None,
             mask_image: None,
             cfg_scale: 7.0,
-            image_strength: Some(image_strength),
+            image_strength: Some(params.image_strength),
             steps: 50,
             style_preset: Some("photographic".to_string()),
             seed: None,
-        };
-        
-        self.invoke_model(request).await
+            width: None,
+            height: None,
+        }
     }
 
-    // Inpainting (Modify part of an image)
-    pub async fn inpaint(
+    /// Inpainting (Modify part of an image) from in-memory image and mask bytes
+    pub async fn inpaint_bytes(
         &self,
-        base_image_path: &str,
-        mask_image_path: &str,
+        base_image: &[u8],
+        mask_image: &[u8],
         prompt: &str,
         negative_prompt: Option<&str>,
+        seed: Option<u32>,
     ) -> Result<Vec<u8>> {
-        let base_image = self.encode_image(base_image_path)?;
-        let mask_image = self.encode_image(mask_image_path)?;
+        // A mask made for a different image would be silently stretched over this one below
+        let (width, height) = encoded_dimensions(base_image)?;
+        let (mask_width, mask_height) = encoded_dimensions(mask_image)?;
+        if (mask_width, mask_height) != (width, height) {
+            anyhow::bail!(
+                "Mask is {}x{} but the base image is {}x{}; the mask must be made from the same image",
+                mask_width, mask_height, width, height
+            );
+        }
+
+        // SDXL only takes init images at one of its trained sizes, and the mask must match
+        let size = nearest_sdxl_size(width, height);
+        let base_image = fit_to_size(base_image, size, FilterType::Lanczos3)?;
+        let mask_image = fit_to_size(mask_image, size, FilterType::Triangle)?;
+
+        let request = Self::inpaint_request(
+            general_purpose::STANDARD.encode(base_image),
+            general_purpose::STANDARD.encode(mask_image),
+            prompt,
+            negative_prompt,
+            seed,
+        );
         
+        self.invoke_model(request).await
+    }
+
+    // Build the inpainting request body, a fixed seed makes it reproducible
+    fn inpaint_request(
+        base_image: String,
+        mask_image: String,
+        prompt: &str,
+        negative_prompt: Option<&str>,
+        seed: Option<u32>,
+    ) -> StableDiffusionRequest {
         let mut text_prompts = vec![
             TextPrompt {
                 text: prompt.to_string(),
@@ -159,7 +452,7 @@ impl BedrockImageGenerator {
             });
         }
         
-        let request = StableDiffusionRequest {
+        StableDiffusionRequest {
             text_prompts,
             init_image: Some(base_image),
             mask_source: Some("MASK_IMAGE_BLACK".to_string()),
@@ -168,35 +461,452 @@ impl BedrockImageGenerator {
             image_strength: None,
             steps: 50,
             style_preset: Some("photographic".to_string()),
-            seed: None,
+            seed,
+            width: None,
+            height: None,
+        }
+    }
+
+    // Errors that are specific to one region and worth retrying elsewhere
+    fn should_fail_over<R>(error: &SdkError<InvokeModelError, R>) -> bool {
+        matches!(
+            error.as_service_error(),
+            Some(InvokeModelError::ThrottlingException(_))
+                | Some(InvokeModelError::ModelNotReadyException(_))
+                | Some(InvokeModelError::ServiceUnavailableException(_))
+                | Some(InvokeModelError::ResourceNotFoundException(_))
+        )
+    }
+
+    // The error code when the call failed because the credentials are no longer accepted
+    fn expired_credentials_code<E: ProvideErrorMetadata, R>(error: &SdkError<E, R>) -> Option<&str> {
+        const CODES: [&str; 3] = ["ExpiredToken", "ExpiredTokenException", "UnrecognizedClientException"];
+        error.code().filter(|code| CODES.contains(code))
+    }
+
+    // Decode the first image, naming the model and request id in errors so they can go in a support ticket
+    fn first_artifact(body: &[u8], model_id: &str, request_id: Option<&str>) -> Result<Vec<u8>> {
+        let context = format!("model {}, request id {}", model_id, request_id.unwrap_or("unknown"));
+
+        let response: StableDiffusionResponse = serde_json::from_slice(body)
+            .map_err(|e| anyhow::anyhow!("Failed to parse Bedrock response ({}): {}", context, e))?;
+
+        let Some(artifact) = response.artifacts.first() else {
+            anyhow::bail!("Bedrock returned no artifacts ({})", context);
         };
-        
-        self.invoke_model(request).await
+        // e.g. CONTENT_FILTERED comes back as a blurred placeholder rather than an error
+        if artifact.finish_reason != "SUCCESS" {
+            anyhow::bail!(
+                "Bedrock returned no usable image: finishReason {} ({})",
+                artifact.finish_reason,
+                context
+            );
+        }
+
+        Ok(general_purpose::STANDARD.decode(&artifact.base64)?)
     }
 
-    // Call Bedrock API
+    // Call Bedrock API, failing over to the next region on region-specific errors
     async fn invoke_model(&self, request: StableDiffusionRequest) -> Result<Vec<u8>> {
         let body_json = serde_json::to_string(&request)?;
-        let body_blob = Blob::new(body_json.as_bytes());
-        
-        let response = self.client
-            .invoke_model()
-            .model_id("stability.stable-diffusion-xl-v1")
-            .content_type("application/json")
-            .accept("application/json")
-            .body(body_blob)
-            .send()
-            .await?;
-        
-        let body_bytes = response.body.as_ref();
-        let response_body: StableDiffusionResponse = 
-            serde_json::from_slice(body_bytes)?;
-        
-        if let Some(artifact) = response_body.artifacts.first() {
-            let image_bytes = general_purpose::STANDARD.decode(&artifact.base64)?;
-            Ok(image_bytes)
-        } else {
-            anyhow::bail!("No image generated")
+        let model_id = Self::MODEL_ID;
+
+        let mut regions = self.clients.iter().peekable();
+
+        while let Some((region, client)) = regions.next() {
+            let dump = self.debug_dump.as_ref().map(|d| d.call("bedrock", region));
+            if let Some(dump) = &dump {
+                dump.request(&body_json);
+            }
+
+            let started = Instant::now();
+            let call = client
+                .invoke_model()
+                .model_id(model_id)
+                .content_type("application/json")
+                .accept("application/json")
+                .body(Blob::new(body_json.as_bytes()))
+                .send();
+            let Ok(result) = tokio::time::timeout(self.timeout, call).await else {
+                warn!(provider = "bedrock", region = %region, timeout_secs = self.timeout.as_secs_f32(), "invoke timed out");
+                return Err(BedrockTimeout { region: region.clone(), after: self.timeout }.into());
+            };
+            let latency_ms = started.elapsed().as_millis() as u64;
+
+            let response = match result {
+                Ok(response) => response,
+                Err(e) if regions.peek().is_some() && Self::should_fail_over(&e) => {
+                    warn!(
+                        provider = "bedrock",
+                        region = %region,
+                        latency_ms,
+                        "region unavailable, failing over: {}",
+                        DisplayErrorContext(&e)
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    if let Some(code) = Self::expired_credentials_code(&e) {
+                        warn!(provider = "bedrock", region = %region, code, "credentials rejected");
+                        return Err(BedrockCredentialsExpired { region: region.clone(), code: code.to_string() }.into());
+                    }
+                    return Err(e.into());
+                }
+            };
+
+            let body_bytes = response.body.as_ref();
+            if let Some(dump) = &dump {
+                dump.response(&String::from_utf8_lossy(body_bytes));
+            }
+
+            let image_bytes = Self::first_artifact(body_bytes, model_id, response.request_id())?;
+            info!(
+                provider = "bedrock",
+                op = "invoke_model",
+                region = %region,
+                model_id,
+                bytes = image_bytes.len(),
+                latency_ms,
+                "done"
+            );
+            return Ok(image_bytes);
+        }
+
+        anyhow::bail!("No Bedrock regions configured")
+    }
+}
+
+fn encoded_dimensions(data: &[u8]) -> Result<(u32, u32)> {
+    Ok(image::io::Reader::new(std::io::Cursor::new(data)).with_guessed_format()?.into_dimensions()?)
+}
+
+// Resize an encoded image to exactly `size` as PNG, passing it through when it already fits.
+// SDXL expects PNG init and mask images; a JPEG is accepted but degrades the result, and
+// a lossy mask blurs the edit boundary, so anything else is transcoded.
+fn fit_to_size(data: &[u8], (width, height): (u32, u32), filter: FilterType) -> Result<Vec<u8>> {
+    let format = image::guess_format(data)?;
+    let image = image::load_from_memory_with_format(data, format)?;
+    let fits = image.dimensions() == (width, height);
+    if fits && format == ImageFormat::Png {
+        return Ok(data.to_vec());
+    }
+    if format != ImageFormat::Png {
+        info!(provider = "bedrock", "transcoding {:?} input image to PNG", format);
+    }
+
+    let image = if fits { image } else { image.resize_exact(width, height, filter) };
+    let mut png = Vec::new();
+    image.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bedrock_client, spawn_mock};
+    use axum::{Json, Router, http::StatusCode, response::IntoResponse, routing::post};
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Bedrock mock that fails with the given error type, counting calls
+    async fn failing_region(error_type: &'static str, status: StatusCode, calls: Arc<AtomicUsize>) -> String {
+        spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    (
+                        status,
+                        [("x-amzn-ErrorType", error_type)],
+                        Json(json!({ "message": "region trouble" })),
+                    ).into_response()
+                }
+            }),
+        )).await
+    }
+
+    async fn healthy_region(calls: Arc<AtomicUsize>) -> String {
+        spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    Json(json!({
+                        "artifacts": [{
+                            "base64": general_purpose::STANDARD.encode(b"sdxl-image"),
+                            "finishReason": "SUCCESS"
+                        }]
+                    }))
+                }
+            }),
+        )).await
+    }
+
+    fn inpaint_body(seed: Option<u32>) -> String {
+        let request = BedrockImageGenerator::inpaint_request(
+            "YmFzZQ==".to_string(),
+            "bWFzaw==".to_string(),
+            "chrome exhaust",
+            Some("blurry"),
+            seed,
+        );
+        serde_json::to_string(&request).unwrap()
+    }
+
+    #[test]
+    fn same_seed_produces_identical_request_bodies() {
+        let first = inpaint_body(Some(42));
+        let second = inpaint_body(Some(42));
+
+        assert_eq!(first, second);
+        assert!(first.contains("\"seed\":42"));
+        assert_ne!(first, inpaint_body(Some(43)));
+    }
+
+    #[test]
+    fn missing_seed_is_not_serialized() {
+        assert!(!inpaint_body(None).contains("seed"));
+    }
+
+    #[test]
+    fn valid_size_is_serialized_and_invalid_rejected() {
+        let default = serde_json::to_string(&BedrockImageGenerator::text_request("bike", None, &SdxlParams::default())).unwrap();
+        assert!(!default.contains("width") && !default.contains("height"));
+
+        let params = SdxlParams::default().with_size(1152, 896).unwrap();
+        let body = serde_json::to_string(&BedrockImageGenerator::text_request("bike", None, &params)).unwrap();
+        assert!(body.contains("\"width\":1152"), "{}", body);
+        assert!(body.contains("\"height\":896"), "{}", body);
+
+        let err = SdxlParams::default().with_size(1000, 1000).unwrap_err().to_string();
+        assert!(err.contains("got '1000x1000'"), "{}", err);
+        assert!(err.contains("1024x1024|1152x896"), "{}", err);
+    }
+
+    #[test]
+    fn image_strength_defaults_and_rejects_out_of_range() {
+        let body = |params: &SdxlParams| {
+            serde_json::to_string(&BedrockImageGenerator::image_request("YmFzZQ==".to_string(), "bike", params)).unwrap()
+        };
+        assert!(body(&SdxlParams::default()).contains("\"image_strength\":0.35"));
+
+        let params = SdxlParams::default().with_image_strength(0.8).unwrap();
+        assert!(body(&params).contains("\"image_strength\":0.8"));
+        assert!(SdxlParams::default().with_image_strength(0.0).is_ok());
+        assert!(SdxlParams::default().with_image_strength(1.0).is_ok());
+
+        for bad in [-0.1, 1.5, f32::NAN] {
+            assert!(SdxlParams::default().with_image_strength(bad).is_err(), "{}", bad);
+        }
+        let err = SdxlParams::default().with_image_strength(1.5).unwrap_err().to_string();
+        assert_eq!(err, "image_strength must be between 0 and 1, got 1.5");
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_next_region_when_throttled() {
+        let first_calls = Arc::new(AtomicUsize::new(0));
+        let second_calls = Arc::new(AtomicUsize::new(0));
+        let first = failing_region("ThrottlingException", StatusCode::TOO_MANY_REQUESTS, first_calls.clone()).await;
+        let second = healthy_region(second_calls.clone()).await;
+
+        let generator = BedrockImageGenerator::from_regional_clients(vec![
+            ("us-west-2".to_string(), bedrock_client(&first)),
+            ("us-east-1".to_string(), bedrock_client(&second)),
+        ]);
+
+        let image = generator.generate_from_text("a motorcycle", None).await.unwrap();
+
+        assert_eq!(image, b"sdxl-image");
+        assert_eq!(first_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(second_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn empty_artifacts_name_model_and_request() {
+        let err = BedrockImageGenerator::first_artifact(
+            br#"{"result": "success", "artifacts": []}"#,
+            "stability.stable-diffusion-xl-v1",
+            Some("req-123"),
+        ).unwrap_err().to_string();
+
+        assert_eq!(
+            err,
+            "Bedrock returned no artifacts (model stability.stable-diffusion-xl-v1, request id req-123)"
+        );
+    }
+
+    #[test]
+    fn filtered_artifact_is_not_returned_as_an_image() {
+        let body = json!({
+            "artifacts": [{ "base64": "AA==", "finishReason": "CONTENT_FILTERED" }]
+        }).to_string();
+
+        let err = BedrockImageGenerator::first_artifact(body.as_bytes(), "sdxl", None)
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("finishReason CONTENT_FILTERED"), "{}", err);
+        assert!(err.contains("request id unknown"));
+    }
+
+    #[tokio::test]
+    async fn empty_artifacts_error_carries_sdk_request_id() {
+        let mock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(|| async {
+                ([("x-amzn-RequestId", "req-from-sdk")], Json(json!({ "artifacts": [] })))
+            }),
+        )).await;
+        let generator = BedrockImageGenerator::from_client(bedrock_client(&mock));
+
+        let err = generator.generate_from_text("a motorcycle", None).await.unwrap_err().to_string();
+
+        assert!(err.starts_with("Bedrock returned no artifacts"), "{}", err);
+        assert!(err.contains("request id req-from-sdk"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn inpaint_refuses_a_mask_of_another_size_before_calling_bedrock() {
+        use crate::test_support::png_fixture;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let generator = BedrockImageGenerator::from_client(bedrock_client(&healthy_region(calls.clone()).await));
+
+        let err = generator
+            .inpaint_bytes(&png_fixture(64, 48), &png_fixture(32, 32), "chrome exhaust", None, None)
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(err, "Mask is 32x32 but the base image is 64x48; the mask must be made from the same image");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn inpaint_sends_jpeg_inputs_as_png() {
+        let received = Arc::new(Mutex::new(None::<serde_json::Value>));
+        let captured = received.clone();
+        let mock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(move |body: axum::body::Bytes| {
+                *captured.lock().unwrap() = Some(serde_json::from_slice(&body).unwrap());
+                async {
+                    Json(json!({
+                        "artifacts": [{ "base64": general_purpose::STANDARD.encode(b"sdxl-image"), "finishReason": "SUCCESS" }]
+                    }))
+                }
+            }),
+        )).await;
+        let generator = BedrockImageGenerator::from_client(bedrock_client(&mock));
+
+        // Already an SDXL size, so only the format needs fixing
+        let jpeg = |luma: u8| {
+            let mut data = Vec::new();
+            image::DynamicImage::ImageLuma8(image::GrayImage::from_pixel(1024, 1024, image::Luma([luma])))
+                .write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Jpeg)
+                .unwrap();
+            data
+        };
+        generator.inpaint_bytes(&jpeg(90), &jpeg(255), "chrome exhaust", None, None).await.unwrap();
+
+        let body = received.lock().unwrap().take().expect("Bedrock was called");
+        for field in ["init_image", "mask_image"] {
+            let sent = general_purpose::STANDARD.decode(body[field].as_str().unwrap()).unwrap();
+            assert_eq!(image::guess_format(&sent).unwrap(), ImageFormat::Png, "{}", field);
+            assert_eq!(image::load_from_memory(&sent).unwrap().dimensions(), (1024, 1024), "{}", field);
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn expired_token_gets_an_actionable_error_without_failing_over() {
+        let first_calls = Arc::new(AtomicUsize::new(0));
+        let second_calls = Arc::new(AtomicUsize::new(0));
+        let first = failing_region("ExpiredTokenException", StatusCode::FORBIDDEN, first_calls.clone()).await;
+        let second = healthy_region(second_calls.clone()).await;
+
+        let generator = BedrockImageGenerator::from_regional_clients(vec![
+            ("us-west-2".to_string(), bedrock_client(&first)),
+            ("us-east-1".to_string(), bedrock_client(&second)),
+        ]);
+
+        let err = generator.generate_from_text("a motorcycle", None).await.unwrap_err();
+
+        let expired = err.downcast_ref::<BedrockCredentialsExpired>().expect("a credentials error");
+        assert_eq!(expired.code, "ExpiredTokenException");
+        assert_eq!(
+            err.to_string(),
+            "AWS credentials expired — refresh your session (ExpiredTokenException in region us-west-2)"
+        );
+        assert_eq!(first_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(second_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn hung_invoke_times_out() {
+        let mock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(std::future::pending::<Json<serde_json::Value>>),
+        )).await;
+        let generator = BedrockImageGenerator::from_client(bedrock_client(&mock))
+            .with_timeout(Duration::from_millis(100));
+
+        let started = Instant::now();
+        let err = generator.generate_from_text("a motorcycle", None).await.unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let timeout = err.downcast_ref::<BedrockTimeout>().expect("a timeout error");
+        assert_eq!(timeout.region, "us-west-2");
+        assert_eq!(err.to_string(), "Bedrock did not respond within 0.1s (region us-west-2)");
+        assert!(is_bedrock_outage(&err));
+    }
+
+    #[tokio::test]
+    async fn only_server_side_failures_count_as_an_outage() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        for (error_type, status, outage) in [
+            ("ServiceUnavailableException", StatusCode::SERVICE_UNAVAILABLE, true),
+            ("InternalServerException", StatusCode::INTERNAL_SERVER_ERROR, true),
+            ("ValidationException", StatusCode::BAD_REQUEST, false),
+            ("AccessDeniedException", StatusCode::FORBIDDEN, false),
+        ] {
+            let mock = failing_region(error_type, status, calls.clone()).await;
+            let generator = BedrockImageGenerator::from_client(bedrock_client(&mock));
+
+            let err = generator.generate_from_text("a motorcycle", None).await.unwrap_err();
+            assert_eq!(is_bedrock_outage(&err), outage, "{}", error_type);
+        }
+        assert!(!is_bedrock_outage(&anyhow::anyhow!("Bedrock returned no artifacts")));
+    }
+
+    #[tokio::test]
+    async fn hung_model_access_check_times_out() {
+        let mock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(std::future::pending::<Json<serde_json::Value>>),
+        )).await;
+        let generator = BedrockImageGenerator::from_client(bedrock_client(&mock))
+            .with_timeout(Duration::from_millis(100));
+
+        let started = Instant::now();
+        let err = generator.model_access().await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(err.ends_with("(us-west-2: no response within 0.1s)"), "{}", err);
+        assert!(generator.warm_up().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_on_validation_errors() {
+        let second_calls = Arc::new(AtomicUsize::new(0));
+        let first = failing_region("ValidationException", StatusCode::BAD_REQUEST, Arc::new(AtomicUsize::new(0))).await;
+        let second = healthy_region(second_calls.clone()).await;
+
+        let generator = BedrockImageGenerator::from_regional_clients(vec![
+            ("us-west-2".to_string(), bedrock_client(&first)),
+            ("us-east-1".to_string(), bedrock_client(&second)),
+        ]);
+
+        assert!(generator.generate_from_text("a motorcycle", None).await.is_err());
+        assert_eq!(second_calls.load(Ordering::SeqCst), 0);
+    }
+}
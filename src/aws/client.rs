@@ -1,4 +1,7 @@
 // src/aws.rs
+// Legacy helpers, not wired into the HTTP server
+#![allow(dead_code)]
+
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::Client as S3Client;
 use aws_sdk_sts::Client as StsClient;
@@ -6,6 +9,7 @@ use aws_sdk_bedrockruntime::Client as BedrockClient;
 use aws_sdk_bedrockruntime::primitives::Blob;
 use tracing::info;
 use serde_json::json;
+use base64::{Engine as _, engine::general_purpose};
 
 /*
 AWS Legacy code
@@ -256,7 +260,7 @@ impl AwsClients {
                     .ok_or("No image in response")?;
 
                 // Base64 디코딩
-                let image_bytes = base64::decode(base64_image)
+                let image_bytes = general_purpose::STANDARD.decode(base64_image)
                     .map_err(|e| format!("Failed to decode base64: {}", e))?;
 
                 info!("Image generated successfully, size: {} bytes", image_bytes.len());
@@ -310,7 +314,7 @@ impl AwsClients {
                     .as_str()
                     .ok_or("No image in response")?;
 
-                let image_bytes = base64::decode(base64_image)
+                let image_bytes = general_purpose::STANDARD.decode(base64_image)
                     .map_err(|e| format!("Failed to decode base64: {}", e))?;
 
                 info!("Image generated successfully, size: {} bytes", image_bytes.len());
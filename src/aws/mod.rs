@@ -1,2 +1,4 @@
 pub mod bedrock;
-pub mod client;
\ No newline at end of file
+pub mod client;
+pub mod model_cache;
+pub mod s3_input;
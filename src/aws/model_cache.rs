@@ -0,0 +1,70 @@
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::primitives::ByteStream;
+use axum::body::Body;
+use bytes::Bytes;
+use tracing::info;
+
+use crate::aws::client::AwsClients;
+
+// Fetched Meshy models kept in S3 so repeat downloads skip the CDN
+pub struct ModelCache {
+    s3: S3Client,
+    bucket: String,
+}
+
+impl ModelCache {
+    // Connect to the bucket with the default AWS credentials chain
+    pub async fn connect(bucket: String) -> Self {
+        let s3 = AwsClients::new().await.s3;
+
+        info!("Model cache enabled, bucket: {}", bucket);
+        Self::new(s3, bucket)
+    }
+
+    pub fn new(s3: S3Client, bucket: impl Into<String>) -> Self {
+        Self {
+            s3,
+            bucket: bucket.into(),
+        }
+    }
+
+    fn key(task_id: &str, format: &str) -> String {
+        format!("models/{}.{}", task_id, format)
+    }
+
+    // Cached model as a streaming body, or None on a cache miss
+    pub async fn get(&self, task_id: &str, format: &str) -> Result<Option<Body>, String> {
+        let result = self.s3
+            .get_object()
+            .bucket(&self.bucket)
+            .key(Self::key(task_id, format))
+            .send()
+            .await;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(format!("S3 get failed: {}", aws_sdk_s3::error::DisplayErrorContext(&e))),
+        };
+
+        let stream = futures::stream::unfold(output.body, |mut body| async move {
+            body.try_next().await.transpose().map(|chunk| (chunk, body))
+        });
+
+        Ok(Some(Body::from_stream(stream)))
+    }
+
+    pub async fn put(&self, task_id: &str, format: &str, data: Bytes) -> Result<(), String> {
+        self.s3
+            .put_object()
+            .bucket(&self.bucket)
+            .key(Self::key(task_id, format))
+            .content_type("model/gltf-binary")
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .map_err(|e| format!("S3 put failed: {}", aws_sdk_s3::error::DisplayErrorContext(&e)))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use std::fmt;
+use tracing::info;
+
+use crate::aws::client::AwsClients;
+
+// Why an `s3://` input couldn't be used
+#[derive(Debug, PartialEq)]
+pub enum S3InputError {
+    // Not of the form s3://bucket/key
+    InvalidUri(String),
+    // The bucket isn't one of S3_INPUT_BUCKETS
+    BucketNotAllowed(String),
+    NotFound(String),
+    TooLarge { uri: String, size: u64, limit: usize },
+    Fetch(String),
+}
+
+impl fmt::Display for S3InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUri(uri) => write!(f, "Invalid S3 URI '{}': expected s3://bucket/key", uri),
+            Self::BucketNotAllowed(bucket) => write!(f, "Bucket '{}' is not allowed as an input source", bucket),
+            Self::NotFound(uri) => write!(f, "No S3 object at {}", uri),
+            Self::TooLarge { uri, size, limit } => {
+                write!(f, "S3 object {} is {} bytes, over the {} byte upload limit", uri, size, limit)
+            }
+            Self::Fetch(message) => write!(f, "Failed to fetch S3 input: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for S3InputError {}
+
+// Input images referenced as `s3://bucket/key` instead of uploaded.
+// Only allow-listed buckets are read, so callers can't use the server's credentials
+// to pull arbitrary objects through the providers.
+pub struct S3Inputs {
+    s3: S3Client,
+    allowed_buckets: Vec<String>,
+}
+
+impl S3Inputs {
+    // Connect with the default AWS credentials chain
+    pub async fn connect(allowed_buckets: Vec<String>) -> Self {
+        let s3 = AwsClients::new().await.s3;
+
+        info!("S3 inputs enabled for buckets: {}", allowed_buckets.join(", "));
+        Self::new(s3, allowed_buckets)
+    }
+
+    pub fn new(s3: S3Client, allowed_buckets: Vec<String>) -> Self {
+        Self { s3, allowed_buckets }
+    }
+
+    // Split `s3://bucket/key` into its bucket and key
+    pub fn parse_uri(uri: &str) -> Result<(&str, &str), S3InputError> {
+        let invalid = || S3InputError::InvalidUri(uri.to_string());
+        let (bucket, key) = uri.strip_prefix("s3://").and_then(|rest| rest.split_once('/')).ok_or_else(invalid)?;
+        if bucket.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+        Ok((bucket, key))
+    }
+
+    // The object's bytes, refusing anything larger than `max_bytes` before downloading it
+    pub async fn fetch(&self, uri: &str, max_bytes: usize) -> Result<Bytes, S3InputError> {
+        let (bucket, key) = Self::parse_uri(uri)?;
+        if !self.allowed_buckets.iter().any(|allowed| allowed == bucket) {
+            return Err(S3InputError::BucketNotAllowed(bucket.to_string()));
+        }
+
+        let output = match self.s3.get_object().bucket(bucket).key(key).send().await {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_no_such_key()) => {
+                return Err(S3InputError::NotFound(uri.to_string()));
+            }
+            Err(e) => return Err(S3InputError::Fetch(aws_sdk_s3::error::DisplayErrorContext(&e).to_string())),
+        };
+
+        let too_large = |size: u64| S3InputError::TooLarge { uri: uri.to_string(), size, limit: max_bytes };
+        if let Some(size) = output.content_length().filter(|&size| size > max_bytes as i64) {
+            return Err(too_large(size as u64));
+        }
+        let data = output.body.collect().await
+            .map_err(|e| S3InputError::Fetch(e.to_string()))?
+            .into_bytes();
+        if data.len() > max_bytes {
+            return Err(too_large(data.len() as u64));
+        }
+
+        info!("Fetched S3 input {}: {} bytes", uri, data.len());
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bucket_and_key() {
+        assert_eq!(S3Inputs::parse_uri("s3://inputs/bikes/cafe.png"), Ok(("inputs", "bikes/cafe.png")));
+
+        for bad in ["inputs/bikes/cafe.png", "s3://inputs", "s3://inputs/", "s3:///cafe.png", "https://inputs/cafe.png"] {
+            assert_eq!(S3Inputs::parse_uri(bad), Err(S3InputError::InvalidUri(bad.to_string())), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,519 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::aws::bedrock::SdxlParams;
+use crate::util::encode::OutputFormat;
+use crate::util::image_mask::InvalidOptionError;
+use crate::util::post_process::Corner;
+
+// Provider used for the Gemini-style generation endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageProvider {
+    Gemini,
+}
+
+impl ImageProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageProvider::Gemini => "gemini",
+        }
+    }
+}
+
+impl FromStr for ImageProvider {
+    type Err = InvalidOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "gemini" => Ok(ImageProvider::Gemini),
+            _ => Err(InvalidOptionError {
+                value: s.to_string(),
+                expected: vec!["gemini"],
+            }),
+        }
+    }
+}
+
+// What the part-based /customize endpoint generates with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CustomizeBackend {
+    // Mask, then SDXL inpainting
+    Bedrock,
+    // Gemini edits the whole photo and only the part's mask is kept from it
+    Gemini,
+}
+
+impl CustomizeBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CustomizeBackend::Bedrock => "bedrock",
+            CustomizeBackend::Gemini => "gemini",
+        }
+    }
+}
+
+impl FromStr for CustomizeBackend {
+    type Err = InvalidOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "bedrock" => Ok(CustomizeBackend::Bedrock),
+            "gemini" => Ok(CustomizeBackend::Gemini),
+            _ => Err(InvalidOptionError {
+                value: s.to_string(),
+                expected: vec!["bedrock", "gemini"],
+            }),
+        }
+    }
+}
+
+// Every problem found while loading the config, reported together at startup
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Server configuration, loaded once from the environment at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind_addr: SocketAddr,
+    pub upload_dir: PathBuf,
+    // Scratch space for staged uploads, created on first use
+    pub temp_dir: PathBuf,
+    pub max_upload_bytes: usize,
+    pub poll_interval: Duration,
+    // WebSocket keepalive; a ping left unanswered until the next one closes the socket
+    pub ws_ping_interval: Duration,
+    // A client that can't take a message within this long is treated as gone
+    pub ws_send_timeout: Duration,
+    pub image_provider: ImageProvider,
+    pub customize_backend: CustomizeBackend,
+    // Budget for a single Bedrock call or model download
+    pub upstream_timeout: Duration,
+    // Per-provider budgets: generation legitimately takes a minute, a Meshy status check
+    // shouldn't. Each falls back to UPSTREAM_TIMEOUT_SECS when that is set.
+    pub gemini_timeout: Duration,
+    pub meshy_timeout: Duration,
+    pub gemini_api_key: String,
+    pub meshy_api_key: String,
+    pub bedrock_regions: Vec<String>,
+    pub model_cache_bucket: Option<String>,
+    // Buckets callers may name as `s3://` inputs; empty turns S3 inputs off
+    pub s3_input_buckets: Vec<String>,
+    pub idempotency_ttl: Duration,
+    // How long a finished /generate/async result stays fetchable
+    pub job_result_ttl: Duration,
+    // How often expired idempotency keys and job results are swept out of memory
+    pub registry_cleanup_interval: Duration,
+    pub debug_dump_dir: Option<PathBuf>,
+    pub bedrock_fallback: bool,
+    pub model_download_retries: u32,
+    pub gemini_max_response_bytes: usize,
+    // Repeats of a Gemini request whose response came back with empty content
+    pub gemini_empty_parts_retries: u32,
+    // Models callers may request by name; empty allows any
+    pub allowed_models: Vec<String>,
+    // Limits on the image sent for 3D reconstruction, which fails on tiny or extreme inputs
+    pub min_3d_image_side: u32,
+    pub max_3d_aspect_ratio: f32,
+    // Prime every provider's connection pool in the background once the server is up
+    pub warmup: bool,
+    // Consecutive provider failures that open its circuit, and how long it stays open
+    pub breaker_failure_threshold: u32,
+    pub breaker_cooldown: Duration,
+    // Optional parts.toml with site-specific parts for the customize endpoints
+    pub parts_manifest: Option<PathBuf>,
+    // Post-processing of every generated image; each step is off unless set
+    pub post_max_dimension: Option<u32>,
+    pub post_watermark: Option<PathBuf>,
+    pub post_watermark_corner: Corner,
+    pub post_format: Option<OutputFormat>,
+    // SDXL settings for Bedrock; BEDROCK_OUTPUT_SIZE (e.g. 1152x896) fixes the text-to-image size
+    // and IMAGE_STRENGTH (0..=1) how much of an image-to-image input survives
+    pub sdxl_params: SdxlParams,
+    // Hook that classifies every upload before it reaches a provider; unset turns moderation off
+    pub moderation_url: Option<String>,
+    pub moderation_timeout: Duration,
+    // Let uploads through when the hook can't be reached, instead of refusing them
+    pub moderation_fail_open: bool,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    // Build from any key lookup so parsing can be tested without touching the process env
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut problems = Vec::new();
+        let get = |key: &str| lookup(key).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+
+        // Secrets can also come from a file named by KEY_FILE (mounted secrets); KEY wins if both are set
+        let mut required = |key: &str| {
+            if let Some(value) = get(key) {
+                return value;
+            }
+            let file_key = format!("{}_FILE", key);
+            let Some(path) = get(&file_key) else {
+                problems.push(format!("{} is required (or set {})", key, file_key));
+                return String::new();
+            };
+            match std::fs::read_to_string(&path) {
+                Ok(contents) if !contents.trim().is_empty() => contents.trim().to_string(),
+                Ok(_) => {
+                    problems.push(format!("{} points at an empty file: {}", file_key, path));
+                    String::new()
+                }
+                Err(e) => {
+                    problems.push(format!("{} could not be read from {}: {}", file_key, path, e));
+                    String::new()
+                }
+            }
+        };
+        let gemini_api_key = required("GEMINI_API_KEY");
+        let meshy_api_key = required("MESHY_API_KEY");
+
+        let parsed = |key: &str, default: &str| -> String {
+            get(key).unwrap_or_else(|| default.to_string())
+        };
+        let bind_addr_raw = parsed("BIND_ADDR", "127.0.0.1:8080");
+        let max_upload_raw = parsed("MAX_UPLOAD_BYTES", "26214400");
+        let poll_raw = parsed("POLL_INTERVAL_SECS", "5");
+        let ws_ping_raw = parsed("WS_PING_INTERVAL_SECS", "30");
+        let ws_send_raw = parsed("WS_SEND_TIMEOUT_SECS", "10");
+        let provider_raw = parsed("IMAGE_PROVIDER", "gemini");
+        let customize_backend_raw = parsed("CUSTOMIZE_BACKEND", "bedrock");
+        let timeout_raw = parsed("UPSTREAM_TIMEOUT_SECS", "120");
+        let provider_timeout = |key: &str, default: &str| {
+            get(key).or_else(|| get("UPSTREAM_TIMEOUT_SECS")).unwrap_or_else(|| default.to_string())
+        };
+        let gemini_timeout_raw = provider_timeout("GEMINI_TIMEOUT_SECS", "120");
+        let meshy_timeout_raw = provider_timeout("MESHY_TIMEOUT_SECS", "30");
+        let moderation_timeout_raw = provider_timeout("MODERATION_TIMEOUT_SECS", "10");
+        let idempotency_raw = parsed("IDEMPOTENCY_TTL_SECS", "86400");
+        let job_result_ttl_raw = parsed("JOB_RESULT_TTL_SECS", "3600");
+        let cleanup_interval_raw = parsed("REGISTRY_CLEANUP_INTERVAL_SECS", "60");
+        let fallback_raw = parsed("BEDROCK_FALLBACK", "false");
+        let download_retries_raw = parsed("MODEL_DOWNLOAD_RETRIES", "3");
+        let empty_parts_retries_raw = parsed("GEMINI_EMPTY_PARTS_RETRIES", "2");
+        let gemini_response_raw = parsed("GEMINI_MAX_RESPONSE_BYTES", "67108864");
+        let min_3d_side_raw = parsed("MIN_3D_IMAGE_SIDE", "64");
+        let max_3d_aspect_raw = parsed("MAX_3D_ASPECT_RATIO", "4");
+        let warmup_raw = parsed("WARMUP", "false");
+        let breaker_threshold_raw = parsed("BREAKER_FAILURE_THRESHOLD", "5");
+        let breaker_cooldown_raw = parsed("BREAKER_COOLDOWN_SECS", "30");
+        let watermark_corner_raw = parsed("POST_WATERMARK_CORNER", "bottom-right");
+        let moderation_fail_open_raw = parsed("MODERATION_FAIL_OPEN", "false");
+
+        let bind_addr = bind_addr_raw.parse::<SocketAddr>()
+            .map_err(|_| problems.push(format!("BIND_ADDR must be host:port, got '{}'", bind_addr_raw)))
+            .unwrap_or_else(|_| SocketAddr::from(([127, 0, 0, 1], 8080)));
+        let max_upload_bytes = positive(&max_upload_raw, "MAX_UPLOAD_BYTES", &mut problems) as usize;
+        let poll_interval = Duration::from_secs(positive(&poll_raw, "POLL_INTERVAL_SECS", &mut problems));
+        let ws_ping_interval = Duration::from_secs(positive(&ws_ping_raw, "WS_PING_INTERVAL_SECS", &mut problems));
+        let ws_send_timeout = Duration::from_secs(positive(&ws_send_raw, "WS_SEND_TIMEOUT_SECS", &mut problems));
+        let upstream_timeout = Duration::from_secs(positive(&timeout_raw, "UPSTREAM_TIMEOUT_SECS", &mut problems));
+        let gemini_timeout = Duration::from_secs(positive(&gemini_timeout_raw, "GEMINI_TIMEOUT_SECS", &mut problems));
+        let meshy_timeout = Duration::from_secs(positive(&meshy_timeout_raw, "MESHY_TIMEOUT_SECS", &mut problems));
+        let moderation_timeout = Duration::from_secs(
+            positive(&moderation_timeout_raw, "MODERATION_TIMEOUT_SECS", &mut problems),
+        );
+        let idempotency_ttl = Duration::from_secs(positive(&idempotency_raw, "IDEMPOTENCY_TTL_SECS", &mut problems));
+        let job_result_ttl = Duration::from_secs(positive(&job_result_ttl_raw, "JOB_RESULT_TTL_SECS", &mut problems));
+        let registry_cleanup_interval = Duration::from_secs(
+            positive(&cleanup_interval_raw, "REGISTRY_CLEANUP_INTERVAL_SECS", &mut problems),
+        );
+        let image_provider = provider_raw.parse::<ImageProvider>()
+            .map_err(|e| problems.push(format!("IMAGE_PROVIDER: {}", e)))
+            .unwrap_or(ImageProvider::Gemini);
+        let customize_backend = customize_backend_raw.parse::<CustomizeBackend>()
+            .map_err(|e| problems.push(format!("CUSTOMIZE_BACKEND: {}", e)))
+            .unwrap_or(CustomizeBackend::Bedrock);
+        let gemini_max_response_bytes = positive(&gemini_response_raw, "GEMINI_MAX_RESPONSE_BYTES", &mut problems) as usize;
+        let min_3d_image_side = positive(&min_3d_side_raw, "MIN_3D_IMAGE_SIDE", &mut problems) as u32;
+        let max_3d_aspect_ratio = max_3d_aspect_raw.parse::<f32>().ok()
+            .filter(|ratio| ratio.is_finite() && *ratio >= 1.0)
+            .unwrap_or_else(|| {
+                problems.push(format!("MAX_3D_ASPECT_RATIO must be a number >= 1, got '{}'", max_3d_aspect_raw));
+                1.0
+            });
+        let bedrock_fallback = flag(&fallback_raw, "BEDROCK_FALLBACK", &mut problems);
+        let warmup = flag(&warmup_raw, "WARMUP", &mut problems);
+        let moderation_fail_open = flag(&moderation_fail_open_raw, "MODERATION_FAIL_OPEN", &mut problems);
+        let breaker_failure_threshold = positive(&breaker_threshold_raw, "BREAKER_FAILURE_THRESHOLD", &mut problems) as u32;
+        let breaker_cooldown = Duration::from_secs(positive(&breaker_cooldown_raw, "BREAKER_COOLDOWN_SECS", &mut problems));
+        let model_download_retries = download_retries_raw.parse::<u32>()
+            .map_err(|_| problems.push(format!(
+                "MODEL_DOWNLOAD_RETRIES must be a non-negative integer, got '{}'",
+                download_retries_raw
+            )))
+            .unwrap_or(0);
+        let gemini_empty_parts_retries = empty_parts_retries_raw.parse::<u32>()
+            .map_err(|_| problems.push(format!(
+                "GEMINI_EMPTY_PARTS_RETRIES must be a non-negative integer, got '{}'",
+                empty_parts_retries_raw
+            )))
+            .unwrap_or(0);
+        let post_max_dimension = get("POST_MAX_DIMENSION")
+            .map(|raw| positive(&raw, "POST_MAX_DIMENSION", &mut problems) as u32);
+        let post_watermark_corner = watermark_corner_raw.parse::<Corner>()
+            .map_err(|e| problems.push(format!("POST_WATERMARK_CORNER: {}", e)))
+            .unwrap_or(Corner::BottomRight);
+        let post_format = get("POST_FORMAT").and_then(|raw| {
+            raw.parse::<OutputFormat>()
+                .map_err(|e| problems.push(format!("POST_FORMAT: {}", e)))
+                .ok()
+        });
+        let mut sdxl_params = match get("BEDROCK_OUTPUT_SIZE") {
+            None => SdxlParams::default(),
+            Some(raw) => raw.split_once(['x', 'X'])
+                .and_then(|(width, height)| Some((width.trim().parse().ok()?, height.trim().parse().ok()?)))
+                .ok_or_else(|| format!("BEDROCK_OUTPUT_SIZE must be WIDTHxHEIGHT, got '{}'", raw))
+                .and_then(|(width, height)| {
+                    SdxlParams::default().with_size(width, height)
+                        .map_err(|e| format!("BEDROCK_OUTPUT_SIZE: {}", e))
+                })
+                .unwrap_or_else(|problem| {
+                    problems.push(problem);
+                    SdxlParams::default()
+                }),
+        };
+        if let Some(raw) = get("IMAGE_STRENGTH") {
+            match raw.parse::<f32>().map(|strength| sdxl_params.with_image_strength(strength)) {
+                Ok(Ok(params)) => sdxl_params = params,
+                Ok(Err(e)) => problems.push(format!("IMAGE_STRENGTH: {}", e)),
+                Err(_) => problems.push(format!("IMAGE_STRENGTH must be a number between 0 and 1, got '{}'", raw)),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+
+        Ok(Self {
+            bind_addr,
+            upload_dir: PathBuf::from(get("UPLOAD_DIR").unwrap_or_else(|| "./uploads".to_string())),
+            temp_dir: get("TEMP_DIR").map(PathBuf::from).unwrap_or_else(std::env::temp_dir),
+            max_upload_bytes,
+            poll_interval,
+            ws_ping_interval,
+            ws_send_timeout,
+            image_provider,
+            customize_backend,
+            upstream_timeout,
+            gemini_timeout,
+            meshy_timeout,
+            gemini_api_key,
+            meshy_api_key,
+            bedrock_regions: split_list(&get("BEDROCK_REGIONS").unwrap_or_default()),
+            model_cache_bucket: get("MODEL_CACHE_BUCKET"),
+            s3_input_buckets: split_list(&get("S3_INPUT_BUCKETS").unwrap_or_default()),
+            idempotency_ttl,
+            job_result_ttl,
+            registry_cleanup_interval,
+            debug_dump_dir: get("DEBUG_DUMP_DIR").map(PathBuf::from),
+            bedrock_fallback,
+            model_download_retries,
+            gemini_max_response_bytes,
+            gemini_empty_parts_retries,
+            allowed_models: split_list(&get("ALLOWED_MODELS").unwrap_or_default()),
+            min_3d_image_side,
+            max_3d_aspect_ratio,
+            warmup,
+            breaker_failure_threshold,
+            breaker_cooldown,
+            parts_manifest: get("PARTS_MANIFEST").map(PathBuf::from),
+            post_max_dimension,
+            post_watermark: get("POST_WATERMARK").map(PathBuf::from),
+            post_watermark_corner,
+            post_format,
+            sdxl_params,
+            moderation_url: get("MODERATION_URL"),
+            moderation_timeout,
+            moderation_fail_open,
+        })
+    }
+}
+
+// Comma-separated list, ignoring blanks
+pub fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn positive(raw: &str, key: &str, problems: &mut Vec<String>) -> u64 {
+    match raw.parse::<u64>() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            problems.push(format!("{} must be a positive integer, got '{}'", key, raw));
+            1
+        }
+    }
+}
+
+fn flag(raw: &str, key: &str, problems: &mut Vec<String>) -> bool {
+    match raw.to_ascii_lowercase().as_str() {
+        "true" | "1" => true,
+        "false" | "0" => false,
+        _ => {
+            problems.push(format!("{} must be true or false, got '{}'", key, raw));
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::from_lookup(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn parses_values_and_applies_defaults() {
+        let config = load(&[
+            ("GEMINI_API_KEY", "g-key"),
+            ("MESHY_API_KEY", "m-key"),
+            ("BIND_ADDR", "0.0.0.0:9000"),
+            ("POLL_INTERVAL_SECS", "2"),
+            ("BEDROCK_REGIONS", "us-east-1, us-west-2,"),
+        ]).unwrap();
+
+        assert_eq!(config.bind_addr, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.poll_interval, Duration::from_secs(2));
+        assert_eq!(config.ws_send_timeout, Duration::from_secs(10));
+        assert_eq!(config.bedrock_regions, vec!["us-east-1", "us-west-2"]);
+        assert_eq!(config.upload_dir, PathBuf::from("./uploads"));
+        assert_eq!(config.temp_dir, std::env::temp_dir());
+        assert_eq!(config.max_upload_bytes, 25 * 1024 * 1024);
+        assert_eq!(config.image_provider, ImageProvider::Gemini);
+        assert_eq!(config.customize_backend, CustomizeBackend::Bedrock);
+        assert_eq!(config.model_cache_bucket, None);
+        assert!(config.s3_input_buckets.is_empty());
+        assert_eq!(config.debug_dump_dir, None);
+        assert!(!config.bedrock_fallback);
+        assert_eq!(config.model_download_retries, 3);
+        assert_eq!(config.gemini_max_response_bytes, 64 * 1024 * 1024);
+        assert_eq!(config.gemini_empty_parts_retries, 2);
+        assert!(config.allowed_models.is_empty());
+        assert_eq!(config.min_3d_image_side, 64);
+        assert_eq!(config.max_3d_aspect_ratio, 4.0);
+        assert!(!config.warmup);
+        assert_eq!(config.breaker_failure_threshold, 5);
+        assert_eq!(config.breaker_cooldown, Duration::from_secs(30));
+        assert_eq!(config.upstream_timeout, Duration::from_secs(120));
+        assert_eq!(config.gemini_timeout, Duration::from_secs(120));
+        assert_eq!(config.meshy_timeout, Duration::from_secs(30));
+        assert_eq!(config.post_max_dimension, None);
+        assert_eq!(config.post_watermark, None);
+        assert_eq!(config.post_watermark_corner, Corner::BottomRight);
+        assert_eq!(config.post_format, None);
+        assert_eq!(config.sdxl_params, SdxlParams::default());
+        assert_eq!(config.moderation_url, None);
+        assert_eq!(config.moderation_timeout, Duration::from_secs(10));
+        assert!(!config.moderation_fail_open);
+    }
+
+    #[test]
+    fn provider_timeouts_fall_back_to_the_upstream_timeout() {
+        let keys = [("GEMINI_API_KEY", "g-key"), ("MESHY_API_KEY", "m-key")];
+
+        let config = load(&[keys[0], keys[1], ("GEMINI_TIMEOUT_SECS", "90"), ("MESHY_TIMEOUT_SECS", "10")]).unwrap();
+        assert_eq!(config.gemini_timeout, Duration::from_secs(90));
+        assert_eq!(config.meshy_timeout, Duration::from_secs(10));
+
+        let config = load(&[keys[0], keys[1], ("UPSTREAM_TIMEOUT_SECS", "60"), ("MESHY_TIMEOUT_SECS", "5")]).unwrap();
+        assert_eq!(config.gemini_timeout, Duration::from_secs(60));
+        assert_eq!(config.meshy_timeout, Duration::from_secs(5));
+
+        let err = load(&[keys[0], keys[1], ("MESHY_TIMEOUT_SECS", "0")]).unwrap_err();
+        assert!(err.to_string().contains("MESHY_TIMEOUT_SECS must be a positive integer, got '0'"), "{}", err);
+    }
+
+    #[test]
+    fn parses_the_image_strength() {
+        let keys = [("GEMINI_API_KEY", "g-key"), ("MESHY_API_KEY", "m-key")];
+
+        let config = load(&[keys[0], keys[1], ("IMAGE_STRENGTH", "0.6")]).unwrap();
+        assert_eq!(config.sdxl_params, SdxlParams::default().with_image_strength(0.6).unwrap());
+
+        let err = load(&[keys[0], keys[1], ("IMAGE_STRENGTH", "1.5")]).unwrap_err();
+        assert!(err.to_string().contains("IMAGE_STRENGTH: image_strength must be between 0 and 1, got 1.5"), "{}", err);
+        let err = load(&[keys[0], keys[1], ("IMAGE_STRENGTH", "strong")]).unwrap_err();
+        assert!(err.to_string().contains("IMAGE_STRENGTH must be a number between 0 and 1"), "{}", err);
+    }
+
+    #[test]
+    fn parses_the_bedrock_output_size() {
+        let keys = [("GEMINI_API_KEY", "g-key"), ("MESHY_API_KEY", "m-key")];
+
+        let config = load(&[keys[0], keys[1], ("BEDROCK_OUTPUT_SIZE", "1152x896")]).unwrap();
+        assert_eq!(config.sdxl_params, SdxlParams::default().with_size(1152, 896).unwrap());
+
+        let err = load(&[keys[0], keys[1], ("BEDROCK_OUTPUT_SIZE", "1000x1000")]).unwrap_err();
+        assert!(err.to_string().contains("BEDROCK_OUTPUT_SIZE: expected one of"), "{}", err);
+        let err = load(&[keys[0], keys[1], ("BEDROCK_OUTPUT_SIZE", "large")]).unwrap_err();
+        assert!(err.to_string().contains("BEDROCK_OUTPUT_SIZE must be WIDTHxHEIGHT, got 'large'"), "{}", err);
+    }
+
+    #[test]
+    fn reads_keys_from_files_unless_set_directly() {
+        let key_file = crate::util::temp::unique_temp_path("gemini_key", "txt");
+        std::fs::write(&key_file, "  file-key\n").unwrap();
+        let key_path = key_file.to_string_lossy().to_string();
+
+        let config = load(&[("GEMINI_API_KEY_FILE", &key_path), ("MESHY_API_KEY", "m-key")]).unwrap();
+        assert_eq!(config.gemini_api_key, "file-key");
+
+        let config = load(&[
+            ("GEMINI_API_KEY", "env-key"),
+            ("GEMINI_API_KEY_FILE", &key_path),
+            ("MESHY_API_KEY", "m-key"),
+        ]).unwrap();
+        assert_eq!(config.gemini_api_key, "env-key");
+        let _ = std::fs::remove_file(&key_file);
+
+        let err = load(&[("GEMINI_API_KEY", "g-key"), ("MESHY_API_KEY_FILE", "/nonexistent/meshy-key")]).unwrap_err();
+        assert!(err.to_string().contains("MESHY_API_KEY_FILE could not be read from /nonexistent/meshy-key"), "{}", err);
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let err = load(&[
+            ("MESHY_API_KEY", "m-key"),
+            ("BIND_ADDR", "not-an-addr"),
+            ("POLL_INTERVAL_SECS", "0"),
+            ("IMAGE_PROVIDER", "dalle"),
+            ("BEDROCK_FALLBACK", "sometimes"),
+        ]).unwrap_err();
+
+        assert_eq!(err.problems.len(), 5, "{}", err);
+        let message = err.to_string();
+        assert!(message.contains("GEMINI_API_KEY is required"));
+        assert!(message.contains("BIND_ADDR must be host:port"));
+        assert!(message.contains("POLL_INTERVAL_SECS must be a positive integer"));
+        assert!(message.contains("IMAGE_PROVIDER: expected one of gemini, got 'dalle'"));
+        assert!(message.contains("BEDROCK_FALLBACK must be true or false"));
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView, GrayImage, RgbImage, imageops::FilterType};
+use std::sync::Arc;
+
+use crate::aws::bedrock::BedrockImageGenerator;
+use crate::custom::motorcycle::MotorcycleCustomizer;
+use crate::gemini::client::GeminiClient;
+use crate::util::image_mask::{MaskConfig, MaskGenerator, MaskIntensity, PartType};
+use crate::util::mime::ImageBytes;
+use crate::util::prompt::sanitize_description;
+
+// What the user asked for, in their own words
+#[derive(Debug, Clone, Copy)]
+pub struct PartDescriptions<'a> {
+    pub bike: &'a str,
+    pub part: &'a str,
+}
+
+// One way of turning "this bike, this part, described like so" into a new image
+pub trait CustomizationBackend {
+    async fn customize(
+        &self,
+        base: &[u8],
+        part: PartType,
+        descriptions: PartDescriptions<'_>,
+        intensity: MaskIntensity,
+    ) -> Result<Vec<u8>>;
+}
+
+// Part mask sized to the base image
+fn base_mask(base: &DynamicImage, part: PartType, intensity: MaskIntensity) -> Result<GrayImage> {
+    let (width, height) = base.dimensions();
+    MaskGenerator::create_part_mask(width, height, part, intensity, &MaskConfig::default())
+}
+
+// SDXL: generate the part mask, then inpaint inside it
+impl CustomizationBackend for BedrockImageGenerator {
+    async fn customize(
+        &self,
+        base: &[u8],
+        part: PartType,
+        descriptions: PartDescriptions<'_>,
+        intensity: MaskIntensity,
+    ) -> Result<Vec<u8>> {
+        let mask = base_mask(&image::load_from_memory(base)?, part, intensity)?;
+        let mask_png = MotorcycleCustomizer::encode_mask(&mask)?;
+        let (prompt, negative_prompt) = MotorcycleCustomizer::build_prompt(
+            descriptions.bike,
+            MotorcycleCustomizer::part_name(part),
+            descriptions.part,
+        );
+
+        self.inpaint_bytes(base, &mask_png, &prompt, Some(&negative_prompt), None).await
+    }
+}
+
+// Gemini: let it edit the whole photo, then keep only the masked part of its output
+// so the rest of the bike stays pixel-identical to the original
+pub struct GeminiBackend {
+    client: Arc<GeminiClient>,
+}
+
+impl GeminiBackend {
+    pub fn new(client: Arc<GeminiClient>) -> Self {
+        Self { client }
+    }
+
+    fn prompt(part: PartType, descriptions: PartDescriptions<'_>) -> String {
+        format!(
+            "Edit this photo of a {} motorcycle: replace the {} with a custom {}. \
+            Change nothing else, keep the framing, lighting and background identical.",
+            sanitize_description(descriptions.bike),
+            MotorcycleCustomizer::part_name(part),
+            sanitize_description(descriptions.part),
+        )
+    }
+}
+
+impl CustomizationBackend for GeminiBackend {
+    async fn customize(
+        &self,
+        base: &[u8],
+        part: PartType,
+        descriptions: PartDescriptions<'_>,
+        intensity: MaskIntensity,
+    ) -> Result<Vec<u8>> {
+        let base_image = image::load_from_memory(base)?;
+        let edited = self.client
+            .gen_image_nanobanana(
+                Self::prompt(part, descriptions),
+                vec![ImageBytes::new(bytes::Bytes::copy_from_slice(base))],
+            )
+            .await?;
+        let edited = image::load_from_memory(&edited)?;
+
+        let mask = base_mask(&base_image, part, intensity)?;
+        let composite = composite(&base_image, &edited, &mask);
+
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(composite)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+        Ok(png)
+    }
+}
+
+// Blend `overlay` over `base` weighted by the mask, resizing the overlay if the model changed its size
+fn composite(base: &DynamicImage, overlay: &DynamicImage, mask: &GrayImage) -> RgbImage {
+    let (width, height) = base.dimensions();
+    let overlay = if overlay.dimensions() == (width, height) {
+        overlay.to_rgb8()
+    } else {
+        overlay.resize_exact(width, height, FilterType::Lanczos3).to_rgb8()
+    };
+
+    let mut out = base.to_rgb8();
+    for ((dst, src), weight) in out.pixels_mut().zip(overlay.pixels()).zip(mask.pixels()) {
+        let alpha = weight[0] as f32 / 255.0;
+        for channel in 0..3 {
+            dst[channel] = (dst[channel] as f32 * (1.0 - alpha) + src[channel] as f32 * alpha).round() as u8;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bedrock_client, png_fixture, spawn_mock};
+    use axum::{Json, Router, routing::post};
+    use base64::{Engine, engine::general_purpose};
+    use serde_json::json;
+
+    fn assert_backend<B: CustomizationBackend>() {}
+
+    async fn customize_with<B: CustomizationBackend>(backend: B, base: &[u8]) -> Vec<u8> {
+        MotorcycleCustomizer::with_generator(backend)
+            .customize(
+                base,
+                PartType::Exhaust,
+                PartDescriptions { bike: "cafe racer", part: "titanium slip-on" },
+                MaskIntensity::Medium,
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn both_backends_satisfy_the_trait() {
+        assert_backend::<BedrockImageGenerator>();
+        assert_backend::<GeminiBackend>();
+        let base = png_fixture(400, 400);
+
+        let bedrock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(|| async {
+                Json(json!({
+                    "artifacts": [{ "base64": general_purpose::STANDARD.encode(b"sdxl-image"), "finishReason": "SUCCESS" }]
+                }))
+            }),
+        )).await;
+        let generator = BedrockImageGenerator::from_client(bedrock_client(&bedrock));
+        assert_eq!(customize_with(generator, &base).await, b"sdxl-image");
+
+        // Gemini repaints the whole frame red; only the exhaust region may change
+        let mut red = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(400, 400, image::Rgb([255, 0, 0])))
+            .write_to(&mut std::io::Cursor::new(&mut red), image::ImageFormat::Png)
+            .unwrap();
+        let red = general_purpose::STANDARD.encode(&red);
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || async move {
+                Json(json!({
+                    "candidates": [{ "content": { "parts": [{ "inlineData": { "data": red } }] } }]
+                }))
+            }),
+        )).await;
+        let backend = GeminiBackend::new(Arc::new(GeminiClient::with_base_url("test-key", gemini)));
+        let result = image::load_from_memory(&customize_with(backend, &base).await).unwrap().to_rgb8();
+
+        assert_eq!(result.get_pixel(0, 0).0, [40, 80, 120]);
+        let center = result.get_pixel(200, 260).0;
+        assert!(center[0] > 200 && center[1] < 40, "exhaust region should come from Gemini: {:?}", center);
+    }
+}
@@ -1 +1,3 @@
-pub mod motorcycle;
\ No newline at end of file
+pub mod backend;
+pub mod motorcycle;
+pub mod parts;
@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use image::GrayImage;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::custom::motorcycle::MotorcycleCustomizer;
+use crate::util::image_mask::{MaskConfig, MaskGenerator, MaskIntensity, MaskShape, PartRegion, PartType};
+
+// Site-specific parts loaded from a `parts.toml` manifest, e.g.
+//
+//   [parts.crash_bars]
+//   name = "crash bars"
+//   region = { x = 0.25, y = 0.45, width = 0.3, height = 0.25 }
+//   shape = "rectangle"
+//   prompt = "tubular steel engine guard"
+#[derive(Debug, Default)]
+pub struct PartsManifest {
+    // Keyed by lowercase part key
+    parts: BTreeMap<String, CustomPart>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomPart {
+    pub key: String,
+    // What the prompt calls the part
+    pub name: String,
+    pub region: PartRegion,
+    pub shape: MaskShape,
+    // Added ahead of the caller's own part description
+    pub prompt: String,
+}
+
+// A part the customize endpoints can paint: built in, or from the manifest
+#[derive(Debug, Clone)]
+pub enum Part {
+    Builtin(PartType),
+    Custom(CustomPart),
+}
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    #[serde(default)]
+    parts: BTreeMap<String, PartEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PartEntry {
+    name: String,
+    region: RegionBox,
+    #[serde(default)]
+    shape: ShapeName,
+    #[serde(default)]
+    prompt: String,
+}
+
+// Top-left corner and size, as fractions of the image
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegionBox {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ShapeName {
+    #[default]
+    Ellipse,
+    Rectangle,
+}
+
+impl PartsManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read parts manifest {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid parts manifest {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: ManifestFile = toml::from_str(text)?;
+        let mut parts = BTreeMap::new();
+
+        for (key, entry) in file.parts {
+            let key = key.trim().to_ascii_lowercase();
+            if key.parse::<PartType>().is_ok() {
+                anyhow::bail!("part '{}' is built in and can't be redefined", key);
+            }
+            let RegionBox { x, y, width, height } = entry.region;
+            let fits = |start: f32, size: f32| start >= 0.0 && size > 0.0 && start + size <= 1.0;
+            if !fits(x, width) || !fits(y, height) {
+                anyhow::bail!("part '{}': region must lie within the image (fractions 0..1)", key);
+            }
+
+            let part = CustomPart {
+                key: key.clone(),
+                name: entry.name,
+                region: PartRegion {
+                    center_x: x + width / 2.0,
+                    center_y: y + height / 2.0,
+                    radius_x: width / 2.0,
+                    radius_y: height / 2.0,
+                },
+                shape: match entry.shape {
+                    ShapeName::Ellipse => MaskShape::Ellipse,
+                    ShapeName::Rectangle => MaskShape::Rectangle,
+                },
+                prompt: entry.prompt,
+            };
+            parts.insert(key, part);
+        }
+
+        Ok(Self { parts })
+    }
+
+    pub fn len(&self) -> usize {
+        self.parts.len()
+    }
+
+    // A built-in part name or a manifest key, case-insensitive
+    pub fn resolve(&self, value: &str) -> Result<Part, String> {
+        if let Ok(part_type) = value.parse::<PartType>() {
+            return Ok(Part::Builtin(part_type));
+        }
+        if let Some(part) = self.parts.get(&value.trim().to_ascii_lowercase()) {
+            return Ok(Part::Custom(part.clone()));
+        }
+
+        let expected: Vec<&str> = PartType::all().iter()
+            .map(|part| part.as_str())
+            .chain(self.parts.keys().map(String::as_str))
+            .collect();
+        Err(format!("expected one of {}, got '{}'", expected.join("|"), value))
+    }
+}
+
+impl From<PartType> for Part {
+    fn from(part_type: PartType) -> Self {
+        Part::Builtin(part_type)
+    }
+}
+
+impl Part {
+    pub fn key(&self) -> &str {
+        match self {
+            Part::Builtin(part_type) => part_type.as_str(),
+            Part::Custom(part) => &part.key,
+        }
+    }
+
+    // The part as named in the inpaint prompt
+    pub fn name(&self) -> &str {
+        match self {
+            Part::Builtin(part_type) => MotorcycleCustomizer::part_name(*part_type),
+            Part::Custom(part) => &part.name,
+        }
+    }
+
+    // The caller's description, after the manifest's prompt fragment if there is one
+    pub fn describe(&self, part_description: &str) -> String {
+        match self {
+            Part::Custom(part) if !part.prompt.trim().is_empty() => match part_description.trim() {
+                "" => part.prompt.clone(),
+                description => format!("{}, {}", part.prompt, description),
+            },
+            _ => part_description.to_string(),
+        }
+    }
+
+    pub fn mask(&self, width: u32, height: u32, intensity: MaskIntensity) -> Result<GrayImage> {
+        let config = MaskConfig::default();
+        match self {
+            Part::Builtin(part_type) => MaskGenerator::create_part_mask(width, height, *part_type, intensity, &config),
+            Part::Custom(part) => {
+                MaskGenerator::create_region_mask(width, height, part.region, part.shape, intensity, &config)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp::unique_path_in;
+
+    const MANIFEST: &str = r#"
+        [parts.Crash_Bars]
+        name = "crash bars"
+        region = { x = 0.25, y = 0.5, width = 0.5, height = 0.25 }
+        shape = "rectangle"
+        prompt = "tubular steel engine guard"
+
+        [parts.top_box]
+        name = "top box"
+        region = { x = 0.7, y = 0.1, width = 0.2, height = 0.2 }
+    "#;
+
+    #[test]
+    fn loads_a_custom_part_and_masks_its_region() {
+        let path = unique_path_in(&std::env::temp_dir(), "parts", "toml");
+        std::fs::write(&path, MANIFEST).unwrap();
+        let manifest = PartsManifest::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(manifest.len(), 2);
+
+        let part = manifest.resolve("crash_bars").unwrap();
+        assert_eq!(part.key(), "crash_bars");
+        assert_eq!(part.name(), "crash bars");
+        assert_eq!(part.describe("black"), "tubular steel engine guard, black");
+
+        // A 0.5x0.25 box at (0.25, 0.5): solid in the middle, untouched far outside it
+        let mask = part.mask(400, 400, MaskIntensity::Medium).unwrap();
+        assert!(mask.get_pixel(200, 250)[0] > 200);
+        assert!(mask.get_pixel(110, 210)[0] > 100, "a rectangle fills its corners");
+        assert_eq!(mask.get_pixel(20, 20)[0], 0);
+        assert_eq!(mask.get_pixel(200, 390)[0], 0);
+
+        assert!(matches!(manifest.resolve("Seat").unwrap(), Part::Builtin(PartType::Seat)));
+        assert_eq!(
+            manifest.resolve("fairing").unwrap_err(),
+            "expected one of exhaust|seat|handlebar|crash_bars|top_box, got 'fairing'"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_regions_and_builtin_keys() {
+        let outside = r#"
+            [parts.spoiler]
+            name = "spoiler"
+            region = { x = 0.8, y = 0.1, width = 0.5, height = 0.2 }
+        "#;
+        assert!(PartsManifest::parse(outside).unwrap_err().to_string().contains("within the image"));
+
+        let builtin = r#"
+            [parts.seat]
+            name = "seat"
+            region = { x = 0.4, y = 0.3, width = 0.2, height = 0.2 }
+        "#;
+        assert!(PartsManifest::parse(builtin).unwrap_err().to_string().contains("built in"));
+    }
+}
@@ -2,194 +2,386 @@ use base64::{Engine, engine::general_purpose};
 use bytes::Bytes;
 
 use serde_json::json;
-use tracing::info;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::util::debug_dump::DebugDump;
+use crate::util::mime::ImageBytes;
+use crate::util::rate_limit::{RateLimited, retry_after_secs};
+use crate::util::single_flight::SingleFlight;
+
+// Why a Gemini call failed, so handlers can tell a refused prompt from an outage
+#[derive(Debug, Clone)]
+pub enum GeminiError {
+    // Gemini answered with an `error` object
+    Api { code: i64, message: String },
+    // A 429; no point retrying before the quota resets
+    RateLimited(RateLimited),
+    // Unreachable or a 5xx; unlike a rejected prompt, another provider may well succeed
+    Unavailable(String),
+    // The prompt or the generated image was blocked; holds the block reason and flagged categories
+    Safety(Vec<String>),
+    // A well-formed response without any image in it
+    NoImage,
+    // A candidate whose parts carry neither text nor inline data; a transient hiccup that
+    // a repeat request usually gets past, so it is retried before surfacing
+    EmptyParts,
+    // The response body wasn't what the API documents
+    Parse(String),
+    // The request itself failed before any response came back (shared with coalesced callers)
+    Http(Arc<reqwest::Error>),
+    // Inline input or the response went over a size limit
+    TooLarge(String),
+}
+
+impl GeminiError {
+    // Gemini can't serve the request right now, as opposed to refusing this particular one
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self, Self::Unavailable(_) | Self::RateLimited(_))
+    }
+}
+
+impl fmt::Display for GeminiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Api { code, message } => write!(f, "Gemini API error ({}): {}", code, message),
+            Self::RateLimited(limit) => limit.fmt(f),
+            Self::Unavailable(message) | Self::TooLarge(message) => f.write_str(message),
+            Self::Safety(reasons) => write!(f, "Gemini blocked the request for safety reasons: {}", reasons.join(", ")),
+            Self::NoImage => f.write_str("Failed to extract image data from response"),
+            Self::EmptyParts => f.write_str("Gemini returned a response with no content in it"),
+            Self::Parse(message) => write!(f, "Failed to parse Gemini response: {}", message),
+            Self::Http(e) => write!(f, "Gemini request failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GeminiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for GeminiError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(Arc::new(e))
+    }
+}
+
+// finishReason values meaning the output was withheld rather than never produced
+const SAFETY_FINISH_REASONS: &[&str] = &["SAFETY", "IMAGE_SAFETY", "PROHIBITED_CONTENT", "BLOCKLIST", "SPII"];
+
+// The block reason plus every category Gemini flagged as blocked, when the prompt
+// (promptFeedback) or the first candidate (finishReason) was stopped by a safety filter
+fn safety_block(result: &serde_json::Value) -> Option<Vec<String>> {
+    let (reason, ratings) = if let Some(reason) = result["promptFeedback"]["blockReason"].as_str() {
+        (reason, &result["promptFeedback"]["safetyRatings"])
+    } else {
+        let candidate = &result["candidates"][0];
+        let reason = candidate["finishReason"].as_str()
+            .filter(|reason| SAFETY_FINISH_REASONS.contains(reason))?;
+        (reason, &candidate["safetyRatings"])
+    };
+
+    let mut reasons = vec![reason.to_string()];
+    reasons.extend(
+        ratings.as_array().into_iter().flatten()
+            .filter(|rating| rating["blocked"].as_bool() == Some(true))
+            .filter_map(|rating| rating["category"].as_str().map(String::from)),
+    );
+    Some(reasons)
+}
 
 pub struct GeminiClient {
     api_key : String,
+    base_url: String,
+    client: reqwest::Client,
+    debug_dump: Option<DebugDump>,
+    max_response_bytes: usize,
+    // Extra attempts after an `EmptyParts` response
+    empty_parts_retries: u32,
+    // Identical generations running at the same time share one upstream call
+    in_flight: SingleFlight<GenerationKey, Result<Bytes, GeminiError>>,
 }
 
+// (operation, prompt, input images); images compare by content
+type GenerationKey = (&'static str, String, Vec<ImageBytes>);
+
 impl GeminiClient {
-    pub fn new() -> Self {
-        let api_res = std::env::var("GEMINI_API_KEY");
+    const GEMINI_API_BASE: &str = "https://generativelanguage.googleapis.com";
+    const MODEL: &str = "gemini-2.5-flash-image";
+    // Gemini rejects requests whose inline data exceeds ~20MB
+    const MAX_INLINE_BYTES: usize = 20 * 1024 * 1024;
+    const DEFAULT_MAX_RESPONSE_BYTES: usize = 64 * 1024 * 1024;
+    const DEFAULT_EMPTY_PARTS_RETRIES: u32 = 2;
 
-        match api_res {
-            Ok(key) => GeminiClient { api_key: key },
-            Err(_) => panic!("GEMINI_API_KEY environment variable not set"),
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self::with_base_url(api_key, Self::GEMINI_API_BASE)
+    }
+
+    // Point the client at a different API host (used by tests against a local mock)
+    pub fn with_base_url(api_key: impl Into<String>, base_url: impl Into<String>) -> Self {
+        GeminiClient {
+            api_key: api_key.into(),
+            base_url: base_url.into(),
+            client: reqwest::Client::new(),
+            debug_dump: None,
+            max_response_bytes: Self::DEFAULT_MAX_RESPONSE_BYTES,
+            empty_parts_retries: Self::DEFAULT_EMPTY_PARTS_RETRIES,
+            in_flight: SingleFlight::default(),
         }
     }
 
+    // Refuse response bodies larger than `limit` instead of buffering them
+    pub fn with_max_response_bytes(mut self, limit: usize) -> Self {
+        self.max_response_bytes = limit;
+        self
+    }
+
+    // Re-request up to `retries` times when a response comes back without any content parts
+    pub fn with_empty_parts_retries(mut self, retries: u32) -> Self {
+        self.empty_parts_retries = retries;
+        self
+    }
+
+    // Give up on requests that take longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+        self
+    }
+
+    // Dump request/response bodies to `dir` (API key redacted) when set
+    pub fn with_debug_dump(mut self, dir: Option<std::path::PathBuf>) -> Self {
+        self.debug_dump = dir.map(|dir| DebugDump::new(dir, vec![self.api_key.clone()]));
+        self
+    }
+
     pub async fn extract_image_nanobanana(
         &self,
         prompt: String,
-        image: Bytes
-    ) -> Result<Bytes, Box<dyn std::error::Error>> {
-        info!("Starting image generation with {} images", image.len());
-        
+        image: ImageBytes
+    ) -> Result<Bytes, GeminiError> {
+        let key = ("extract_image", prompt.clone(), vec![image.clone()]);
+        self.in_flight.run(key, self.extract_image(prompt, image)).await
+    }
+
+    async fn extract_image(&self, prompt: String, image: ImageBytes) -> Result<Bytes, GeminiError> {
+        info!(provider = "gemini", op = "extract_image", input_bytes = image.len(), "start");
+        Self::check_inline_size(std::slice::from_ref(&image))?;
+
         let mut __parts__ = vec![
             json!({
                 "text": prompt
             })
         ];
-        
-        let mime_type = if image.starts_with(&[0xFF, 0xD8, 0xFF]) {
-            "image/jpeg"
-        } else if image.starts_with(&[0x89, 0x50, 0x4E, 0x47]) {
-            "image/png"
-        } else if image.starts_with(&[0x47, 0x49, 0x46]) {
-            "image/gif"
-        } else if image.starts_with(&[0x52, 0x49, 0x46, 0x46]) {
-            "image/webp"
-        } else {
-            info!("Unknown image format, defaulting to image/jpeg");
-            "image/jpeg"
-        };
-        
-        info!("Detected MIME type: {}", mime_type);
-        
-        let img_base64 = general_purpose::STANDARD.encode(&image);
+
+        info!("Image MIME type: {}", image.mime());
+
+        let img_base64 = general_purpose::STANDARD.encode(&*image);
 
         __parts__.push(json!({
             "inline_data": {
-                "mime_type": mime_type,
+                "mime_type": image.mime(),
                 "data": img_base64
             }
         }));
-            
-        let body = json!({
-            "contents": [{
-                "parts": __parts__
-            }]
-        });
-        
-        info!("Sending request to Gemini API...");
-            
-        // API 호출
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash-image:generateContent")
-            .header("x-goog-api-key", &self.api_key)
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
-            
-        let status = response.status();
-        info!("Gemini API response status: {}", status);
-        
-        // 응답 텍스트를 먼저 가져오기
-        let response_text = response.text().await?;
-        //info!("Gemini API response length: {} bytes", response_text.len());
-        
-        // 텍스트를 JSON으로 파싱
-        let result: serde_json::Value = serde_json::from_str(&response_text)
-            .map_err(|e| format!("Failed to parse JSON."))?;
-        
-        // 에러 체크
-        if let Some(error) = result.get("error") {
-            let error_message = error.get("message")
-                .and_then(|m| m.as_str())
-                .unwrap_or("Unknown error");
-            let error_code = error.get("code")
-                .and_then(|c| c.as_i64())
-                .unwrap_or(0);
-
-            info!("Gemini API error ({}): {}", error_code, error_message);
-
-            return Err(format!("Gemini API error ({}): {}", error_code, error_message).into());
-        }
-        
-        // 생성된 이미지 추출
-        let parts = result["candidates"][0]["content"]["parts"].as_array()
-            .ok_or("Failed to get parts array")?;
 
-        for part in parts {
-            // inlineData로 변경!
-            if let Some(data) = part["inlineData"]["data"].as_str() {
-                info!("Successfully extracted image data");
-                let decoded = general_purpose::STANDARD.decode(data)?;
-                info!("Decoded image size: {} bytes", decoded.len());
-                return Ok(Bytes::from(decoded));
-            }
-        }
-                
-        info!("No image data found in response. Response structure: {}", 
-            serde_json::to_string_pretty(&result["candidates"][0]["content"]).unwrap_or_else(|_| "Unable to serialize".to_string())
-        );
-        
-        Err("Failed to extract image data from response".into())
+        self.generate_content("extract_image", __parts__).await
     }
 
     pub async fn gen_image_nanobanana(
         &self,
         prompt: String,
-        images: Vec<Bytes>
-    ) -> Result<Bytes, Box<dyn std::error::Error>> {
-        info!("Starting image generation with {} images", images.len());
-        
+        images: Vec<ImageBytes>
+    ) -> Result<Bytes, GeminiError> {
+        let key = ("gen_image", prompt.clone(), images.clone());
+        self.in_flight.run(key, self.gen_image(prompt, images)).await
+    }
+
+    async fn gen_image(&self, prompt: String, images: Vec<ImageBytes>) -> Result<Bytes, GeminiError> {
+        info!(provider = "gemini", op = "gen_image", images = images.len(), "start");
+        Self::check_inline_size(&images)?;
+
         // 이미지들을 base64로 인코딩
         let mut __parts__ = vec![
             json!({
                 "text": prompt
             })
         ];
-        
+
         for (idx, image_bytes) in images.iter().enumerate() {
             info!("Processing image {}: {} bytes", idx, image_bytes.len());
-            
-            // 이미지 타입 감지
-            let mime_type = if image_bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
-                "image/jpeg"
-            } else if image_bytes.starts_with(&[0x89, 0x50, 0x4E, 0x47]) {
-                "image/png"
-            } else if image_bytes.starts_with(&[0x47, 0x49, 0x46]) {
-                "image/gif"
-            } else if image_bytes.starts_with(&[0x52, 0x49, 0x46, 0x46]) {
-                "image/webp"
-            } else {
-                info!("Unknown image format, defaulting to image/jpeg");
-                "image/jpeg"
-            };
-            
-            info!("Detected MIME type: {}", mime_type);
-            
-            let img_base64 = general_purpose::STANDARD.encode(&image_bytes);
+
+            info!("Image MIME type: {}", image_bytes.mime());
+
+            let img_base64 = general_purpose::STANDARD.encode(&**image_bytes);
             __parts__.push(json!({
                 "inline_data": {
-                    "mime_type": mime_type,
+                    "mime_type": image_bytes.mime(),
                     "data": img_base64
                 }
             }));
         }
-        
+
+        self.generate_content("gen_image", __parts__).await
+    }
+
+    // Look up the model, which checks the key without generating anything
+    pub async fn probe(&self) -> Result<(), GeminiError> {
+        let response = self.client
+            .get(format!("{}/v1beta/models/{}", self.base_url, Self::MODEL))
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await?;
+            return Err(GeminiError::Api { code: status.as_u16() as i64, message });
+        }
+        Ok(())
+    }
+
+    // Buffer the body, giving up as soon as it is known to exceed `max_response_bytes`:
+    // up front from Content-Length when the upstream sends one, otherwise while streaming
+    async fn read_limited(&self, mut response: reqwest::Response) -> Result<String, GeminiError> {
+        let limit = self.max_response_bytes;
+        let too_large = |size: u64| {
+            GeminiError::TooLarge(format!(
+                "Gemini response too large: {} bytes exceeds the {} byte limit (GEMINI_MAX_RESPONSE_BYTES)",
+                size, limit
+            ))
+        };
+
+        if let Some(length) = response.content_length()
+            && length > limit as u64
+        {
+            return Err(too_large(length));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(too_large((body.len() + chunk.len()) as u64));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        String::from_utf8(body).map_err(|e| GeminiError::Parse(e.to_string()))
+    }
+
+    // Fail before encoding when the combined base64 payload would exceed the inline limit
+    fn check_inline_size(images: &[ImageBytes]) -> Result<(), GeminiError> {
+        let encoded: usize = images.iter().map(|img| img.len().div_ceil(3) * 4).sum();
+
+        if encoded > Self::MAX_INLINE_BYTES {
+            return Err(GeminiError::TooLarge(format!(
+                "Images too large for an inline Gemini request: {} bytes base64-encoded, limit is {} bytes. \
+                Send fewer or smaller images, or upload them through the Gemini Files API",
+                encoded,
+                Self::MAX_INLINE_BYTES
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Send a generateContent request and pull the first inline image out of the response,
+    // repeating it when Gemini answers with empty content
+    async fn generate_content(
+        &self,
+        op: &'static str,
+        parts: Vec<serde_json::Value>,
+    ) -> Result<Bytes, GeminiError> {
         let body = json!({
             "contents": [{
-                "parts": __parts__
+                "parts": parts
             }]
         });
-        
+
+        let mut attempt = 0;
+        loop {
+            match self.generate_content_once(op, &body).await {
+                Err(GeminiError::EmptyParts) if attempt < self.empty_parts_retries => {
+                    attempt += 1;
+                    warn!(provider = "gemini", op, attempt, "response had no content parts, retrying");
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn generate_content_once(&self, op: &'static str, body: &serde_json::Value) -> Result<Bytes, GeminiError> {
+        let dump = self.debug_dump.as_ref().map(|d| d.call("gemini", op));
+        if let Some(dump) = &dump {
+            dump.request(&body.to_string());
+        }
+
         info!("Sending request to Gemini API...");
-        
+        let started = Instant::now();
+
         // API 호출
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash-image:generateContent")
+        let response = self.client
+            .post(format!("{}/v1beta/models/{}:generateContent", self.base_url, Self::MODEL))
             .header("x-goog-api-key", &self.api_key)
             .header("Content-Type", "application/json")
-            .json(&body)
+            .json(body)
             .send()
-            .await?;
-        
+            .await
+            .map_err(|e| {
+                if e.is_connect() || e.is_timeout() {
+                    GeminiError::Unavailable(e.to_string())
+                } else {
+                    GeminiError::Http(Arc::new(e))
+                }
+            })?;
+
         let status = response.status();
-        info!("Gemini API response status: {}", status);
-        
+        let unavailable = status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+        let retry_after = retry_after_secs(response.headers());
+
         // 응답 텍스트를 먼저 가져오기
-        let response_text = response.text().await?;
-        //info!("Gemini API response length: {} bytes", response_text.len());
-        
+        let response_text = self.read_limited(response).await?;
+        let latency_ms = started.elapsed().as_millis() as u64;
+
+        if let Some(dump) = &dump {
+            dump.response(&response_text);
+        }
+
+        info!(
+            provider = "gemini",
+            op,
+            status = status.as_u16(),
+            response_bytes = response_text.len(),
+            latency_ms,
+            "response received"
+        );
+
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let message = serde_json::from_str::<serde_json::Value>(&response_text).ok()
+                .and_then(|body| body["error"]["message"].as_str().map(String::from))
+                .unwrap_or(response_text);
+            return Err(GeminiError::RateLimited(RateLimited { provider: "gemini", retry_after, message }));
+        }
+
         // 텍스트를 JSON으로 파싱
-        let result: serde_json::Value = serde_json::from_str(&response_text)
-            .map_err(|e| format!("Failed to parse JSON."))?;
-        
+        let result: serde_json::Value = match serde_json::from_str(&response_text) {
+            Ok(result) => result,
+            // Outages often come back as an HTML error page rather than JSON
+            Err(e) if unavailable => {
+                return Err(GeminiError::Unavailable(format!("Gemini API unavailable ({}): {}", status, e)));
+            }
+            Err(e) => return Err(GeminiError::Parse(e.to_string())),
+        };
+
         // 에러 체크
         if let Some(error) = result.get("error") {
             let error_message = error.get("message")
@@ -199,28 +391,282 @@ impl GeminiClient {
                 .and_then(|c| c.as_i64())
                 .unwrap_or(0);
 
-            info!("Gemini API error ({}): {}", error_code, error_message);
+            info!(provider = "gemini", op, code = error_code, latency_ms, "api error: {}", error_message);
+
+            if unavailable {
+                return Err(GeminiError::Unavailable(format!("Gemini API error ({}): {}", error_code, error_message)));
+            }
+            return Err(GeminiError::Api { code: error_code, message: error_message.to_string() });
+        }
 
-            return Err(format!("Gemini API error ({}): {}", error_code, error_message).into());
+        if let Some(reasons) = safety_block(&result) {
+            info!(provider = "gemini", op, latency_ms, "blocked by safety filters: {}", reasons.join(", "));
+            return Err(GeminiError::Safety(reasons));
         }
-        
+
         // 생성된 이미지 추출
         let parts = result["candidates"][0]["content"]["parts"].as_array()
-            .ok_or("Failed to get parts array")?;
+            .ok_or(GeminiError::NoImage)?;
+
+        // Parts that are there but say nothing, as opposed to a text-only answer
+        if parts.iter().all(|part| part["text"].is_null() && part["inlineData"].is_null()) {
+            info!(provider = "gemini", op, latency_ms, parts = parts.len(), "no content parts in response");
+            return Err(GeminiError::EmptyParts);
+        }
 
         for part in parts {
             // inlineData로 변경!
             if let Some(data) = part["inlineData"]["data"].as_str() {
-                info!("Successfully extracted image data");
-                let decoded = general_purpose::STANDARD.decode(data)?;
-                info!("Decoded image size: {} bytes", decoded.len());
+                let decoded = general_purpose::STANDARD.decode(data)
+                    .map_err(|e| GeminiError::Parse(format!("invalid image data: {}", e)))?;
+                info!(provider = "gemini", op, bytes = decoded.len(), latency_ms, "done");
                 return Ok(Bytes::from(decoded));
             }
         }
-                
-        info!("No image data found in response. Response structure: {}", 
+
+        info!("No image data found in response. Response structure: {}",
             serde_json::to_string_pretty(&result["candidates"][0]["content"]).unwrap_or_else(|_| "Unable to serialize".to_string())
         );
-        Err("Failed to extract image data from response".into())
+
+        Err(GeminiError::NoImage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::spawn_mock;
+    use axum::{Json, Router, routing::post};
+    use tracing_test::traced_test;
+
+    fn image_response(data: &[u8]) -> serde_json::Value {
+        json!({
+            "candidates": [{
+                "content": {
+                    "parts": [{
+                        "inlineData": {
+                            "mimeType": "image/png",
+                            "data": general_purpose::STANDARD.encode(data)
+                        }
+                    }]
+                }
+            }]
+        })
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn identical_concurrent_generations_share_one_upstream_call() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mock = Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+                async {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    Json(image_response(b"fake-png"))
+                }
+            }),
+        );
+        let client = GeminiClient::with_base_url("test-key", spawn_mock(mock).await);
+        let image = || ImageBytes::new(Bytes::from_static(&[0x89, 0x50, 0x4E, 0x47]));
+
+        let (first, second) = tokio::join!(
+            client.extract_image_nanobanana("extract".to_string(), image()),
+            client.extract_image_nanobanana("extract".to_string(), image()),
+        );
+        assert_eq!(first.unwrap().as_ref(), b"fake-png");
+        assert_eq!(second.unwrap().as_ref(), b"fake-png");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A different prompt is a different generation
+        let (_, _) = tokio::join!(
+            client.extract_image_nanobanana("extract".to_string(), image()),
+            client.extract_image_nanobanana("extract the seat".to_string(), image()),
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn logs_structured_fields_for_generation() {
+        let mock = Router::new().route(
+            "/v1beta/models/{model}",
+            post(|| async { Json(image_response(b"fake-png")) }),
+        );
+        let base_url = spawn_mock(mock).await;
+        let client = GeminiClient::with_base_url("test-key", base_url);
+
+        let image = client
+            .extract_image_nanobanana("extract".to_string(), ImageBytes::new(Bytes::from_static(&[0x89, 0x50, 0x4E, 0x47])))
+            .await
+            .unwrap();
+
+        assert_eq!(image.as_ref(), b"fake-png");
+        assert!(logs_contain("provider=\"gemini\""));
+        assert!(logs_contain("op=\"extract_image\""));
+        assert!(logs_contain("bytes=8"));
+        assert!(logs_contain("latency_ms="));
+    }
+
+    #[tokio::test]
+    async fn oversized_response_is_rejected() {
+        let mock = Router::new().route(
+            "/v1beta/models/{model}",
+            post(|| async { Json(image_response(&[0u8; 4096])) }),
+        );
+        let base_url = spawn_mock(mock).await;
+        let client = GeminiClient::with_base_url("test-key", base_url).with_max_response_bytes(1024);
+
+        let err = client
+            .gen_image_nanobanana("generate".to_string(), vec![ImageBytes::new(Bytes::from_static(&[0x89, 0x50, 0x4E, 0x47]))])
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.starts_with("Gemini response too large"), "{}", err);
+        assert!(err.contains("1024 byte limit"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn debug_dump_redacts_api_key() {
+        // The mock echoes the key back so both request and response dumps would leak it
+        let mock = Router::new().route(
+            "/v1beta/models/{model}",
+            post(|| async {
+                let mut body = image_response(b"fake-png");
+                body["echo"] = json!("test-key");
+                Json(body)
+            }),
+        );
+        let base_url = spawn_mock(mock).await;
+        let dir = crate::util::temp::unique_temp_path("debug_dump", "d");
+        let client = GeminiClient::with_base_url("test-key", base_url)
+            .with_debug_dump(Some(dir.clone()));
+
+        client
+            .extract_image_nanobanana("extract with test-key".to_string(), ImageBytes::new(Bytes::from_static(&[0x89, 0x50, 0x4E, 0x47])))
+            .await
+            .unwrap();
+
+        let mut dumps: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        dumps.sort();
+        assert_eq!(dumps.len(), 2);
+        assert!(dumps[0].to_string_lossy().ends_with("_request.json"));
+        assert!(dumps[1].to_string_lossy().ends_with("_response.json"));
+        for path in &dumps {
+            let contents = std::fs::read_to_string(path).unwrap();
+            assert!(!contents.contains("test-key"), "{}", path.display());
+            assert!(contents.contains("[REDACTED]"));
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_inline_payload_before_sending() {
+        // Nothing listens here; the size check must fail before any request goes out
+        let client = GeminiClient::with_base_url("test-key", "http://127.0.0.1:9");
+        let large = ImageBytes::new(Bytes::from(vec![0u8; 8 * 1024 * 1024]));
+
+        let err = client
+            .gen_image_nanobanana("combine".to_string(), vec![large.clone(), large])
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("too large for an inline Gemini request"), "{}", err);
+        assert!(err.contains("Files API"));
+    }
+
+    async fn generate_error(base_url: String) -> GeminiError {
+        GeminiClient::with_base_url("test-key", base_url)
+            .gen_image_nanobanana("generate".to_string(), vec![ImageBytes::new(Bytes::from_static(&[0x89, 0x50, 0x4E, 0x47]))])
+            .await
+            .unwrap_err()
+    }
+
+    async fn error_for(status: axum::http::StatusCode, body: &'static str) -> GeminiError {
+        let mock = Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || async move { (status, body) }),
+        );
+        generate_error(spawn_mock(mock).await).await
+    }
+
+    #[tokio::test]
+    async fn retries_a_response_without_content_parts() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mock = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || {
+                let call = counted.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match call {
+                        0 => Json(json!({ "candidates": [{ "content": { "role": "model", "parts": [{}] } }] })),
+                        _ => Json(image_response(b"fake-png")),
+                    }
+                }
+            }),
+        )).await;
+        let image = || ImageBytes::new(Bytes::from_static(&[0x89, 0x50, 0x4E, 0x47]));
+
+        let client = GeminiClient::with_base_url("test-key", mock.as_str());
+        let result = client.extract_image_nanobanana("extract".to_string(), image()).await.unwrap();
+        assert_eq!(result.as_ref(), b"fake-png");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // With retries off the empty response surfaces as its own error
+        calls.store(0, Ordering::SeqCst);
+        let client = GeminiClient::with_base_url("test-key", mock.as_str()).with_empty_parts_retries(0);
+        let err = client.extract_image_nanobanana("extract".to_string(), image()).await.unwrap_err();
+        assert!(matches!(err, GeminiError::EmptyParts), "{:?}", err);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn maps_each_failure_to_its_variant() {
+        use axum::http::StatusCode;
+
+        let err = error_for(StatusCode::BAD_REQUEST, r#"{"error":{"code":400,"message":"API key not valid"}}"#).await;
+        assert!(
+            matches!(&err, GeminiError::Api { code: 400, message } if message == "API key not valid"),
+            "{:?}", err
+        );
+
+        let err = error_for(StatusCode::TOO_MANY_REQUESTS, r#"{"error":{"code":429,"message":"Quota exceeded"}}"#).await;
+        assert!(matches!(&err, GeminiError::RateLimited(limit) if limit.message == "Quota exceeded"), "{:?}", err);
+
+        let err = error_for(StatusCode::SERVICE_UNAVAILABLE, r#"{"error":{"code":503,"message":"overloaded"}}"#).await;
+        assert!(matches!(err, GeminiError::Unavailable(_)), "{:?}", err);
+
+        let err = error_for(
+            StatusCode::OK,
+            r#"{"promptFeedback":{"blockReason":"SAFETY","safetyRatings":[
+                {"category":"HARM_CATEGORY_DANGEROUS_CONTENT","probability":"HIGH","blocked":true},
+                {"category":"HARM_CATEGORY_HARASSMENT","probability":"NEGLIGIBLE"}]}}"#,
+        ).await;
+        assert!(matches!(&err, GeminiError::Safety(reasons) if reasons == &["SAFETY", "HARM_CATEGORY_DANGEROUS_CONTENT"]), "{:?}", err);
+
+        let err = error_for(StatusCode::OK, r#"{"candidates":[{"finishReason":"IMAGE_SAFETY","content":{"parts":[]}}]}"#).await;
+        assert!(matches!(&err, GeminiError::Safety(reasons) if reasons == &["IMAGE_SAFETY"]), "{:?}", err);
+
+        let err = error_for(StatusCode::OK, r#"{"candidates":[{"content":{"parts":[{"text":"I can't draw that"}]}}]}"#).await;
+        assert!(matches!(err, GeminiError::NoImage), "{:?}", err);
+
+        let err = error_for(StatusCode::OK, "<html>not json</html>").await;
+        assert!(matches!(err, GeminiError::Parse(_)), "{:?}", err);
+
+        // A base URL reqwest can't even build a request from
+        let err = generate_error("not a url".to_string()).await;
+        assert!(matches!(err, GeminiError::Http(_)), "{:?}", err);
+    }
+}
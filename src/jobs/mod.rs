@@ -0,0 +1 @@
+pub mod queue;
\ No newline at end of file
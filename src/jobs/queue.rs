@@ -0,0 +1,202 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock, mpsc};
+use tracing::{error, info};
+
+use crate::util::mime::ImageBytes;
+
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<Bytes, String>> + Send>>;
+
+// Runs a single generation job against the configured provider
+pub type JobRunner = Arc<dyn Fn(GenerationJob) -> JobFuture + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub struct GenerationJob {
+    pub prompt: String,
+    pub images: Vec<ImageBytes>,
+}
+
+#[derive(Debug, Clone)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded(Bytes),
+    Failed(String),
+}
+
+impl JobStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded(_) => "succeeded",
+            JobStatus::Failed(_) => "failed",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Succeeded(_) | JobStatus::Failed(_))
+    }
+}
+
+#[derive(Debug)]
+pub enum SubmitError {
+    QueueFull,
+    Closed,
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::QueueFull => write!(f, "Job queue is full, try again later"),
+            SubmitError::Closed => write!(f, "Job queue is not accepting work"),
+        }
+    }
+}
+
+// Bounded mpsc queue drained by a fixed pool of workers
+pub struct JobQueue {
+    sender: mpsc::Sender<(String, GenerationJob)>,
+    // Each job's status and when it last changed
+    results: Arc<RwLock<HashMap<String, (JobStatus, Instant)>>>,
+}
+
+impl JobQueue {
+    pub fn start(workers: usize, capacity: usize, runner: JobRunner) -> Self {
+        let (sender, receiver) = mpsc::channel::<(String, GenerationJob)>(capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let results = Arc::new(RwLock::new(HashMap::new()));
+
+        for worker_id in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            let results = results.clone();
+            let runner = runner.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    // Hold the receiver lock only while waiting for the next job
+                    let next = receiver.lock().await.recv().await;
+                    let Some((job_id, job)) = next else {
+                        break;
+                    };
+
+                    info!("Worker {} picked up job {}", worker_id, job_id);
+                    results.write().await.insert(job_id.clone(), (JobStatus::Running, Instant::now()));
+
+                    let status = match runner(job).await {
+                        Ok(image) => {
+                            info!("Job {} succeeded: {} bytes", job_id, image.len());
+                            JobStatus::Succeeded(image)
+                        }
+                        Err(e) => {
+                            error!("Job {} failed: {}", job_id, e);
+                            JobStatus::Failed(e)
+                        }
+                    };
+
+                    results.write().await.insert(job_id, (status, Instant::now()));
+                }
+            });
+        }
+
+        Self { sender, results }
+    }
+
+    pub async fn submit(&self, job: GenerationJob) -> Result<String, SubmitError> {
+        let job_id = uuid::Uuid::new_v4().to_string();
+        self.results.write().await.insert(job_id.clone(), (JobStatus::Queued, Instant::now()));
+
+        if let Err(e) = self.sender.try_send((job_id.clone(), job)) {
+            self.results.write().await.remove(&job_id);
+            return Err(match e {
+                mpsc::error::TrySendError::Full(_) => SubmitError::QueueFull,
+                mpsc::error::TrySendError::Closed(_) => SubmitError::Closed,
+            });
+        }
+
+        Ok(job_id)
+    }
+
+    pub async fn status(&self, job_id: &str) -> Option<JobStatus> {
+        self.results.read().await.get(job_id).map(|(status, _)| status.clone())
+    }
+
+    // Forget results that finished more than `ttl` ago, returning how many went.
+    // Queued and running jobs are kept however old they are.
+    pub async fn evict_finished(&self, ttl: Duration) -> usize {
+        let mut results = self.results.write().await;
+        let before = results.len();
+        results.retain(|_, (status, updated)| !status.is_finished() || updated.elapsed() < ttl);
+        before - results.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> GenerationJob {
+        GenerationJob {
+            prompt: "prompt".to_string(),
+            images: vec![ImageBytes::new(Bytes::from_static(b"img"))],
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_work_when_queue_is_full() {
+        let runner: JobRunner = Arc::new(|_| Box::pin(async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(Bytes::new())
+        }));
+        let queue = JobQueue::start(1, 1, runner);
+
+        // One job occupies the worker, one fills the channel
+        queue.submit(job()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        queue.submit(job()).await.unwrap();
+
+        assert!(matches!(queue.submit(job()).await, Err(SubmitError::QueueFull)));
+    }
+
+    #[tokio::test]
+    async fn records_failures() {
+        let runner: JobRunner = Arc::new(|_| Box::pin(async { Err("boom".to_string()) }));
+        let queue = JobQueue::start(1, 4, runner);
+
+        let job_id = queue.submit(job()).await.unwrap();
+        for _ in 0..50 {
+            if let Some(JobStatus::Failed(e)) = queue.status(&job_id).await {
+                assert_eq!(e, "boom");
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job never failed");
+    }
+
+    #[tokio::test]
+    async fn evicts_finished_results_after_ttl() {
+        let runner: JobRunner = Arc::new(|job: GenerationJob| Box::pin(async move {
+            if job.prompt == "slow" {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+            Ok(Bytes::new())
+        }));
+        let queue = JobQueue::start(2, 4, runner);
+
+        let done = queue.submit(job()).await.unwrap();
+        let running = queue.submit(GenerationJob { prompt: "slow".to_string(), ..job() }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(queue.status(&done).await, Some(JobStatus::Succeeded(_))));
+        assert_eq!(queue.evict_finished(Duration::from_secs(60)).await, 0);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(queue.evict_finished(Duration::from_millis(50)).await, 1);
+        assert!(queue.status(&done).await.is_none());
+        assert!(matches!(queue.status(&running).await, Some(JobStatus::Running)));
+    }
+}
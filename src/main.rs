@@ -1,45 +1,105 @@
 mod aws;
+mod config;
 mod gemini;
 mod custom;
 mod util;
 mod meshy;
+mod jobs;
+mod moderation;
+#[cfg(test)]
+mod test_support;
 
 use base64::{Engine, engine::general_purpose};
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::time::Duration;
 
 use reqwest::Client;
 use axum::{
     Router, 
-    extract::{ConnectInfo, Multipart, Request, Path, ws::{Message, WebSocket, WebSocketUpgrade}, State}, 
-    http::{StatusCode, header}, 
-    response::{IntoResponse, Json, Response}, 
-    routing::{get, post},
+    extract::{DefaultBodyLimit, Multipart, Path, Query, ws::{Message, WebSocket, WebSocketUpgrade}, State}, 
+    http::{HeaderMap, HeaderValue, StatusCode, header}, 
+    response::{IntoResponse, Json, Response, sse::{Event, KeepAlive, Sse}}, 
+    routing::{delete, get, post},
     body::Body
 };
 
-use futures::sink::SinkExt;
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{self, SplitSink, StreamExt};
 
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 
-use std::{net::SocketAddr, sync::Arc};
-use tracing::{info, error, Level};
-use tracing_subscriber;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{info, error, warn, Level};
+use tower_http::compression::{CompressionLayer, Predicate, predicate::{DefaultPredicate, NotForContentType}};
 use tower_http::cors::{CorsLayer, Any};
 use dotenv::dotenv;
 
-use crate::{gemini::client::GeminiClient, meshy::client::TaskCreatedResponse};
+use crate::{gemini::client::{GeminiClient, GeminiError}, meshy::client::{ArtStyle, CreateTaskError, Meshy3dOptions, TaskCreatedResponse, TaskNotFound}};
+use crate::aws::bedrock::{BedrockImageGenerator, is_bedrock_outage};
+use crate::aws::model_cache::ModelCache;
+use crate::aws::s3_input::{S3InputError, S3Inputs};
+use crate::config::{Config, CustomizeBackend, ImageProvider};
 use crate::meshy::client::MeshyClient;
+use crate::custom::backend::{GeminiBackend, PartDescriptions};
+use crate::custom::motorcycle::MotorcycleCustomizer;
+use crate::custom::parts::{Part, PartsManifest};
+use crate::jobs::queue::{GenerationJob, JobQueue, JobRunner, JobStatus, SubmitError};
+use crate::moderation::client::{ModerationClient, ModerationError};
+use crate::util::circuit_breaker::{CircuitOpen, ProviderBreakers};
+use crate::util::encode::{OutputFormat, data_url, encode_as, letterbox, negotiate};
+use crate::util::idempotency::IdempotencyStore;
+use crate::util::single_flight::SingleFlight;
+use crate::util::post_process::{PostProcess, Watermark};
+use crate::util::image_mask::{InvalidOptionError, MaskGenerator, MaskIntensity, PartType};
+use crate::util::keying::{DEFAULT_WHITE_TOLERANCE, white_to_alpha};
+use crate::util::rate_limit::RateLimited;
+#[cfg(feature = "heic")]
+use crate::util::mime::heic_to_png;
+use crate::util::mime::{ImageBytes, check_raster, gif_first_frame_png, is_glb, is_heic, sniff_header, sniff_mime};
+use crate::util::multipart::{collect_form, collect_images, empty_form_error, is_image_field, upload_too_large_error};
+use crate::util::temp::{check_writable, is_not_writable, unique_path_in};
+#[cfg(feature = "stats")]
+use crate::util::stats::Stats;
 
 #[derive(Clone)]
 pub struct AppState {
+    config: Arc<Config>,
+    gemini: Arc<GeminiClient>,
     meshy_client: Arc<MeshyClient>,
+    customizer: Arc<MotorcycleCustomizer>,
+    jobs: Arc<JobQueue>,
+    idempotency: Arc<IdempotencyStore>,
+    // 3D creates in progress by Idempotency-Key, so a retry waits on the original
+    creating_3d: Arc<SingleFlight<String, Result<String, ApiError>>>,
+    model_cache: Option<Arc<ModelCache>>,
+    // Only set when S3_INPUT_BUCKETS names at least one bucket
+    s3_inputs: Option<Arc<S3Inputs>>,
+    breakers: Arc<ProviderBreakers>,
+    parts: Arc<PartsManifest>,
+    post_process: Arc<PostProcess>,
+    // Only set when MODERATION_URL names a hook
+    moderation: Option<Arc<ModerationClient>>,
+    #[cfg(feature = "stats")]
+    stats: Arc<Stats>,
 }
 
+const JOB_WORKERS: usize = 2;
+const JOB_QUEUE_CAPACITY: usize = 32;
+
+const EXHAUST_INSTALL_PROMPT: &str =
+    "Generate a photorealistic image of the base motorcycle with the custom exhaust system installed.
+        The exhaust should replace the original exhaust, maintaining the same lighting conditions, shadows, and perspective as the base image. 
+        Ensure the exhaust pipe diameter, mounting position, and finish match realistic installation standards. 
+        The image should look like a professional product photograph.";
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
@@ -49,446 +109,5511 @@ async fn main() {
         .with_max_level(Level::INFO)
         .init();
 
-    // API 키 확인
-    match std::env::var("GEMINI_API_KEY") {
-        Ok(_) => info!("GEMINI_API_KEY loaded successfully"),
-        Err(_) => panic!("GEMINI_API_KEY not found in environment"),
-    }
-
-    match std::env::var("MESHY_API_KEY") {
-        Ok(_) => info!("MESHY_API_KEY loaded successfully"),
-        Err(_) => panic!("MESHY_API_KEY not found in environment"),
-    }
+    // 설정 로드 (API 키 포함)
+    let config = match Config::from_env() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "Configuration loaded, image provider: {}, customize backend: {}",
+        config.image_provider.as_str(),
+        config.customize_backend.as_str(),
+    );
+    report_unwritable_dirs(&config);
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let meshy_client = Arc::new(MeshyClient::new());
+    // Background generation jobs run against Gemini
+    let gemini_client = Arc::new(configured_gemini(GeminiClient::new(config.gemini_api_key.clone()), &config));
+    let runner_client = gemini_client.clone();
+    let runner: JobRunner = Arc::new(move |job: GenerationJob| {
+        let gemini_client = runner_client.clone();
+        Box::pin(async move {
+            gemini_client.gen_image_nanobanana(job.prompt, job.images).await
+                .map_err(|e| e.to_string())
+        })
+    });
+
+    let model_cache = match &config.model_cache_bucket {
+        Some(bucket) => Some(Arc::new(ModelCache::connect(bucket.clone()).await)),
+        None => None,
+    };
+    let s3_inputs = match config.s3_input_buckets.is_empty() {
+        true => None,
+        false => Some(Arc::new(S3Inputs::connect(config.s3_input_buckets.clone()).await)),
+    };
+    let parts = match &config.parts_manifest {
+        Some(path) => match PartsManifest::load(path) {
+            Ok(manifest) => {
+                info!("Loaded {} custom parts from {}", manifest.len(), path.display());
+                manifest
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+        },
+        None => PartsManifest::default(),
+    };
+    let post_process = match post_process_from(&config) {
+        Ok(post_process) => post_process,
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let state = AppState {
+        config: config.clone(),
+        gemini: gemini_client,
+        meshy_client: Arc::new(configured_meshy(MeshyClient::new(config.meshy_api_key.clone()), &config)),
+        customizer: Arc::new(MotorcycleCustomizer::with_generator(
+            BedrockImageGenerator::new(&config.bedrock_regions)
+                .await
+                .expect("Failed to initialize Bedrock customizer")
+                .with_timeout(config.upstream_timeout)
+                .with_params(config.sdxl_params)
+                .with_debug_dump(config.debug_dump_dir.clone()),
+        )),
+        jobs: Arc::new(JobQueue::start(JOB_WORKERS, JOB_QUEUE_CAPACITY, runner)),
+        idempotency: Arc::new(IdempotencyStore::new(config.idempotency_ttl)),
+        creating_3d: Arc::new(SingleFlight::default()),
+        model_cache,
+        s3_inputs,
+        breakers: Arc::new(ProviderBreakers::new(config.breaker_failure_threshold, config.breaker_cooldown)),
+        parts: Arc::new(parts),
+        post_process: Arc::new(post_process),
+        moderation: config.moderation_url.clone().map(|url| {
+            info!("Content moderation enabled, failing {}", if config.moderation_fail_open { "open" } else { "closed" });
+            Arc::new(
+                ModerationClient::new(url)
+                    .with_timeout(config.moderation_timeout)
+                    .with_fail_open(config.moderation_fail_open),
+            )
+        }),
+        #[cfg(feature = "stats")]
+        stats: Arc::new(Stats::new()),
+    };
+
+    let warmup_state = config.warmup.then(|| state.clone());
+    spawn_registry_cleanup(state.clone());
 
     let app = Router::new()
-        .route("/test", post(test))
-        .route("/gen_image", post(generate_image))
-        // Consider to integrate these three into one with different prompts
-        .route("/extract_exhaust", post(extract_exhaust_image))
-        .route("/extract_seat", post(extract_seat_image))
-        .route("/extract_frame", post(extract_frame_image))
+        .route("/mask/preview", post(mask_preview))
+        .route("/meta/options", get(meta_options))
         .route("/", post(handler))
-        .merge(create_router(meshy_client))
+        .with_state(state.clone())
+        .merge(create_router(state))
         .layer(cors);
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080")
+    let listener = tokio::net::TcpListener::bind(config.bind_addr)
         .await
         .unwrap();
 
-    info!("Server running on http://127.0.0.1:8080");
+    info!("Server running on http://{}", config.bind_addr);
+
+    if let Some(state) = warmup_state {
+        tokio::spawn(async move { warm_up(&state).await });
+    }
 
     axum::serve(listener, app)
         .await
         .unwrap();
 }
 
-async fn test(mut multipart: Multipart) -> Result<Json<serde_json::Value>, StatusCode> {
+// Say up front when UPLOAD_DIR or TEMP_DIR can't be written, instead of on the first request
+// that needs it. Not fatal: most endpoints never touch the upload dir.
+fn report_unwritable_dirs(config: &Config) {
+    for (label, setting, dir) in [("upload", "UPLOAD_DIR", &config.upload_dir), ("temp", "TEMP_DIR", &config.temp_dir)] {
+        if let Err(e) = check_writable(dir) {
+            error!("{} directory is not writable: {} ({}); set {} to a writable path", label, dir.display(), e, setting);
+        }
+    }
+}
+
+// A failed write into UPLOAD_DIR or TEMP_DIR; a permission problem is the deployment's to fix,
+// so it gets a 503 naming the directory rather than an opaque 500
+fn dir_write_error(label: &str, dir: &std::path::Path, action: String, e: std::io::Error) -> (StatusCode, String) {
+    if is_not_writable(&e) {
+        error!("{} directory is not writable: {} ({})", label, dir.display(), e);
+        return (StatusCode::SERVICE_UNAVAILABLE, format!("{} directory is not writable: {}", label, dir.display()));
+    }
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("{}: {}", action, e))
+}
+
+// The POST_* steps run on every generated image; the watermark file is read once here
+fn post_process_from(config: &Config) -> anyhow::Result<PostProcess> {
+    let mut post_process = PostProcess::default();
+    if let Some(max_dimension) = config.post_max_dimension {
+        post_process = post_process.with_max_dimension(max_dimension);
+    }
+    if let Some(path) = &config.post_watermark {
+        post_process = post_process.with_watermark(Watermark::load(path, config.post_watermark_corner)?);
+        info!("Watermarking generated images with {}", path.display());
+    }
+    if let Some(format) = config.post_format {
+        post_process = post_process.with_format(format);
+    }
+    Ok(post_process)
+}
+
+// Apply the configured limits to a Gemini client
+fn configured_gemini(client: GeminiClient, config: &Config) -> GeminiClient {
+    client
+        .with_timeout(config.gemini_timeout)
+        .with_max_response_bytes(config.gemini_max_response_bytes)
+        .with_empty_parts_retries(config.gemini_empty_parts_retries)
+        .with_debug_dump(config.debug_dump_dir.clone())
+}
+
+fn configured_meshy(client: MeshyClient, config: &Config) -> MeshyClient {
+    client.with_timeout(config.meshy_timeout)
+}
+
+async fn test(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
     info!("Received multipart request");
-    
+
+    let form = collect_form(&mut multipart, |_| true).await?;
     let mut saved_files = Vec::new();
-    
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap_or("unknown").to_string();
-        let filename = field.file_name()
-            .map(|s| s.to_string())
+    // How each upload was recognized, so clients can check theirs came through as intended
+    let mut detected = serde_json::Map::new();
+
+    for ((name, data), file_name) in form.images.into_iter().zip(form.file_names) {
+        // Only the last component of the client's name, so it can't point outside the upload dir
+        let filename = file_name
+            .as_deref()
+            .and_then(|f| std::path::Path::new(f).file_name())
+            .map(|f| f.to_string_lossy().into_owned())
             .unwrap_or_else(|| format!("{}.png", name));
-        
-        let data = field.bytes().await.unwrap();
-        
-        let filepath = format!("./uploads/{}", filename);
-        let mut file = File::create(&filepath).await.unwrap();
-        file.write_all(&data).await.unwrap();
-        
-        info!("Saved {} ({} bytes) to {}", name, data.len(), filepath);
+
+        let upload_dir = &state.config.upload_dir;
+        let filepath = upload_dir.join(&filename);
+        let mut file = File::create(&filepath).await
+            .map_err(|e| dir_write_error("upload", upload_dir, format!("Failed to create {}", filepath.display()), e))?;
+        file.write_all(&data).await
+            .map_err(|e| dir_write_error("upload", upload_dir, format!("Failed to write {}", filepath.display()), e))?;
+
+        info!("Saved {} ({} bytes) to {}", name, data.len(), filepath.display());
+        let mime = sniff_mime(sniff_header(&data)).unwrap_or("application/octet-stream");
+        detected.insert(filename.clone(), json!(mime));
         saved_files.push(filename);
     }
     
     let response = json!({
         "message": "Images uploaded successfully!",
-        "files": saved_files
+        "files": saved_files,
+        "detected": detected,
     });
     
     Ok(Json(response))
 }
 
-async fn generate_image(mut multipart: Multipart) -> Result<Response, (StatusCode, String)> {
+async fn generate_image(
+    State(state): State<AppState>,
+    Query(output): Query<OutputQuery>,
+    Query(encoding): Query<EncodingQuery>,
+    headers: HeaderMap,
+    body: UploadBody,
+) -> Result<Response, ApiError> {
     info!("Received image generation request");
+    let output_format = output.output_format(&headers, state.post_process.format())?;
+    let inline = encoding.is_base64()?;
     
-    let mut images = Vec::new();
-    let prompt = String::from(
-        "Generate a photorealistic image of the base motorcycle with the custom exhaust system installed.
-        The exhaust should replace the original exhaust, maintaining the same lighting conditions, shadows, and perspective as the base image. 
-        Ensure the exhaust pipe diameter, mounting position, and finish match realistic installation standards. 
-        The image should look like a professional product photograph."
-    );
-    
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))? 
-    {
-        let name = field.name().unwrap_or("unknown").to_string();
-        info!("Processing field: {}", name);
-        
-        if name.starts_with("image") || name == "file" {
-            let data = field.bytes().await
-                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?;
-            info!("Received image field '{}': {} bytes", name, data.len());
-            images.push(data);
-        }
-    }
-    
-    if images.is_empty() {
-        info!("No images received");
-        return Err((StatusCode::BAD_REQUEST, "No images provided".to_string()));
+    let prompt = String::from(EXHAUST_INSTALL_PROMPT);
+    let images = read_upload_body(&state, body).await?.images;
+    let base = images[0].clone();
+
+    let (image, provider) = generate_with_fallback(
+        &state,
+        state.gemini.gen_image_nanobanana(prompt, images),
+        base,
+        BedrockFallback::Install(PartType::Exhaust),
+    ).await?;
+    let image = post_processed(&state, &image)?;
+    if inline {
+        return Ok(provider_data_url_response(&image, output_format, provider)?);
     }
+    Ok(provider_image_response(&image, output_format, provider)?)
+}
 
-    let gemini_client = GeminiClient::new();
+// A free-form `prompt` with any number of `image*` reference fields, sent to the configured
+// IMAGE_PROVIDER. No Bedrock fallback: inpainting can't follow an arbitrary prompt.
+async fn generate_with_refs_handler(
+    State(state): State<AppState>,
+    Query(output): Query<OutputQuery>,
+    headers: HeaderMap,
+    body: UploadBody,
+) -> Result<Response, ApiError> {
+    info!("Received generation request with reference images");
+    let output_format = output.output_format(&headers, state.post_process.format())?;
 
-    match gemini_client.gen_image_nanobanana(prompt, images).await {
-        Ok(result_image) => {
-            info!("Successfully generated image: {} bytes", result_image.len());
-            
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "image/png")
-                .body(axum::body::Body::from(result_image))
-                .unwrap())
+    let form = read_upload_body(&state, body).await?;
+    let prompt = match form.fields.get("prompt").map(|prompt| prompt.trim()) {
+        None => return Err(ApiError::Message(StatusCode::BAD_REQUEST, missing_field_message("prompt"))),
+        Some("") => return Err(ApiError::Message(StatusCode::BAD_REQUEST, empty_text_field_message("prompt"))),
+        Some(prompt) => prompt.to_string(),
+    };
+    info!("Generating from {} reference images", form.images.len());
+
+    let (image, provider) = match state.config.image_provider {
+        ImageProvider::Gemini => {
+            let result = state.breakers.gemini.call(
+                state.gemini.gen_image_nanobanana(prompt, form.images),
+                is_gemini_outage,
+            ).await?;
+            (result.map_err(gemini_generate_error)?, ImageProvider::Gemini.as_str())
         }
-        Err(e) => {
-            let error_msg = format!("Failed to generate image: {}", e);
-            info!("{}", error_msg);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, error_msg))
+    };
+    let image = post_processed(&state, &image)?;
+    Ok(provider_image_response(&image, output_format, provider)?)
+}
+
+// What the extract endpoints can pull out of a bike photo
+#[derive(Debug, Clone, Copy)]
+enum ExtractTarget {
+    Exhaust,
+    Seat,
+    Frame,
+}
+
+impl ExtractTarget {
+    fn all() -> &'static [ExtractTarget] {
+        &[ExtractTarget::Exhaust, ExtractTarget::Seat, ExtractTarget::Frame]
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ExtractTarget::Exhaust => "exhaust",
+            ExtractTarget::Seat => "seat",
+            ExtractTarget::Frame => "frame",
         }
     }
-}
 
-async fn extract_exhaust_image(
-    mut multipart: Multipart,
-) -> Result<Response, (StatusCode, String)> {
-    let prompt = String::from("
+    fn prompt(&self) -> &'static str {
+        match self {
+            ExtractTarget::Exhaust => "
         Extract only the muffler and exhaust pipe from this motorcycle image. 
         Show the exhaust system as an isolated part on a clean white background. 
         Remove the motorcycle body and all other components.
-    ");
+    ",
+            ExtractTarget::Seat => "
+        Extract only the seat (saddle) from this motorcycle image.
+        Show the seat as an isolated part on a clean white background.
+        Remove the motorcycle body and all other components.
+    ",
+            ExtractTarget::Frame => "
+        Remove the exhaust pipe, muffler, and seat from the motorcycle. 
+        Show only the bare frame and engine where these parts were located. 
+        Keep the rest of the motorcycle intact and unchanged. Clean, realistic result.
+    ",
+        }
+    }
 
-    let mut img = Bytes::new();
+    fn fallback(&self) -> BedrockFallback {
+        match self {
+            ExtractTarget::Exhaust => BedrockFallback::Isolate(PartType::Exhaust),
+            ExtractTarget::Seat => BedrockFallback::Isolate(PartType::Seat),
+            ExtractTarget::Frame => BedrockFallback::Remove(&[PartType::Exhaust, PartType::Seat]),
+        }
+    }
+}
 
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))? 
-    {
-        let name = field.name().unwrap_or("unknown").to_string();
+impl std::str::FromStr for ExtractTarget {
+    type Err = InvalidOptionError;
 
-        if name == "image_motorcycle" {
-            img = field.bytes().await
-                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().to_ascii_lowercase();
 
-            info!("Extracted frame image: {} bytes", img.len());
-        }
+        ExtractTarget::all()
+            .iter()
+            .find(|target| target.as_str() == normalized)
+            .copied()
+            .ok_or_else(|| InvalidOptionError {
+                value: s.to_string(),
+                expected: ExtractTarget::all().iter().map(|t| t.as_str()).collect(),
+            })
     }
+}
 
-    if img.is_empty() {
-        info!("No images received");
-        return Err((StatusCode::BAD_REQUEST, "No images provided".to_string()));
-    }
+// Extract `target` from the image, with the Bedrock fallback when Gemini is down
+async fn extract_one(state: &AppState, target: ExtractTarget, img: ImageBytes) -> Result<(Vec<u8>, &'static str), GenerateError> {
+    generate_with_fallback(
+        state,
+        state.gemini.extract_image_nanobanana(target.prompt().to_string(), img.clone()),
+        img,
+        target.fallback(),
+    ).await
+}
+
+// `?transparent=true` keys the white background out of an extracted part for compositing;
+// `?match_input=true` letterboxes the result back to the upload's size for overlays
+#[derive(Debug, Default, Deserialize)]
+pub struct ExtractQuery {
+    #[serde(default)]
+    transparent: bool,
+    #[serde(default)]
+    match_input: bool,
+}
 
-    let gemini_client = GeminiClient::new();
+// Set on `?match_input=true` responses: whether the result had to be resized to match
+const RESIZED_HEADER: &str = "x-resized-to-input";
 
-    match gemini_client.extract_image_nanobanana(prompt, img).await {
-        Ok(result_image) => {
-            info!("Successfully generated image: {} bytes", result_image.len());
-            
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "image/png")
-                .body(axum::body::Body::from(result_image))
-                .unwrap())
-        }
-        Err(e) => {
-            let error_msg = format!("Failed to generate image: {}", e);
-            info!("{}", error_msg);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, error_msg))
+async fn extract_image(
+    state: AppState,
+    output: OutputQuery,
+    extract: ExtractQuery,
+    encoding: EncodingQuery,
+    headers: HeaderMap,
+    body: UploadBody,
+    target: ExtractTarget,
+) -> Result<Response, ApiError> {
+    let mut output_format = output.output_format(&headers, state.post_process.format())?;
+    if extract.transparent && matches!(output_format, OutputFormat::Jpeg { .. }) {
+        // JPEG can't carry alpha; only refuse when the caller asked for it explicitly
+        if output.format.is_some() {
+            return Err(ApiError::Message(StatusCode::BAD_REQUEST, "transparent output needs format=png or webp".to_string()));
         }
+        output_format = OutputFormat::Png;
+    }
+    let inline = encoding.is_base64()?;
+    let img = match body {
+        UploadBody::Form(mut multipart) => read_required_image(&mut multipart, "image_motorcycle").await?,
+        UploadBody::S3(request) => fetch_s3_image(&state, &request.s3_uri).await?,
+    };
+    moderate(&state, std::slice::from_ref(&img)).await?;
+    let input_size = match extract.match_input {
+        true => Some(image_dimensions(&img, "image_motorcycle")?),
+        false => None,
+    };
+
+    let (mut image, provider) = extract_one(&state, target, img).await?;
+    // Before keying, so the white letterbox bars turn transparent along with the background
+    let mut resized = false;
+    if let Some(size) = input_size
+        && let Some(fitted) = letterbox(&image, size).map_err(|e| {
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to resize extracted image: {}", e))
+        })?
+    {
+        image = fitted;
+        resized = true;
+    }
+    if extract.transparent {
+        image = key_out_background(&image)?;
+    }
+    let image = post_processed(&state, &image)?;
+
+    let mut response = match inline {
+        true => provider_data_url_response(&image, output_format, provider)?,
+        false => provider_image_response(&image, output_format, provider)?,
+    };
+    if input_size.is_some() {
+        response.headers_mut().insert(RESIZED_HEADER, HeaderValue::from_static(if resized { "true" } else { "false" }));
     }
+    Ok(response)
+}
+
+fn key_out_background(image: &[u8]) -> Result<Vec<u8>, (StatusCode, String)> {
+    let decoded = image::load_from_memory(image)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to decode extracted image: {}", e)))?;
+    let keyed = white_to_alpha(&decoded, DEFAULT_WHITE_TOLERANCE);
+
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(keyed)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to encode transparent image: {}", e)))?;
+    Ok(png)
+}
+
+async fn extract_exhaust_image(
+    State(state): State<AppState>,
+    Query(output): Query<OutputQuery>,
+    Query(extract): Query<ExtractQuery>,
+    Query(encoding): Query<EncodingQuery>,
+    headers: HeaderMap,
+    body: UploadBody,
+) -> Result<Response, ApiError> {
+    extract_image(state, output, extract, encoding, headers, body, ExtractTarget::Exhaust).await
 }
 
 async fn extract_seat_image(
+    State(state): State<AppState>,
+    Query(output): Query<OutputQuery>,
+    Query(extract): Query<ExtractQuery>,
+    Query(encoding): Query<EncodingQuery>,
+    headers: HeaderMap,
+    body: UploadBody,
+) -> Result<Response, ApiError> {
+    extract_image(state, output, extract, encoding, headers, body, ExtractTarget::Seat).await
+}
+
+async fn extract_frame_image(
+    State(state): State<AppState>,
+    Query(output): Query<OutputQuery>,
+    Query(extract): Query<ExtractQuery>,
+    Query(encoding): Query<EncodingQuery>,
+    headers: HeaderMap,
+    body: UploadBody,
+) -> Result<Response, ApiError> {
+    extract_image(state, output, extract, encoding, headers, body, ExtractTarget::Frame).await
+}
+
+// Extractions a batch runs at once, so a big catalog doesn't trip Gemini's rate limits
+const BATCH_EXTRACT_CONCURRENCY: usize = 4;
+
+// One input of a batch extraction; `image` is null when that input failed
+#[derive(Debug, Serialize)]
+struct BatchExtractItem {
+    index: usize,
+    image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Extract the same part from every uploaded image, reporting per-image failures in input order
+async fn extract_batch_handler(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<Vec<BatchExtractItem>>, (StatusCode, String)> {
+    let form = collect_form(&mut multipart, is_image_field).await?;
+    // A bad upload only fails its own slot, so indexes still line up with the request
+    let inputs: Vec<_> = form.images
+        .into_iter()
+        .map(|(name, data)| batch_input(&name, data))
+        .collect();
+
+    let target: ExtractTarget = form.fields.get("part")
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, missing_field_message("part")))?
+        .parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid part: {}", e)))?;
+    if inputs.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, missing_field_message("image")));
+    }
+    info!("Batch extracting {} from {} images", target.as_str(), inputs.len());
+
+    let state = &state;
+    let items = stream::iter(inputs.into_iter().enumerate())
+        .map(|(index, input)| async move {
+            let result = match input {
+                Ok(img) => match moderate(state, std::slice::from_ref(&img)).await {
+                    Ok(()) => extract_one(state, target, img).await.map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok((image, _)) => BatchExtractItem {
+                    index,
+                    image: Some(general_purpose::STANDARD.encode(image)),
+                    error: None,
+                },
+                Err(e) => BatchExtractItem { index, image: None, error: Some(e) },
+            }
+        })
+        .buffered(BATCH_EXTRACT_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(Json(items))
+}
+
+fn batch_input(name: &str, data: Bytes) -> Result<ImageBytes, String> {
+    if data.is_empty() {
+        return Err(empty_field_message(name));
+    }
+    let data = transcode_upload(name, data).map_err(|(_, message)| message)?;
+    ImageBytes::validated(data).map_err(|reason| format!("{}: {}", name, reason))
+}
+
+async fn mask_preview(
+    State(state): State<AppState>,
     mut multipart: Multipart,
 ) -> Result<Response, (StatusCode, String)> {
-    let prompt = String::from("
-        Extract only the seat (saddle) from this motorcycle image.
-        Show the seat as an isolated part on a clean white background.
-        Remove the motorcycle body and all other components.
-    ");
+    info!("Received mask preview request");
 
-    let mut img = Bytes::new();
+    let mut img: Option<Bytes> = None;
+    let mut part = String::new();
+    let mut intensity = String::from("medium");
 
     while let Some(field) = multipart.next_field().await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))? 
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?
     {
         let name = field.name().unwrap_or("unknown").to_string();
 
-        if name == "image_motorcycle" {
-            img = field.bytes().await
-                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?;
-
-            info!("Extracted frame image: {} bytes", img.len());
+        match name.as_str() {
+            "image" => {
+                img = Some(field.bytes().await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?);
+            }
+            "part" => {
+                part = field.text().await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?;
+            }
+            "intensity" => {
+                intensity = field.text().await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?;
+            }
+            _ => {}
         }
     }
 
-    if img.is_empty() {
-        info!("No images received");
-        return Err((StatusCode::BAD_REQUEST, "No images provided".to_string()));
-    }
+    let img = require_image("image", img)?;
 
-    let gemini_client = GeminiClient::new();
+    let part_type: PartType = part.parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid part type: {}", e)))?;
+    let mask_intensity: MaskIntensity = intensity.parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid intensity: {}", e)))?;
 
-    match gemini_client.extract_image_nanobanana(prompt, img).await {
-        Ok(result_image) => {
-            info!("Successfully generated image: {} bytes", result_image.len());
-            
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "image/png")
-                .body(axum::body::Body::from(result_image))
-                .unwrap())
-        }
-        Err(e) => {
-            let error_msg = format!("Failed to generate image: {}", e);
-            info!("{}", error_msg);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, error_msg))
-        }
-    }
+    let base = image::load_from_memory(&img)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to decode image: {}", e)))?;
+
+    let temp_path = stage_upload(&state.config, &img, "mask_preview").await?;
+
+    let mask = MaskGenerator::generate_mask_from_image(
+        &temp_path.to_string_lossy(),
+        part_type,
+        mask_intensity,
+    );
+    let _ = tokio::fs::remove_file(&temp_path).await;
+
+    let mask = mask
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to generate mask: {}", e)))?;
+
+    let preview = MaskGenerator::overlay_preview(&base, &mask);
+
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(preview)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to encode preview: {}", e)))?;
+
+    info!("Generated mask preview: {} bytes", png.len());
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "image/png")
+        .body(Body::from(png))
+        .unwrap())
 }
 
-async fn extract_frame_image(
+// Paste a transparent part (e.g. from `/extract_*?transparent=true`) onto a frame photo, no
+// generation involved. `x`/`y` place the part's top-left corner in frame pixels and may be
+// negative or run off the edge; `scale` resizes the part first.
+async fn compose_handler(
+    Query(output): Query<OutputQuery>,
+    headers: HeaderMap,
     mut multipart: Multipart,
 ) -> Result<Response, (StatusCode, String)> {
-    let prompt = String::from("
-        Remove the exhaust pipe, muffler, and seat from the motorcycle. 
-        Show only the bare frame and engine where these parts were located. 
-        Keep the rest of the motorcycle intact and unchanged. Clean, realistic result.
-    ");
+    // Not a generated image, so the POST_* steps don't apply
+    let output_format = output.output_format(&headers, None)?;
 
-    let mut img = Bytes::new();
+    let mut frame: Option<Bytes> = None;
+    let mut part: Option<Bytes> = None;
+    let mut fields = HashMap::new();
 
     while let Some(field) = multipart.next_field().await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))? 
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?
     {
         let name = field.name().unwrap_or("unknown").to_string();
+        let data = field.bytes().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?;
 
-        if name == "image_motorcycle" {
-            img = field.bytes().await
-                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?;
-
-            info!("Extracted frame image: {} bytes", img.len());
+        match name.as_str() {
+            "frame" => frame = Some(data),
+            "part" => part = Some(data),
+            _ => {
+                fields.insert(name, String::from_utf8_lossy(&data).trim().to_string());
+            }
         }
     }
 
-    if img.is_empty() {
-        info!("No images received");
-        return Err((StatusCode::BAD_REQUEST, "No images provided".to_string()));
+    let number = |name: &str, default: f64| -> Result<f64, (StatusCode, String)> {
+        match fields.get(name).filter(|v| !v.is_empty()) {
+            None => Ok(default),
+            Some(v) => v.parse::<f64>().ok().filter(|n| n.is_finite()).ok_or_else(|| (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid value for '{}': expected a number, got '{}'", name, v),
+            )),
+        }
+    };
+    let x = number("x", 0.0)?.round() as i64;
+    let y = number("y", 0.0)?.round() as i64;
+    let scale = number("scale", 1.0)?;
+    if !(scale > 0.0 && scale <= 10.0) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid value for 'scale': must be in (0, 10], got {}", scale)));
     }
 
-    let gemini_client = GeminiClient::new();
+    let frame = image::load_from_memory(&require_image("frame", frame)?)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to decode frame: {}", e)))?;
+    let part = image::load_from_memory(&require_image("part", part)?)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to decode part: {}", e)))?;
+    if !part.color().has_alpha() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "part must have an alpha channel, e.g. a PNG from /extract_*?transparent=true".to_string(),
+        ));
+    }
 
-    match gemini_client.extract_image_nanobanana(prompt, img).await {
-        Ok(result_image) => {
-            info!("Successfully generated image: {} bytes", result_image.len());
-            
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "image/png")
-                .body(axum::body::Body::from(result_image))
-                .unwrap())
-        }
-        Err(e) => {
-            let error_msg = format!("Failed to generate image: {}", e);
-            info!("{}", error_msg);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, error_msg))
+    let part = if scale == 1.0 {
+        part.to_rgba8()
+    } else {
+        let width = ((part.width() as f64 * scale).round() as u32).max(1);
+        let height = ((part.height() as f64 * scale).round() as u32).max(1);
+        // Anything past the frame is clipped anyway; refuse before allocating it
+        if width > frame.width() || height > frame.height() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Invalid value for 'scale': the scaled part ({}x{}) would be larger than the frame ({}x{})",
+                    width, height, frame.width(), frame.height(),
+                ),
+            ));
         }
+        part.resize_exact(width, height, image::imageops::FilterType::Lanczos3).to_rgba8()
+    };
+
+    let mut canvas = frame.to_rgba8();
+    image::imageops::overlay(&mut canvas, &part, x, y);
+
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(canvas)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to encode composite: {}", e)))?;
+    encoded_image_response(&png, output_format)
+}
+
+// Valid form values for `part` and `intensity`, generated from the enums
+async fn meta_options() -> Json<serde_json::Value> {
+    let parts: Vec<&str> = PartType::all().iter().map(|p| p.as_str()).collect();
+    let intensities: Vec<&str> = MaskIntensity::all().iter().map(|i| i.as_str()).collect();
+    let art_styles: Vec<&str> = ArtStyle::all().iter().map(|s| s.as_str()).collect();
+
+    Json(json!({
+        "parts": parts,
+        "intensities": intensities,
+        "art_styles": art_styles,
+    }))
+}
+
+async fn version_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("ZEPHYR_GIT_HASH"),
+        "build_timestamp": env!("ZEPHYR_BUILD_TIMESTAMP").parse::<u64>().unwrap_or(0),
+        "image_provider": state.config.image_provider.as_str(),
+    }))
+}
+
+// Outcome of one provider's self-test call
+#[derive(Debug, Serialize)]
+struct ProviderCheck {
+    ok: bool,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn check_provider(call: impl Future<Output = Result<(), String>>) -> ProviderCheck {
+    let started = Instant::now();
+    let result = call.await;
+    ProviderCheck {
+        ok: result.is_ok(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: result.err(),
     }
 }
 
-pub async fn create_3d_handler(
-    State(state): State<AppState>,
-    mut multipart: Multipart,
-) -> Result<Json<TaskCreatedResponse>, StatusCode> {
-    info!("Received 3D creation request");
-    
-    let mut images: Vec<Bytes> = Vec::new();
-    
-    // multipart에서 이미지 추출
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap_or("unknown").to_string();
-        info!("Processing field: {}", name);
-        
-        if name.starts_with("image") || name == "file" {
-            let data = field.bytes().await
-                .map_err(|_| StatusCode::BAD_REQUEST)?;
-            info!("Received image field '{}': {} bytes", name, data.len());
-            images.push(data);
-        }
+// Deployment smoke test: one minimal call per provider, run concurrently.
+// Nothing billable is started: Meshy is only asked to list tasks, and Bedrock gets the
+// empty-body invoke that `/ready` uses, which is authorized but never runs the model.
+async fn selftest_handler(State(state): State<AppState>) -> Response {
+    let (gemini, meshy, bedrock) = tokio::join!(
+        check_provider(async { state.gemini.probe().await.map_err(|e| e.to_string()) }),
+        check_provider(async { state.meshy_client.probe().await.map_err(|e| e.to_string()) }),
+        check_provider(state.customizer.generator().model_access()),
+    );
+
+    let ok = gemini.ok && meshy.ok && bedrock.ok;
+    for (provider, check) in [("gemini", &gemini), ("meshy", &meshy), ("bedrock", &bedrock)] {
+        info!(provider, ok = check.ok, latency_ms = check.latency_ms, "selftest");
     }
-    
-    if images.is_empty() {
-        info!("No images received");
-        return Err(StatusCode::BAD_REQUEST);
+
+    let status = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(json!({
+        "ok": ok,
+        "providers": { "gemini": gemini, "meshy": meshy, "bedrock": bedrock },
+    }))).into_response()
+}
+
+// Readiness probe: config loading already proved the keys are set, so this checks what
+// it can't, that the Bedrock model is actually invokable (access is granted per model)
+async fn ready_handler(State(state): State<AppState>) -> Response {
+    let generator = state.customizer.generator();
+    let access = generator.model_access().await;
+    if let Err(e) = &access {
+        warn!("Not ready: {}", e);
     }
-    
-    match state.meshy_client.create_3d_task(images).await {
-        Ok(task_id) => Ok(Json(TaskCreatedResponse { task_id })),
-        Err(e) => {
-            error!("Failed to create 3D task: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+
+    let status = if access.is_ok() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(json!({
+        "ready": access.is_ok(),
+        "bedrock": {
+            "model_id": generator.model_id(),
+            "invokable": access.is_ok(),
+            "error": access.err(),
+        },
+    }))).into_response()
+}
+
+// Idempotency keys and async job results are kept in memory; sweep out the expired ones every
+// REGISTRY_CLEANUP_INTERVAL_SECS so they don't grow for the life of the process.
+// Each sweep holds a map's lock only for a single pass over it.
+fn spawn_registry_cleanup(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut sweeps = tokio::time::interval(state.config.registry_cleanup_interval);
+        sweeps.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        sweeps.tick().await;
+
+        loop {
+            sweeps.tick().await;
+            let keys = state.idempotency.evict_expired();
+            let jobs = state.jobs.evict_finished(state.config.job_result_ttl).await;
+            if keys + jobs > 0 {
+                info!("Registry cleanup reaped {} idempotency keys and {} job results", keys, jobs);
+            }
+        }
+    })
+}
+
+// WARMUP=1: one cheap authenticated call per provider right after boot, so the first
+// real request doesn't pay for TLS handshakes and AWS credential resolution.
+// Failures are only logged; the provider may well be fine by the time traffic arrives.
+async fn warm_up(state: &AppState) -> Vec<(&'static str, ProviderCheck)> {
+    let (gemini, meshy, bedrock) = tokio::join!(
+        check_provider(async { state.gemini.probe().await.map_err(|e| e.to_string()) }),
+        check_provider(async { state.meshy_client.probe().await.map_err(|e| e.to_string()) }),
+        check_provider(async { state.customizer.generator().warm_up().await.map_err(|e| e.to_string()) }),
+    );
+
+    let checks = vec![("gemini", gemini), ("meshy", meshy), ("bedrock", bedrock)];
+    for (provider, check) in &checks {
+        match &check.error {
+            None => info!(provider, latency_ms = check.latency_ms, "warmup done"),
+            Some(error) => warn!(provider, latency_ms = check.latency_ms, "warmup failed: {}", error),
         }
     }
+    checks
 }
 
-async fn handler(mut multipart: Multipart) -> Json<serde_json::Value> {
-    let response = json!({
-        "message": "Hello, World!"
-    });
+// Write an uploaded image to TEMP_DIR for the path-based mask/inpaint pipeline
+async fn stage_upload(config: &Config, img: &Bytes, prefix: &str) -> Result<std::path::PathBuf, (StatusCode, String)> {
+    // image::open picks the decoder from the extension, so keep it accurate
+    let format = image::guess_format(img)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Unsupported image format: {}", e)))?;
+    let extension = format.extensions_str().first().copied().unwrap_or("png");
 
-    Json(response)
+    let temp_dir = &config.temp_dir;
+    tokio::fs::create_dir_all(temp_dir).await
+        .map_err(|e| dir_write_error("temp", temp_dir, format!("Failed to create temp dir {}", temp_dir.display()), e))?;
+    let temp_path = unique_path_in(temp_dir, prefix, extension);
+    tokio::fs::write(&temp_path, img).await
+        .map_err(|e| dir_write_error("temp", temp_dir, "Failed to stage image".to_string(), e))?;
+
+    Ok(temp_path)
 }
 
-// WebSocket 핸들러
-pub async fn ws_handler(
-    ws: WebSocketUpgrade,
-    Path(task_id): Path<String>,
+// `?include_mask=true` returns the mask used alongside the image, for debugging bad results
+#[derive(Debug, Default, Deserialize)]
+pub struct MaskQuery {
+    #[serde(default)]
+    include_mask: bool,
+}
+
+pub async fn customize_handler(
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, task_id, state))
+    Query(output): Query<OutputQuery>,
+    Query(mask_query): Query<MaskQuery>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let output_format = output.output_format(&headers, state.post_process.format())?;
+    info!("Received customization request");
+
+    let form = CustomizeForm::read(&mut multipart, &state.parts).await?;
+    let seed = generation_seed(form.seed);
+    if let (CustomizeBackend::Gemini, Part::Builtin(part)) = (state.config.customize_backend, &form.part) {
+        let result = customize_with_gemini(&state, &form, *part).await?;
+        return customized_response(&state, result, seed, mask_query.include_mask, output_format);
+    }
+
+    let temp_path = stage_upload(&state.config, &form.image, "customize_base").await?;
+    let result = state.breakers.bedrock.call(
+        state.customizer.visualize_custom_part_with_mask(
+            &temp_path.to_string_lossy(),
+            &form.part,
+            &form.bike_desc,
+            &form.part_desc,
+            form.intensity,
+            Some(seed),
+        ),
+        is_bedrock_outage,
+    ).await;
+    let _ = tokio::fs::remove_file(&temp_path).await;
+
+    customized_response(&state, result?, seed, mask_query.include_mask, output_format)
 }
 
-async fn handle_socket(
-    mut socket: WebSocket, 
-    task_id: String, 
-    state: AppState,
-) {
-    info!("WebSocket connected - task: {}", task_id);
-    
-    loop {
-        match state.meshy_client.get_task_status(&task_id).await {
-            Ok(status) => {
-                let status_json = match serde_json::to_string(&status) {
-                    Ok(json) => json,
-                    Err(e) => {
-                        error!("Failed to serialize status: {}", e);
-                        break;
-                    }
-                };
-                
-                info!("Sending status update: {} - progress: {}", 
-                    status.status, 
-                    status.progress.unwrap_or(0)
-                );
-                
-                if socket.send(Message::Text(status_json.into())).await.is_err() {
-                    info!("Client disconnected");
-                    break;
-                }
-                
-                // Check if task completed
-                if status.status == "SUCCEEDED" || status.status == "FAILED" {
-                    info!("Task {} finished with status: {}", task_id, status.status);
-                    let _ = socket.close().await;
-                    break;
-                }
-                
-                // Poll every 5 seconds
-                sleep(Duration::from_secs(5)).await;
+// CUSTOMIZE_BACKEND=gemini for a built-in part: Gemini edits the photo and the part's mask
+// blends its output back over the original. Gemini takes no seed, so the reported one is
+// only meaningful for Bedrock; manifest parts always go to Bedrock.
+async fn customize_with_gemini(
+    state: &AppState,
+    form: &CustomizeForm,
+    part: PartType,
+) -> Result<anyhow::Result<(Vec<u8>, Vec<u8>)>, CircuitOpen> {
+    let customizer = MotorcycleCustomizer::with_generator(GeminiBackend::new(state.gemini.clone()));
+    let descriptions = PartDescriptions { bike: &form.bike_desc, part: &form.part_desc };
+
+    state.breakers.gemini.call(
+        async {
+            let image = customizer.customize(&form.image, part, descriptions, form.intensity).await?;
+            let (width, height) = image_dimensions(&form.image, "image")
+                .map_err(|(_, message)| anyhow::anyhow!(message))?;
+            let mask = MotorcycleCustomizer::encode_mask(&form.part.mask(width, height, form.intensity)?)?;
+            Ok((image, mask))
+        },
+        |e: &anyhow::Error| e.downcast_ref::<GeminiError>().is_some_and(is_gemini_outage),
+    ).await
+}
+
+// Same form as /customize, but with a JSON `parts` field of `{ "part", "part_desc" }`
+// entries that are all inpainted into one image
+pub async fn customize_multi_handler(
+    State(state): State<AppState>,
+    Query(output): Query<OutputQuery>,
+    Query(mask_query): Query<MaskQuery>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let output_format = output.output_format(&headers, state.post_process.format())?;
+    info!("Received multi-part customization request");
+
+    let form = CustomizeMultiForm::read(&mut multipart, &state.parts).await?;
+    let keys: Vec<&str> = form.parts.iter().map(|(part, _)| part.key()).collect();
+    info!("Customizing {} parts together: {}", keys.len(), keys.join(", "));
+
+    let seed = generation_seed(form.seed);
+    let result = state.breakers.bedrock.call(
+        state.customizer.visualize_parts_with_mask(
+            &form.image,
+            &form.parts,
+            &form.bike_desc,
+            form.intensity,
+            Some(seed),
+        ),
+        is_bedrock_outage,
+    ).await?;
+
+    customized_response(&state, result, seed, mask_query.include_mask, output_format)
+}
+
+// The generated image, or with `include_mask` a JSON body carrying the mask too
+fn customized_response(
+    state: &AppState,
+    result: anyhow::Result<(Vec<u8>, Vec<u8>)>,
+    seed: u32,
+    include_mask: bool,
+    output_format: OutputFormat,
+) -> Result<Response, ApiError> {
+    match result {
+        Ok((result_image, mask)) if include_mask => {
+            info!("Successfully customized image: {} bytes (with mask)", result_image.len());
+            let result_image = post_processed(state, &result_image)?;
+            let encoded = encode_as(&result_image, output_format)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to encode output image: {}", e)))?;
+            let response = Json(json!({
+                "image": general_purpose::STANDARD.encode(encoded),
+                "content_type": output_format.content_type(),
+                "mask": general_purpose::STANDARD.encode(mask),
+                "seed": seed,
+            })).into_response();
+            Ok(with_seed_header(response, seed))
+        }
+        Ok((result_image, _)) => {
+            info!("Successfully customized image: {} bytes", result_image.len());
+            let response = encoded_image_response(&post_processed(state, &result_image)?, output_format)?;
+            Ok(with_seed_header(response, seed))
+        }
+        Err(e) => {
+            let error_msg = format!("Failed to customize image: {}", e);
+            error!("{}", error_msg);
+            Err(ApiError::Message(StatusCode::INTERNAL_SERVER_ERROR, error_msg))
+        }
+    }
+}
+
+// Generate every intensity variant at once so the user can pick one
+pub async fn customize_options_handler(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    info!("Received customization options request");
+
+    let (options, seed) = generate_customize_options(&state, &mut multipart, "customize_options").await?;
+    let options = options
+        .into_iter()
+        .map(|(intensity, image)| Ok(json!({
+            "intensity": intensity.as_str(),
+            "image": general_purpose::STANDARD.encode(post_processed(&state, &image)?),
+            "seed": seed,
+        })))
+        .collect::<Result<Vec<_>, (StatusCode, String)>>()?;
+
+    info!("Generated {} customization options", options.len());
+    Ok(with_seed_header(Json(serde_json::Value::Array(options)).into_response(), seed))
+}
+
+// Parameters of /customize/options/ws, sent as the first text message since a WebSocket
+// has no form body; `image` is base64
+#[derive(Debug, Deserialize)]
+struct CustomizeOptionsRequest {
+    image: String,
+    part: String,
+    #[serde(default)]
+    bike_desc: String,
+    #[serde(default)]
+    part_desc: String,
+    seed: Option<u32>,
+}
+
+impl CustomizeOptionsRequest {
+    // Same checks as `CustomizeForm::read`, reporting every bad field at once.
+    // The image skips the HTTP body limit, so it is held to `max_bytes` here.
+    fn validate(self, parts: &PartsManifest, max_bytes: usize) -> Result<(Bytes, Part, Self), ApiError> {
+        let mut errors = Vec::new();
+        let image = match general_purpose::STANDARD.decode(self.image.trim()) {
+            Ok(data) if data.len() > max_bytes => return Err(upload_too_large_error().into()),
+            Ok(data) if data.is_empty() => {
+                errors.push(FieldError { field: "image", message: empty_field_message("image") });
+                None
             }
+            Ok(data) => Some(Bytes::from(data)),
             Err(e) => {
-                error!("Failed to get task status: {}", e);
-                let error_msg = json!({
-                    "error": "Failed to get status",
-                    "details": e.to_string()
-                }).to_string();
-                
-                if socket.send(Message::Text(error_msg.into())).await.is_err() {
-                    break;
-                }
-                break;
+                errors.push(FieldError { field: "image", message: format!("expected base64: {}", e) });
+                None
+            }
+        };
+        let part = parts.resolve(&self.part)
+            .map_err(|message| errors.push(FieldError { field: "part", message }));
+
+        match (image, part) {
+            (Some(image), Ok(part)) if errors.is_empty() => {
+                let image = require_image("image", Some(image))?;
+                Ok((image, part, self))
             }
+            _ => Err(ApiError::Fields(errors)),
         }
     }
-    
-    info!("WebSocket closed for task: {}", task_id);
 }
 
-// Router configuration with proper state management
-pub fn create_router(meshy_client: Arc<MeshyClient>) -> Router {
-    let state = AppState {
-        meshy_client,
+// Message size allowed for the /customize/options/ws parameters besides the image itself
+const OPTIONS_PARAMS_ALLOWANCE: usize = 64 * 1024;
+
+// /customize/options over a WebSocket: every intensity starts at once and each result is sent
+// as its own `{ intensity, image, seed }` (or `{ intensity, error, seed }`) message as soon as it
+// finishes, then a `{ status: "done" }` summary before the socket closes
+pub async fn customize_options_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    // Room for the base64 image (4 bytes per 3) next to the other parameters
+    let max_message = state.config.max_upload_bytes.div_ceil(3) * 4 + OPTIONS_PARAMS_ALLOWANCE;
+    ws.max_message_size(max_message)
+        .on_upgrade(move |socket| stream_customize_options(socket, state))
+}
+
+async fn stream_customize_options(mut socket: WebSocket, state: AppState) {
+    info!("Customization options WebSocket connected");
+
+    let Some(request) = read_options_request(&mut socket).await else {
+        info!("Client left before sending customization parameters");
+        return;
     };
-    
-    Router::new()
-        .route("/api/3d/create", post(create_3d_handler))
-        .route("/api/3d/ws/{task_id}", get(ws_handler))
-        .route("/api/3d/model/{task_id}", get(proxy_model_handler))  // 새 라우트
-        .with_state(state)
+    let staged = async {
+        let (image, part, request) = request?.validate(&state.parts, state.config.max_upload_bytes)?;
+        let temp_path = stage_upload(&state.config, &image, "customize_options_ws").await?;
+        Ok::<_, ApiError>((temp_path, part, request))
+    }.await;
+    let (temp_path, part, request) = match staged {
+        Ok(staged) => staged,
+        Err(e) => {
+            let budget = state.config.ws_send_timeout;
+            if send_within(&mut socket, Message::Text(ws_error_message(e).to_string().into()), budget).await {
+                let _ = tokio::time::timeout(budget, socket.close()).await;
+            }
+            return;
+        }
+    };
+
+    let path = temp_path.to_string_lossy();
+    let seed = generation_seed(request.seed);
+    let mut generations: stream::FuturesUnordered<_> = MaskIntensity::all().iter().map(|&intensity| {
+        let (state, path, part, request) = (&state, &path, &part, &request);
+        async move {
+            let result = state.breakers.bedrock.call(
+                state.customizer.visualize_custom_part(
+                    path,
+                    part,
+                    &request.bike_desc,
+                    &request.part_desc,
+                    intensity,
+                    Some(seed),
+                ),
+                is_bedrock_outage,
+            ).await;
+            (intensity, result.map_err(anyhow::Error::from).and_then(|result| result))
+        }
+    }).collect();
+
+    let (mut succeeded, mut failed) = (0, 0);
+    let mut connected = true;
+    while let Some((intensity, result)) = generations.next().await {
+        let message = match result.and_then(|image| {
+            post_processed(&state, &image)
+                .map(|image| image.into_owned())
+                .map_err(|(_, message)| anyhow::anyhow!(message))
+        }) {
+            Ok(image) => {
+                succeeded += 1;
+                json!({ "intensity": intensity.as_str(), "image": general_purpose::STANDARD.encode(image), "seed": seed })
+            }
+            Err(e) => {
+                failed += 1;
+                warn!("Failed with {:?} intensity: {}", intensity, e);
+                json!({ "intensity": intensity.as_str(), "error": e.to_string(), "seed": seed })
+            }
+        };
+        // Client went away or stopped reading; dropping the rest cancels them
+        if !send_within(&mut socket, Message::Text(message.to_string().into()), state.config.ws_send_timeout).await {
+            connected = false;
+            break;
+        }
+    }
+    drop(generations);
+    let _ = tokio::fs::remove_file(&temp_path).await;
+
+    if connected {
+        let done = json!({ "status": "done", "succeeded": succeeded, "failed": failed, "seed": seed });
+        let budget = state.config.ws_send_timeout;
+        if send_within(&mut socket, Message::Text(done.to_string().into()), budget).await {
+            let _ = tokio::time::timeout(budget, socket.close()).await;
+        }
+    }
+    info!("Streamed {} customization options ({} failed)", succeeded, failed);
 }
 
-pub async fn proxy_model_handler(
-    Path(task_id): Path<String>,
+// The first text message, parsed; `None` when the client closes before sending one
+async fn read_options_request(socket: &mut WebSocket) -> Option<Result<CustomizeOptionsRequest, ApiError>> {
+    loop {
+        match socket.recv().await? {
+            Ok(Message::Text(text)) => {
+                return Some(serde_json::from_str(&text).map_err(|e| {
+                    ApiError::Message(StatusCode::BAD_REQUEST, format!("Invalid customization parameters: {}", e))
+                }));
+            }
+            Ok(Message::Binary(_)) => {
+                return Some(Err(ApiError::Message(
+                    StatusCode::BAD_REQUEST,
+                    "Expected the customization parameters as a JSON text message".to_string(),
+                )));
+            }
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+// An `ApiError` as a WebSocket message, shaped like the HTTP error bodies
+fn ws_error_message(error: ApiError) -> serde_json::Value {
+    match error {
+        ApiError::Message(_, message) => json!({ "error": message }),
+        ApiError::Fields(errors) => json!({ "errors": errors }),
+        ApiError::RateLimited(limit) => json!({ "error": "rate_limited", "provider": limit.provider, "message": limit.message }),
+        ApiError::CircuitOpen(open) => json!({ "error": open.to_string() }),
+    }
+}
+
+// Same form as /customize/options, answered with one side-by-side PNG of the intensities
+pub async fn customize_sheet_handler(
     State(state): State<AppState>,
-) -> Result<Response, StatusCode> {
-    info!("Proxying 3D model for task: {}", task_id);
-    
-    match state.meshy_client.get_task_status(&task_id).await {
-        Ok(status) => {
-            if let Some(model_url) = status.model_url {
-                info!("Fetching model from: {}", model_url);
-                
-                let client = Client::new();
-                match client.get(&model_url).send().await {
-                    Ok(response) => {
-                        if response.status().is_success() {
-                            match response.bytes().await {
-                                Ok(bytes) => {
-                                    info!("Successfully fetched model: {} bytes", bytes.len());
-                                    
-                                    Ok(Response::builder()
-                                        .status(StatusCode::OK)
-                                        .header(header::CONTENT_TYPE, "application/octet-stream")
-                                        .header(
-                                            header::CONTENT_DISPOSITION,
-                                            format!("attachment; filename=\"motorcycle-3d-{}.glb\"", task_id)
-                                        )
-                                        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-                                        .body(Body::from(bytes))
-                                        .unwrap())
-                                }
-                                Err(e) => {
-                                    error!("Failed to read model bytes: {}", e);
-                                    Err(StatusCode::INTERNAL_SERVER_ERROR)
-                                }
-                            }
-                        } else {
-                            error!("Failed to fetch model: {}", response.status());
-                            Err(StatusCode::BAD_GATEWAY)
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to download model: {}", e);
-                        Err(StatusCode::BAD_GATEWAY)
-                    }
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    info!("Received customization contact sheet request");
+
+    let (options, seed) = generate_customize_options(&state, &mut multipart, "customize_sheet").await?;
+    let sheet = MotorcycleCustomizer::options_sheet(&options).map_err(|e| {
+        let error_msg = format!("Failed to build contact sheet: {}", e);
+        error!("{}", error_msg);
+        ApiError::Message(StatusCode::INTERNAL_SERVER_ERROR, error_msg)
+    })?;
+
+    info!("Built contact sheet from {} customization options", options.len());
+    let response = encoded_image_response(&post_processed(&state, &sheet)?, OutputFormat::Png)?;
+    Ok(with_seed_header(response, seed))
+}
+
+// Same form as /customize, answered with the prompts it would send without calling Bedrock
+pub async fn customize_prompt_handler(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    info!("Received customization prompt preview request");
+
+    let form = CustomizeForm::read(&mut multipart, &state.parts).await?;
+    let (prompt, negative_prompt) = MotorcycleCustomizer::part_prompt(&form.part, &form.bike_desc, &form.part_desc);
+
+    Ok(Json(json!({
+        "prompt": prompt,
+        "negative_prompt": negative_prompt,
+    })))
+}
+
+async fn generate_customize_options(
+    state: &AppState,
+    multipart: &mut Multipart,
+    prefix: &str,
+) -> Result<(Vec<(MaskIntensity, Vec<u8>)>, u32), ApiError> {
+    let form = CustomizeForm::read(multipart, &state.parts).await?;
+    let temp_path = stage_upload(&state.config, &form.image, prefix).await?;
+
+    let seed = generation_seed(form.seed);
+    let result = state.breakers.bedrock.call(
+        state.customizer.generate_options(
+            &temp_path.to_string_lossy(),
+            &form.part,
+            &form.bike_desc,
+            &form.part_desc,
+            Some(seed),
+        ),
+        is_bedrock_outage,
+    ).await;
+    let _ = tokio::fs::remove_file(&temp_path).await;
+
+    let options = result?.map_err(|e| {
+        let error_msg = format!("Failed to generate options: {}", e);
+        error!("{}", error_msg);
+        ApiError::Message(StatusCode::INTERNAL_SERVER_ERROR, error_msg)
+    })?;
+    Ok((options, seed))
+}
+
+// One bad form field, reported as `{ "field": ..., "message": ... }`
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    field: &'static str,
+    message: String,
+}
+
+// Handler error that is either a plain message or every invalid form field at once
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    Message(StatusCode, String),
+    Fields(Vec<FieldError>),
+    // Passed through as a 429 with the provider's Retry-After
+    RateLimited(RateLimited),
+    // A 503 with Retry-After, without calling the provider
+    CircuitOpen(CircuitOpen),
+}
+
+impl From<CircuitOpen> for ApiError {
+    fn from(open: CircuitOpen) -> Self {
+        ApiError::CircuitOpen(open)
+    }
+}
+
+impl From<(StatusCode, String)> for ApiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        ApiError::Message(status, message)
+    }
+}
+
+// A flagged upload is a 422 like a provider safety block; a fail-closed outage is a 503
+impl From<ModerationError> for ApiError {
+    fn from(e: ModerationError) -> Self {
+        let status = match &e {
+            ModerationError::Rejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ModerationError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        ApiError::Message(status, e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::Message(status, message) => (status, message).into_response(),
+            ApiError::Fields(errors) => {
+                (StatusCode::BAD_REQUEST, Json(json!({ "errors": errors }))).into_response()
+            }
+            ApiError::RateLimited(limit) => {
+                let body = Json(json!({
+                    "error": "rate_limited",
+                    "provider": limit.provider,
+                    "message": format!("The {} quota was hit, try again later: {}", limit.provider, limit.message),
+                    "retry_after": limit.retry_after,
+                }));
+                let mut response = (StatusCode::TOO_MANY_REQUESTS, body).into_response();
+                if let Some(seconds) = limit.retry_after {
+                    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
                 }
-            } else {
-                error!("No model URL available for task: {}", task_id);
-                Err(StatusCode::NOT_FOUND)
+                response
+            }
+            ApiError::CircuitOpen(open) => {
+                let mut response = (StatusCode::SERVICE_UNAVAILABLE, open.to_string()).into_response();
+                response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(open.retry_after_secs()));
+                response
+            }
+        }
+    }
+}
+
+// Fields shared by the part-based customize endpoints
+struct CustomizeForm {
+    image: Bytes,
+    part: Part,
+    intensity: MaskIntensity,
+    bike_desc: String,
+    part_desc: String,
+    seed: Option<u32>,
+}
+
+impl CustomizeForm {
+    // Validates every field before failing so clients can fix them all in one go
+    // `part` is a built-in part or a key from the parts manifest
+    async fn read(multipart: &mut Multipart, parts: &PartsManifest) -> Result<Self, ApiError> {
+        let mut img: Option<Bytes> = None;
+        let mut part = String::new();
+        let mut intensity = String::from("medium");
+        let mut bike_desc = String::new();
+        let mut part_desc = String::new();
+        let mut seed: Option<u32> = None;
+        let mut errors = Vec::new();
+
+        while let Some(field) = multipart.next_field().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?
+        {
+            let name = field.name().unwrap_or("unknown").to_string();
+
+            if name == "image" {
+                img = Some(field.bytes().await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?);
+                continue;
+            }
+
+            let value = field.text().await
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?;
+
+            match name.as_str() {
+                "part" => part = value,
+                "intensity" => intensity = value,
+                "bike_desc" => bike_desc = value,
+                "part_desc" => part_desc = value,
+                "seed" if !value.trim().is_empty() => match value.trim().parse::<u32>() {
+                    Ok(value) => seed = Some(value),
+                    Err(_) => errors.push(FieldError {
+                        field: "seed",
+                        message: format!("expected a non-negative integer, got '{}'", value),
+                    }),
+                },
+                _ => {}
+            }
+        }
+
+        match &img {
+            None => errors.push(FieldError { field: "image", message: missing_field_message("image") }),
+            Some(data) if data.is_empty() => {
+                errors.push(FieldError { field: "image", message: empty_field_message("image") });
+            }
+            Some(_) => {}
+        }
+        let part = parts.resolve(&part)
+            .map_err(|message| errors.push(FieldError { field: "part", message }));
+        let intensity = intensity.parse::<MaskIntensity>()
+            .map_err(|e| errors.push(FieldError { field: "intensity", message: e.to_string() }));
+
+        match (img, part, intensity) {
+            (Some(img), Ok(part), Ok(intensity)) if errors.is_empty() => {
+                let image = require_image("image", Some(img))?;
+                Ok(Self { image, part, intensity, bike_desc, part_desc, seed })
+            }
+            _ => Err(ApiError::Fields(errors)),
+        }
+    }
+}
+
+// One entry of the /customize/multi `parts` field
+#[derive(Debug, Deserialize)]
+struct MultiPartEntry {
+    part: String,
+    #[serde(default)]
+    part_desc: String,
+}
+
+// Fields of /customize/multi, validated the same way as `CustomizeForm`
+struct CustomizeMultiForm {
+    image: Bytes,
+    parts: Vec<(Part, String)>,
+    intensity: MaskIntensity,
+    bike_desc: String,
+    seed: Option<u32>,
+}
+
+impl CustomizeMultiForm {
+    async fn read(multipart: &mut Multipart, manifest: &PartsManifest) -> Result<Self, ApiError> {
+        let mut img: Option<Bytes> = None;
+        let mut entries: Option<String> = None;
+        let mut intensity = String::from("medium");
+        let mut bike_desc = String::new();
+        let mut seed: Option<u32> = None;
+        let mut errors = Vec::new();
+
+        while let Some(field) = multipart.next_field().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?
+        {
+            let name = field.name().unwrap_or("unknown").to_string();
+
+            if name == "image" {
+                img = Some(field.bytes().await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?);
+                continue;
+            }
+
+            let value = field.text().await
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?;
+
+            match name.as_str() {
+                "parts" => entries = Some(value),
+                "intensity" => intensity = value,
+                "bike_desc" => bike_desc = value,
+                "seed" if !value.trim().is_empty() => match value.trim().parse::<u32>() {
+                    Ok(value) => seed = Some(value),
+                    Err(_) => errors.push(FieldError {
+                        field: "seed",
+                        message: format!("expected a non-negative integer, got '{}'", value),
+                    }),
+                },
+                _ => {}
+            }
+        }
+
+        match &img {
+            None => errors.push(FieldError { field: "image", message: missing_field_message("image") }),
+            Some(data) if data.is_empty() => {
+                errors.push(FieldError { field: "image", message: empty_field_message("image") });
+            }
+            Some(_) => {}
+        }
+        let parts = Self::resolve_parts(entries.as_deref(), manifest)
+            .map_err(|message| errors.push(FieldError { field: "parts", message }));
+        let intensity = intensity.parse::<MaskIntensity>()
+            .map_err(|e| errors.push(FieldError { field: "intensity", message: e.to_string() }));
+
+        match (img, parts, intensity) {
+            (Some(img), Ok(parts), Ok(intensity)) if errors.is_empty() => {
+                let image = require_image("image", Some(img))?;
+                Ok(Self { image, parts, intensity, bike_desc, seed })
+            }
+            _ => Err(ApiError::Fields(errors)),
+        }
+    }
+
+    // At least one entry, each a known part listed only once
+    fn resolve_parts(entries: Option<&str>, manifest: &PartsManifest) -> Result<Vec<(Part, String)>, String> {
+        let entries = entries.ok_or_else(|| missing_field_message("parts"))?;
+        let entries: Vec<MultiPartEntry> = serde_json::from_str(entries)
+            .map_err(|e| format!("expected a JSON array of {{\"part\", \"part_desc\"}} entries: {}", e))?;
+        if entries.is_empty() {
+            return Err("at least one part is required".to_string());
+        }
+
+        let mut parts: Vec<(Part, String)> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let part = manifest.resolve(&entry.part)?;
+            if parts.iter().any(|(seen, _)| seen.key() == part.key()) {
+                return Err(format!("part '{}' is listed more than once", part.key()));
+            }
+            parts.push((part, entry.part_desc));
+        }
+        Ok(parts)
+    }
+}
+
+// Customize using a hand-drawn mask uploaded alongside the base image
+pub async fn customize_with_mask_handler(
+    State(state): State<AppState>,
+    Query(output): Query<OutputQuery>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let output_format = output.output_format(&headers, state.post_process.format())?;
+    info!("Received customization request with custom mask");
+
+    let mut img: Option<Bytes> = None;
+    let mut mask: Option<Bytes> = None;
+    let mut part = String::from("part");
+    let mut bike_desc = String::new();
+    let mut part_desc = String::new();
+    let mut seed: Option<u32> = None;
+
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?
+    {
+        let name = field.name().unwrap_or("unknown").to_string();
+
+        if name == "image" || name == "mask" {
+            let data = field.bytes().await
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read bytes: {}", e)))?;
+            if name == "image" { img = Some(data) } else { mask = Some(data) }
+            continue;
+        }
+
+        let value = field.text().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?;
+
+        match name.as_str() {
+            "part" if !value.trim().is_empty() => part = value,
+            "bike_desc" => bike_desc = value,
+            "part_desc" => part_desc = value,
+            "seed" if !value.trim().is_empty() => {
+                seed = Some(value.trim().parse::<u32>()
+                    .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid seed: '{}'", value)))?);
             }
+            _ => {}
+        }
+    }
+
+    let img = require_image("image", img)?;
+    let mask = require_image("mask", mask)?;
+
+    let (img_w, img_h) = image_dimensions(&img, "image")?;
+    let (mask_w, mask_h) = image_dimensions(&mask, "mask")?;
+    if (img_w, img_h) != (mask_w, mask_h) {
+        return Err(ApiError::Message(
+            StatusCode::BAD_REQUEST,
+            format!("Mask is {}x{} but image is {}x{}; dimensions must match", mask_w, mask_h, img_w, img_h),
+        ));
+    }
+
+    let seed = generation_seed(seed);
+    let result = state.breakers.bedrock.call(
+        state.customizer.visualize_customization(&img, &mask, &bike_desc, &part, &part_desc, Some(seed)),
+        is_bedrock_outage,
+    ).await?;
+
+    match result {
+        Ok(result_image) => {
+            info!("Successfully customized image: {} bytes", result_image.len());
+            let response = encoded_image_response(&post_processed(&state, &result_image)?, output_format)?;
+            Ok(with_seed_header(response, seed))
         }
         Err(e) => {
-            error!("Failed to get task status: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            let error_msg = format!("Failed to customize image: {}", e);
+            error!("{}", error_msg);
+            Err(ApiError::Message(StatusCode::INTERNAL_SERVER_ERROR, error_msg))
+        }
+    }
+}
+
+// `?format=png|jpeg|webp&quality=1-100` on endpoints that return a generated image.
+// Without `format` the Accept header decides.
+#[derive(Debug, Default, Deserialize)]
+pub struct OutputQuery {
+    format: Option<String>,
+    quality: Option<u8>,
+}
+
+impl OutputQuery {
+    // A POST_FORMAT configured on the server takes the place of the Accept header
+    fn output_format(&self, headers: &HeaderMap, configured: Option<OutputFormat>) -> Result<OutputFormat, (StatusCode, String)> {
+        let format = match (&self.format, configured) {
+            (Some(name), _) => name.parse::<OutputFormat>()
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid format: {}", e)))?,
+            (None, Some(format)) => format,
+            (None, None) => headers
+                .get(header::ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .map(negotiate)
+                .unwrap_or(OutputFormat::Png),
+        };
+
+        Ok(match self.quality {
+            Some(quality) => format.with_quality(quality),
+            None => format,
+        })
+    }
+}
+
+// What the Bedrock fallback does in place of a Gemini edit prompt
+#[derive(Debug, Clone, Copy)]
+enum BedrockFallback {
+    Isolate(PartType),
+    Remove(&'static [PartType]),
+    Install(PartType),
+}
+
+// Names the provider that produced the returned image
+const IMAGE_PROVIDER_HEADER: &str = "x-image-provider";
+
+// The seed a Bedrock generation ran with, so a result can be reproduced later
+const GENERATION_SEED_HEADER: &str = "x-generation-seed";
+
+// The caller's seed, or a random one for variety; either way it is reported back
+fn generation_seed(requested: Option<u32>) -> u32 {
+    requested.unwrap_or_else(|| {
+        let seed = fastrand::u32(..);
+        info!("No seed given, using random seed {}", seed);
+        seed
+    })
+}
+
+fn with_seed_header(mut response: Response, seed: u32) -> Response {
+    response.headers_mut().insert(GENERATION_SEED_HEADER, HeaderValue::from(seed));
+    response
+}
+
+// Run a Gemini edit; when Gemini is unavailable and BEDROCK_FALLBACK is on, redo it on
+// Bedrock as a mask + prompt over `base` instead of failing. Returns the image and its provider.
+async fn generate_with_fallback(
+    state: &AppState,
+    gemini: impl Future<Output = Result<Bytes, GeminiError>>,
+    base: ImageBytes,
+    fallback: BedrockFallback,
+) -> Result<(Vec<u8>, &'static str), GenerateError> {
+    let (unavailable, error) = match state.breakers.gemini.call(gemini, is_gemini_outage).await {
+        Ok(Ok(result_image)) => return Ok((result_image.to_vec(), "gemini")),
+        Ok(Err(e)) => (e.is_unavailable(), gemini_generate_error(e)),
+        // Gemini's circuit is open: don't even try, but Bedrock can still stand in
+        Err(open) => (true, GenerateError::CircuitOpen(open)),
+    };
+
+    if !(unavailable && state.config.bedrock_fallback) {
+        info!("{}", error);
+        return Err(error);
+    }
+
+    warn!("Gemini unavailable, falling back to Bedrock ({:?}): {}", fallback, error);
+    let result = state.breakers.bedrock.call(
+        async {
+            match fallback {
+                BedrockFallback::Isolate(part) => state.customizer.isolate_part(&base, part).await,
+                BedrockFallback::Remove(parts) => state.customizer.remove_parts(&base, parts).await,
+                BedrockFallback::Install(part) => state.customizer.install_part(&base, part).await,
+            }
+        },
+        is_bedrock_outage,
+    ).await;
+
+    match result {
+        Ok(Ok(result_image)) => Ok((result_image, "bedrock")),
+        Ok(Err(e)) => {
+            let error_msg = format!("{}; Bedrock fallback also failed: {}", error, e);
+            error!("{}", error_msg);
+            Err(GenerateError::Failed(error_msg))
+        }
+        Err(open) => Err(GenerateError::CircuitOpen(open)),
+    }
+}
+
+fn gemini_generate_error(e: GeminiError) -> GenerateError {
+    match e {
+        GeminiError::RateLimited(limit) => GenerateError::RateLimited(limit),
+        GeminiError::Safety(reasons) => GenerateError::Blocked(reasons),
+        e => GenerateError::Failed(format!("Failed to generate image: {}", e)),
+    }
+}
+
+// Only failures that say Gemini itself is in trouble count towards opening its circuit
+fn is_gemini_outage(e: &GeminiError) -> bool {
+    matches!(e, GeminiError::Unavailable(_) | GeminiError::Http(_))
+}
+
+// Why an image generation failed; a provider rate limit reaches the client as a 429
+// and a safety block as a 422, since retrying the same input won't help
+#[derive(Debug)]
+enum GenerateError {
+    Failed(String),
+    RateLimited(RateLimited),
+    Blocked(Vec<String>),
+    CircuitOpen(CircuitOpen),
+}
+
+impl std::fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerateError::Failed(message) => f.write_str(message),
+            GenerateError::RateLimited(limit) => limit.fmt(f),
+            GenerateError::Blocked(reasons) => write!(f, "Blocked by safety filters: {}", reasons.join(", ")),
+            GenerateError::CircuitOpen(open) => open.fmt(f),
+        }
+    }
+}
+
+impl From<GenerateError> for ApiError {
+    fn from(e: GenerateError) -> Self {
+        match e {
+            GenerateError::Failed(message) => ApiError::Message(StatusCode::INTERNAL_SERVER_ERROR, message),
+            GenerateError::RateLimited(limit) => ApiError::RateLimited(limit),
+            GenerateError::Blocked(reasons) => ApiError::Message(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("The image provider refused this input for safety reasons: {}", reasons.join(", ")),
+            ),
+            GenerateError::CircuitOpen(open) => ApiError::CircuitOpen(open),
+        }
+    }
+}
+
+// The generated image after the configured POST_* steps
+fn post_processed<'a>(state: &AppState, image: &'a [u8]) -> Result<Cow<'a, [u8]>, (StatusCode, String)> {
+    state.post_process.apply(image)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to post-process output image: {}", e)))
+}
+
+fn provider_image_response(
+    image: &[u8],
+    format: OutputFormat,
+    provider: &'static str,
+) -> Result<Response, (StatusCode, String)> {
+    info!("Successfully generated image with {}: {} bytes", provider, image.len());
+    let mut response = encoded_image_response(image, format)?;
+    response.headers_mut().insert(IMAGE_PROVIDER_HEADER, HeaderValue::from_static(provider));
+    Ok(response)
+}
+
+// The `?encoding=base64` form of `provider_image_response`: `{ image: <data URL>, size_bytes }`
+fn provider_data_url_response(
+    image: &[u8],
+    format: OutputFormat,
+    provider: &'static str,
+) -> Result<Response, (StatusCode, String)> {
+    info!("Successfully generated image with {}: {} bytes", provider, image.len());
+    let encoded = encode_as(image, format)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to encode output image: {}", e)))?;
+
+    let mut response = Json(json!({
+        "image": data_url(format.content_type(), &encoded),
+        "size_bytes": encoded.len(),
+    })).into_response();
+    response.headers_mut().insert(IMAGE_PROVIDER_HEADER, HeaderValue::from_static(provider));
+    Ok(response)
+}
+
+// Re-encode a generated image and wrap it with the matching content type
+fn encoded_image_response(image: &[u8], format: OutputFormat) -> Result<Response, (StatusCode, String)> {
+    let encoded = encode_as(image, format)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to encode output image: {}", e)))?;
+
+    // Let clients lay out the result without decoding it; only the header is parsed here
+    let (width, height) = image_dimensions(&encoded, "output image")
+        .map_err(|(_, message)| (StatusCode::INTERNAL_SERVER_ERROR, message))?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::CONTENT_LENGTH, encoded.len())
+        .header("x-image-width", width)
+        .header("x-image-height", height)
+        .body(Body::from(encoded))
+        .unwrap())
+}
+
+// Read width/height from the image header without decoding the pixels
+fn image_dimensions(data: &[u8], field: &str) -> Result<(u32, u32), (StatusCode, String)> {
+    image::io::Reader::new(std::io::Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read {}: {}", field, e)))?
+        .into_dimensions()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Unsupported {} format: {}", field, e)))
+}
+
+// Queue a generation job and return its id immediately
+pub async fn generate_async_handler(
+    State(state): State<AppState>,
+    body: UploadBody,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    info!("Received async image generation request");
+
+    let images = read_upload_body(&state, body).await?.images;
+
+    let job = GenerationJob {
+        prompt: EXHAUST_INSTALL_PROMPT.to_string(),
+        images,
+    };
+
+    match state.jobs.submit(job).await {
+        Ok(job_id) => {
+            info!("Queued generation job {}", job_id);
+            Ok((StatusCode::ACCEPTED, Json(json!({ "job_id": job_id }))))
         }
+        Err(e @ SubmitError::QueueFull) => Err(ApiError::Message(StatusCode::SERVICE_UNAVAILABLE, e.to_string())),
+        Err(e) => Err(ApiError::Message(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
-}
\ No newline at end of file
+}
+
+// Return the generated image once the job is done, otherwise its current status
+pub async fn generate_result_handler(
+    Path(job_id): Path<String>,
+    State(state): State<AppState>,
+    Query(output): Query<OutputQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let output_format = output.output_format(&headers, state.post_process.format())?;
+    let Some(status) = state.jobs.status(&job_id).await else {
+        return Err((StatusCode::NOT_FOUND, format!("Unknown job: {}", job_id)));
+    };
+
+    let label = status.label();
+
+    match status {
+        JobStatus::Succeeded(image) => encoded_image_response(&post_processed(&state, &image)?, output_format),
+        JobStatus::Failed(error) => Ok((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "job_id": job_id, "status": label, "error": error })),
+        ).into_response()),
+        _ => Ok((
+            StatusCode::ACCEPTED,
+            Json(json!({ "job_id": job_id, "status": label })),
+        ).into_response()),
+    }
+}
+
+pub async fn create_3d_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: UploadBody,
+) -> Result<Json<TaskCreatedResponse>, ApiError> {
+    info!("Received 3D creation request");
+
+    // A retried request with the same key gets the task created the first time
+    let idempotency_key = headers
+        .get("Idempotency-Key")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+
+    // Concurrent retries wait for the first request's task rather than creating their own
+    let task_id = match idempotency_key {
+        Some(key) => state.creating_3d.run(key.clone(), create_3d_once(&state, key, body)).await?,
+        None => create_3d_task(&state, body).await?,
+    };
+    Ok(Json(TaskCreatedResponse { task_id }))
+}
+
+// The task recorded for `key`, or a new one that is then recorded under it
+async fn create_3d_once(state: &AppState, key: String, body: UploadBody) -> Result<String, ApiError> {
+    if let Some(task_id) = state.idempotency.get(&key) {
+        info!("Reusing task {} for Idempotency-Key {}", task_id, key);
+        return Ok(task_id);
+    }
+    let task_id = create_3d_task(state, body).await?;
+    state.idempotency.insert(key, task_id.clone());
+    Ok(task_id)
+}
+
+async fn create_3d_task(state: &AppState, body: UploadBody) -> Result<String, ApiError> {
+    // multipart에서 이미지 추출
+    let form = read_upload_body(state, body).await?;
+    check_3d_input(&state.config, &form.images[0])?;
+
+    let texture_image = match form.files.get("texture_image") {
+        Some(data) => {
+            let data = require_image("texture_image", Some(data.clone()))?;
+            let image = ImageBytes::validated(data)
+                .map_err(|reason| (StatusCode::BAD_REQUEST, format!("texture_image: {}", reason)))?;
+            // Sent to Meshy alongside the model images, so it is screened the same way
+            moderate(state, std::slice::from_ref(&image)).await?;
+            Some(image)
+        }
+        None => None,
+    };
+
+    let ai_model = form.fields.get("ai_model")
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty());
+    if let Some(model) = &ai_model {
+        check_model_allowed(&state.config, model)?;
+    }
+    let art_style = form.fields.get("art_style")
+        .filter(|style| !style.trim().is_empty())
+        .map(|style| style.parse::<ArtStyle>())
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid art_style: {}", e)))?;
+
+    let defaults = Meshy3dOptions::default();
+    let should_texture = parse_bool_field(&form.fields, "should_texture", defaults.should_texture)?;
+    let text_field = |name: &str| form.fields.get(name)
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty());
+    // Meshy's image-to-3D API takes no negative prompt; dropping it silently would mislead
+    if text_field("negative_prompt").is_some() {
+        return Err(ApiError::Message(
+            StatusCode::BAD_REQUEST,
+            "negative_prompt is not supported for image-to-3D".to_string(),
+        ));
+    }
+    let options = Meshy3dOptions {
+        // Untextured geometry has no PBR maps, so only an explicit enable_pbr=true conflicts
+        enable_pbr: parse_bool_field(&form.fields, "enable_pbr", defaults.enable_pbr && should_texture)?,
+        should_remesh: parse_bool_field(&form.fields, "should_remesh", defaults.should_remesh)?,
+        should_texture,
+        texture_prompt: text_field("texture_prompt"),
+        texture_image,
+        ai_model,
+        art_style,
+    };
+    options.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    
+    let created = state.breakers.meshy.call(
+        state.meshy_client.create_3d_task_safe(form.images, &options),
+        |e| matches!(e, CreateTaskError::NotSent(_) | CreateTaskError::Uncertain(_)),
+    ).await?;
+
+    created.map_err(create_3d_error)
+}
+
+fn create_3d_error(e: CreateTaskError) -> ApiError {
+    match e {
+        e @ CreateTaskError::Uncertain(_) => {
+            // A task may exist; tell the client not to resubmit without checking
+            error!("3D task creation outcome unknown: {}", e);
+            ApiError::Message(
+                StatusCode::BAD_GATEWAY,
+                format!("{}. Check your Meshy tasks before retrying to avoid a duplicate", e),
+            )
+        }
+        CreateTaskError::RateLimited(limit) => {
+            warn!("Meshy rate limit hit: {}", limit);
+            ApiError::RateLimited(limit)
+        }
+        e => {
+            error!("Failed to create 3D task: {}", e);
+            ApiError::Message(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create 3D task: {}", e))
+        }
+    }
+}
+
+// Extract `part` from a bike photo and start a Meshy 3D task from the result, in one call.
+// Which step failed shows in the error message: "Extraction failed: ..." or "3D creation failed: ..."
+pub async fn extract_to_3d_handler(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    info!("Received extract-to-3D request");
+
+    let form = collect_form(&mut multipart, is_image_field).await?;
+    let target: ExtractTarget = form.fields.get("part")
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, missing_field_message("part")))?
+        .parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid part: {}", e)))?;
+    let (name, data) = form.images.into_iter().next()
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, missing_field_message("image_motorcycle")))?;
+    let data = require_image(&name, Some(data))?;
+    let img = ImageBytes::validated(data)
+        .map_err(|reason| (StatusCode::BAD_REQUEST, format!("{}: {}", name, reason)))?;
+    moderate(&state, std::slice::from_ref(&img)).await?;
+
+    let (part, provider) = extract_one(&state, target, img).await
+        .map_err(|e| in_stage("Extraction failed", e.into()))?;
+    info!("Extracted {} with {}: {} bytes", target.as_str(), provider, part.len());
+    check_3d_input(&state.config, &part).map_err(|e| in_stage("Extraction failed", e.into()))?;
+
+    let created = state.breakers.meshy.call(
+        state.meshy_client.create_3d_task_safe(vec![ImageBytes::new(part.into())], &Meshy3dOptions::default()),
+        |e| matches!(e, CreateTaskError::NotSent(_) | CreateTaskError::Uncertain(_)),
+    ).await;
+    let task_id = created
+        .map_err(ApiError::from)
+        .and_then(|created| created.map_err(create_3d_error))
+        .map_err(|e| in_stage("3D creation failed", e))?;
+
+    let mut response = Json(TaskCreatedResponse { task_id }).into_response();
+    response.headers_mut().insert(IMAGE_PROVIDER_HEADER, HeaderValue::from_static(provider));
+    Ok(response)
+}
+
+// Prefix a plain error message with the step of a chained request it came from
+fn in_stage(stage: &str, e: ApiError) -> ApiError {
+    match e {
+        ApiError::Message(status, message) => ApiError::Message(status, format!("{}: {}", stage, message)),
+        other => other,
+    }
+}
+
+// Meshy builds garbage models from tiny or sliver-shaped images, so refuse them before paying for a task
+fn check_3d_input(config: &Config, image: &[u8]) -> Result<(), (StatusCode, String)> {
+    let (width, height) = image_dimensions(image, "image")?;
+    let min_side = config.min_3d_image_side;
+    let max_ratio = config.max_3d_aspect_ratio;
+    let ratio = width.max(height) as f32 / width.min(height).max(1) as f32;
+
+    if width.min(height) < min_side || ratio > max_ratio {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "image is {}x{}; 3D reconstruction needs both sides at least {} px and an aspect ratio \
+                no more extreme than {}:1 (this one is {:.1}:1)",
+                width, height, min_side, max_ratio, ratio
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// On shared instances ALLOWED_MODELS limits which (costlier) models callers can pick
+fn check_model_allowed(config: &Config, model: &str) -> Result<(), (StatusCode, String)> {
+    if config.allowed_models.is_empty() || config.allowed_models.iter().any(|allowed| allowed == model) {
+        return Ok(());
+    }
+    Err((
+        StatusCode::FORBIDDEN,
+        format!("Model '{}' is not allowed here; allowed models: {}", model, config.allowed_models.join(", ")),
+    ))
+}
+
+// Image uploads plus any other fields sent alongside them
+struct UploadForm {
+    images: Vec<ImageBytes>,
+    fields: HashMap<String, String>,
+    // Other file uploads (e.g. `texture_image`), left for the handler to interpret
+    files: HashMap<String, Bytes>,
+}
+
+// Collect `image*`/`file` fields, skipping uploads that aren't usable images.
+// Fails only when no valid image remains, listing why each field was skipped.
+async fn read_upload_form(multipart: &mut Multipart) -> Result<UploadForm, (StatusCode, String)> {
+    let form = collect_form(multipart, is_image_field).await?;
+    let mut images = Vec::new();
+    let mut rejected = Vec::new();
+    let mut empty_fields = Vec::new();
+
+    for (name, data) in form.images {
+        if data.is_empty() {
+            warn!("Skipping empty image field '{}'", name);
+            empty_fields.push(name);
+            continue;
+        }
+        let data = transcode_upload(&name, data)?;
+
+        match ImageBytes::validated(data) {
+            Ok(image) => {
+                info!("Received image field '{}': {} bytes ({})", name, image.len(), image.mime());
+                images.push(image);
+            }
+            Err(reason) => {
+                warn!("Skipping image field '{}': {}", name, reason);
+                rejected.push(format!("{}: {}", name, reason));
+            }
+        }
+    }
+
+    if images.is_empty() {
+        info!("No valid images received");
+        let message = match (empty_fields.as_slice(), rejected.is_empty()) {
+            ([], true) => missing_field_message("image"),
+            ([name], true) => empty_field_message(name),
+            _ => {
+                rejected.extend(empty_fields.iter().map(|name| empty_field_message(name)));
+                format!("No valid images provided ({})", rejected.join("; "))
+            }
+        };
+        return Err((StatusCode::BAD_REQUEST, message));
+    }
+
+    Ok(UploadForm { images, fields: form.fields, files: form.files })
+}
+
+// The body of an endpoint that takes an input image: a multipart upload, or JSON naming
+// an S3 object (`{ "s3_uri": "s3://bucket/key" }`) for callers whose images are already there
+pub enum UploadBody {
+    Form(Multipart),
+    S3(S3ImageRequest),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct S3ImageRequest {
+    s3_uri: String,
+    // Anything else is read like the form field of the same name (e.g. `enable_pbr`)
+    #[serde(flatten)]
+    fields: HashMap<String, serde_json::Value>,
+}
+
+impl<S: Send + Sync> axum::extract::FromRequest<S> for UploadBody {
+    type Rejection = Response;
+
+    async fn from_request(request: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = request.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+
+        if is_json {
+            let Json(body) = Json::<S3ImageRequest>::from_request(request, state).await
+                .map_err(IntoResponse::into_response)?;
+            return Ok(Self::S3(body));
+        }
+        Multipart::from_request(request, state).await
+            .map(Self::Form)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+// `read_upload_form` for either kind of body; an S3 input is the one image
+async fn read_upload_body(state: &AppState, body: UploadBody) -> Result<UploadForm, ApiError> {
+    let form = match body {
+        UploadBody::Form(mut multipart) => read_upload_form(&mut multipart).await?,
+        UploadBody::S3(request) => {
+            let image = fetch_s3_image(state, &request.s3_uri).await?;
+            let fields = request.fields
+                .into_iter()
+                .filter_map(|(name, value)| match value {
+                    serde_json::Value::String(value) => Some((name, value)),
+                    serde_json::Value::Bool(_) | serde_json::Value::Number(_) => Some((name, value.to_string())),
+                    _ => None,
+                })
+                .collect();
+            UploadForm { images: vec![image], fields, files: HashMap::new() }
+        }
+    };
+
+    moderate(state, &form.images).await?;
+    Ok(form)
+}
+
+// Run uploads past the moderation hook, when one is configured, before any provider sees them
+async fn moderate(state: &AppState, images: &[ImageBytes]) -> Result<(), ModerationError> {
+    match &state.moderation {
+        Some(moderation) => moderation.check_all(images).await,
+        None => Ok(()),
+    }
+}
+
+// Fetch an `s3://` input and check it is an image, like an uploaded one
+async fn fetch_s3_image(state: &AppState, uri: &str) -> Result<ImageBytes, ApiError> {
+    let Some(inputs) = &state.s3_inputs else {
+        return Err(ApiError::Message(
+            StatusCode::BAD_REQUEST,
+            "S3 inputs are not enabled on this server".to_string(),
+        ));
+    };
+
+    let data = inputs.fetch(uri, state.config.max_upload_bytes).await.map_err(|e| {
+        let status = match &e {
+            S3InputError::InvalidUri(_) => StatusCode::BAD_REQUEST,
+            S3InputError::BucketNotAllowed(_) => StatusCode::FORBIDDEN,
+            S3InputError::NotFound(_) => StatusCode::NOT_FOUND,
+            S3InputError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            S3InputError::Fetch(_) => StatusCode::BAD_GATEWAY,
+        };
+        warn!("{}", e);
+        ApiError::Message(status, e.to_string())
+    })?;
+
+    let data = transcode_upload("s3_uri", data)?;
+    ImageBytes::validated(data)
+        .map_err(|reason| ApiError::Message(StatusCode::BAD_REQUEST, format!("{}: {}", uri, reason)))
+}
+
+fn missing_field_message(name: &str) -> String {
+    format!("missing required field '{}'", name)
+}
+
+fn empty_field_message(name: &str) -> String {
+    format!("image field '{}' was empty", name)
+}
+
+fn empty_text_field_message(name: &str) -> String {
+    format!("field '{}' was empty", name)
+}
+
+// A required image field must be both present and non-empty
+fn require_image(name: &str, data: Option<Bytes>) -> Result<Bytes, (StatusCode, String)> {
+    match data {
+        None => Err((StatusCode::BAD_REQUEST, missing_field_message(name))),
+        Some(data) if data.is_empty() => Err((StatusCode::BAD_REQUEST, empty_field_message(name))),
+        Some(data) => transcode_upload(name, data),
+    }
+}
+
+// Providers don't take HEIC (iPhone photos), so convert it up front or refuse it clearly.
+// A GIF becomes a PNG of its first frame, since an animation isn't a usable input.
+// SVG and PDF can't be converted and are refused with a 415.
+fn transcode_upload(name: &str, data: Bytes) -> Result<Bytes, (StatusCode, String)> {
+    check_raster(sniff_header(&data))
+        .map_err(|reason| (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("image field '{}': {}", name, reason)))?;
+    if sniff_mime(sniff_header(&data)) == Some("image/gif") {
+        info!("Converting GIF field '{}' to a PNG of its first frame", name);
+        return gif_first_frame_png(&data)
+            .map(Bytes::from)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to decode GIF image in '{}': {}", name, e)));
+    }
+    if !is_heic(sniff_header(&data)) {
+        return Ok(data);
+    }
+
+    #[cfg(feature = "heic")]
+    {
+        info!("Transcoding HEIC field '{}' to PNG", name);
+        heic_to_png(&data)
+            .map(Bytes::from)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to decode HEIC image in '{}': {}", name, e)))
+    }
+
+    #[cfg(not(feature = "heic"))]
+    Err((
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        format!("image field '{}' is HEIC/HEIF, which this server can't decode; convert it to JPEG or PNG", name),
+    ))
+}
+
+// Read a form that carries a single required image field, ignoring anything else
+async fn read_required_image(multipart: &mut Multipart, field_name: &str) -> Result<ImageBytes, (StatusCode, String)> {
+    // The last copy wins if the field is repeated
+    let img = collect_images(multipart, |name| name == field_name).await?
+        .pop()
+        .map(|(_, data)| data);
+    require_image(field_name, img).map(ImageBytes::new)
+}
+
+// Read an optional true/false form field, falling back to the default when absent
+fn parse_bool_field(
+    fields: &HashMap<String, String>,
+    name: &str,
+    default: bool,
+) -> Result<bool, (StatusCode, String)> {
+    match fields.get(name).map(|v| v.trim().to_ascii_lowercase()) {
+        None => Ok(default),
+        Some(v) if v.is_empty() => Ok(default),
+        Some(v) if v == "true" || v == "1" => Ok(true),
+        Some(v) if v == "false" || v == "0" => Ok(false),
+        Some(v) => Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid value for '{}': expected true or false, got '{}'", name, v),
+        )),
+    }
+}
+
+async fn handler(mut multipart: Multipart) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let first = multipart.next_field().await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read field: {}", e)))?;
+    if first.is_none() {
+        return Err(empty_form_error());
+    }
+
+    let response = json!({
+        "message": "Hello, World!"
+    });
+
+    Ok(Json(response))
+}
+
+// WebSocket 핸들러
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Path(task_id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, task_id, state))
+}
+
+async fn handle_socket(
+    socket: WebSocket, 
+    task_id: String, 
+    state: AppState,
+) {
+    info!("WebSocket connected - task: {}", task_id);
+
+    // Polling runs on its own task so the client can interrupt it with a "cancel" message
+    let (sender, mut receiver) = socket.split();
+    let cancel = CancellationToken::new();
+    let awaiting_pong = Arc::new(AtomicBool::new(false));
+    let mut poller = tokio::spawn(poll_task_status(
+        sender,
+        task_id.clone(),
+        state.clone(),
+        cancel.clone(),
+        awaiting_pong.clone(),
+    ));
+
+    loop {
+        tokio::select! {
+            _ = &mut poller => break,
+            message = receiver.next() => match message {
+                Some(Ok(Message::Text(text))) if text.trim() == "cancel" => {
+                    info!("Client cancelled task {}", task_id);
+                    cancel.cancel();
+                    if let Ok(sender) = (&mut poller).await {
+                        cancel_remote_task(sender, &task_id, &state).await;
+                    }
+                    break;
+                }
+                Some(Ok(Message::Pong(_))) => awaiting_pong.store(false, Ordering::Relaxed),
+                Some(Ok(Message::Close(_))) => {
+                    info!("Client closed the socket for task {}", task_id);
+                    cancel.cancel();
+                    break;
+                }
+                Some(Ok(_)) => {}
+                // Client went away; nobody is left to poll for
+                _ => {
+                    cancel.cancel();
+                    break;
+                }
+            }
+        }
+    }
+    
+    info!("WebSocket closed for task: {}", task_id);
+}
+
+// Push status updates until the task finishes, the send fails or `cancel` fires.
+// Pings in between keep idle proxies from dropping a long task's socket; the reader clears
+// `awaiting_pong`, and a ping still unanswered when the next one is due ends the socket.
+// A client too backed up to take a message within WS_SEND_TIMEOUT_SECS ends it as well.
+// Hands the sender back so the caller can still reply after a cancel.
+async fn poll_task_status<S: Sink<Message> + Unpin>(
+    mut socket: S,
+    task_id: String,
+    state: AppState,
+    cancel: CancellationToken,
+    awaiting_pong: Arc<AtomicBool>,
+) -> S {
+    let send_timeout = state.config.ws_send_timeout;
+    let mut pings = tokio::time::interval(state.config.ws_ping_interval);
+    pings.reset();
+    let updates = task_status_updates(task_id.clone(), state);
+    tokio::pin!(updates);
+
+    loop {
+        let update = tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = pings.tick() => {
+                if awaiting_pong.swap(true, Ordering::Relaxed) {
+                    warn!("No pong from the client of task {}, closing", task_id);
+                    let _ = tokio::time::timeout(send_timeout, socket.close()).await;
+                    break;
+                }
+                if !send_within(&mut socket, Message::Ping(Bytes::new()), send_timeout).await {
+                    break;
+                }
+                continue;
+            }
+            update = updates.next() => update,
+        };
+
+        let Some(update) = update else {
+            let _ = tokio::time::timeout(send_timeout, socket.close()).await;
+            break;
+        };
+        if !send_within(&mut socket, Message::Text(update.into()), send_timeout).await {
+            break;
+        }
+    }
+
+    socket
+}
+
+// Send one message, giving up when the client hasn't taken it within `budget` so a stalled
+// reader can't hold the sender forever. `false` means the socket should be dropped.
+async fn send_within<S: Sink<Message> + Unpin>(socket: &mut S, message: Message, budget: Duration) -> bool {
+    match tokio::time::timeout(budget, socket.send(message)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(_)) => {
+            info!("Client disconnected");
+            false
+        }
+        Err(_) => {
+            warn!("Client didn't take a message within {}s, closing the socket", budget.as_secs_f32());
+            false
+        }
+    }
+}
+
+enum TaskPoll {
+    Poll { wait: bool },
+    Done,
+}
+
+// One JSON status message per poll of the Meshy task, shared by the WebSocket and SSE endpoints.
+// Ends once the task is finished (a preview-stage SUCCEEDED keeps polling for the refine stage),
+// or after reporting the first error.
+fn task_status_updates(task_id: String, state: AppState) -> impl futures::Stream<Item = String> {
+    stream::unfold(TaskPoll::Poll { wait: false }, move |poll| {
+        let (task_id, state) = (task_id.clone(), state.clone());
+        async move {
+            let TaskPoll::Poll { wait } = poll else {
+                return None;
+            };
+            if wait {
+                sleep(state.config.poll_interval).await;
+            }
+
+            match state.meshy_client.get_task_status(&task_id).await {
+                Ok(status) => {
+                    let status_json = match status_message(&status) {
+                        Ok(json) => json,
+                        Err(error_frame) => return Some((error_frame, TaskPoll::Done)),
+                    };
+
+                    info!("Sending status update: {} - progress: {}",
+                        status.status,
+                        status.progress.unwrap_or(0)
+                    );
+
+                    if status.is_finished() {
+                        info!("Task {} finished with status: {}", task_id, status.status);
+                        return Some((status_json, TaskPoll::Done));
+                    }
+                    Some((status_json, TaskPoll::Poll { wait: true }))
+                }
+                Err(e) => {
+                    error!("Failed to get task status: {}", e);
+                    let error_msg = json!({
+                        "error": "Failed to get status",
+                        "details": e.to_string()
+                    }).to_string();
+                    Some((error_msg, TaskPoll::Done))
+                }
+            }
+        }
+    })
+}
+
+// Status JSON for the client; if it can't be serialized, an error frame to send instead
+// so the client isn't left waiting on a stream that just stopped
+fn status_message(status: &impl Serialize) -> Result<String, String> {
+    serde_json::to_string(status).map_err(|e| {
+        error!("Failed to serialize status: {}", e);
+        json!({
+            "error": "Failed to serialize status",
+            "details": e.to_string()
+        }).to_string()
+    })
+}
+
+// Same updates as the WebSocket, for clients behind proxies that don't pass WebSockets through
+async fn sse_handler(
+    Path(task_id): Path<String>,
+    State(state): State<AppState>,
+) -> Sse<impl futures::Stream<Item = Result<Event, std::convert::Infallible>>> {
+    info!("SSE connected - task: {}", task_id);
+    let events = task_status_updates(task_id, state).map(|update| Ok(Event::default().data(update)));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+// Cancel a task that is still queued or running. Meshy cancels by deleting, which for a
+// finished task would throw its model away, so those are refused instead.
+pub async fn cancel_3d_handler(
+    Path(task_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    info!("Canceling 3D task: {}", task_id);
+
+    let status = state.meshy_client.get_task_status(&task_id).await.map_err(|e| {
+        if e.is::<TaskNotFound>() {
+            return ApiError::Message(StatusCode::NOT_FOUND, e.to_string());
+        }
+        error!("Failed to get task status: {}", e);
+        ApiError::Message(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get task status: {}", e))
+    })?;
+    if status.is_finished() {
+        return Err(ApiError::Message(
+            StatusCode::CONFLICT,
+            format!("Task {} already {}; only pending or running tasks can be canceled", task_id, status.status),
+        ));
+    }
+
+    state.meshy_client.cancel_task(&task_id).await.map_err(|e| {
+        error!("Failed to cancel task {}: {}", task_id, e);
+        ApiError::Message(StatusCode::BAD_GATEWAY, e.to_string())
+    })?;
+
+    info!("Canceled 3D task: {}", task_id);
+    Ok(Json(json!({ "id": task_id, "status": "CANCELED" })))
+}
+
+// Stop the Meshy task so it doesn't keep burning credits, then tell the client and hang up
+async fn cancel_remote_task(mut socket: SplitSink<WebSocket, Message>, task_id: &str, state: &AppState) {
+    let reply = match state.meshy_client.cancel_task(task_id).await {
+        Ok(()) => json!({ "id": task_id, "status": "CANCELED" }),
+        Err(e) => {
+            error!("Failed to cancel task {}: {}", task_id, e);
+            json!({ "error": "Failed to cancel task", "details": e.to_string() })
+        }
+    };
+
+    let budget = state.config.ws_send_timeout;
+    if send_within(&mut socket, Message::Text(reply.to_string().into()), budget).await {
+        let _ = tokio::time::timeout(budget, socket.close()).await;
+    }
+}
+
+// Router configuration with proper state management
+pub fn create_router(state: AppState) -> Router {
+    let max_upload_bytes = state.config.max_upload_bytes;
+
+    let router = Router::new()
+        .route("/test", post(test))
+        .route("/gen_image", post(generate_image))
+        // Consider to integrate these three into one with different prompts
+        .route("/extract_exhaust", post(extract_exhaust_image))
+        .route("/extract_seat", post(extract_seat_image))
+        .route("/extract_frame", post(extract_frame_image))
+        .route("/extract/batch", post(extract_batch_handler))
+        .route("/extract_to_3d", post(extract_to_3d_handler))
+        .route("/version", get(version_handler))
+        .route("/selftest", get(selftest_handler))
+        .route("/ready", get(ready_handler))
+        .route("/compose", post(compose_handler))
+        .route("/customize", post(customize_handler))
+        .route("/customize/with_mask", post(customize_with_mask_handler))
+        .route("/customize/options", post(customize_options_handler))
+        .route("/customize/options/ws", get(customize_options_ws_handler))
+        .route("/customize/sheet", post(customize_sheet_handler))
+        .route("/customize/prompt", post(customize_prompt_handler))
+        .route("/customize/multi", post(customize_multi_handler))
+        .route("/generate/with_refs", post(generate_with_refs_handler))
+        .route("/generate/async", post(generate_async_handler))
+        .route("/generate/result/{job_id}", get(generate_result_handler))
+        .route("/api/3d/create", post(create_3d_handler))
+        .route("/api/3d/ws/{task_id}", get(ws_handler))
+        .route("/api/3d/sse/{task_id}", get(sse_handler))
+        .route("/api/3d/model/{task_id}", get(proxy_model_handler))  // 새 라우트
+        .route("/api/3d/task/{task_id}", delete(cancel_3d_handler));
+
+    // Counting as a route layer sees the matched route pattern; /stats itself isn't counted
+    #[cfg(feature = "stats")]
+    let router = router
+        .route_layer(axum::middleware::from_fn_with_state(state.stats.clone(), count_request))
+        .route("/stats", get(stats_handler));
+
+    router
+        .layer(compression_layer())
+        .layer(DefaultBodyLimit::max(max_upload_bytes))
+        .with_state(state)
+}
+
+#[cfg(feature = "stats")]
+async fn count_request(
+    State(stats): State<Arc<Stats>>,
+    route: axum::extract::MatchedPath,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    stats.record(route.as_str());
+    next.run(request).await
+}
+
+// Quick counters for when a metrics stack would be overkill
+#[cfg(feature = "stats")]
+async fn stats_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let breakers = &state.breakers;
+    let (gemini, meshy, bedrock) = (breakers.gemini.errors(), breakers.meshy.errors(), breakers.bedrock.errors());
+
+    Json(json!({
+        "uptime_secs": state.stats.uptime_secs(),
+        "requests_total": state.stats.requests(),
+        "routes": state.stats.routes(),
+        "upstream_errors": {
+            "total": gemini + meshy + bedrock,
+            "gemini": gemini,
+            "meshy": meshy,
+            "bedrock": bedrock,
+        },
+    }))
+}
+
+// gzip/br for JSON and text when the client accepts it. The default predicate already
+// skips images (PNG/JPEG/WebP gain nothing) and SSE; model downloads are skipped too so
+// their Content-Length and byte ranges stay meaningful.
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new()
+        .compress_when(DefaultPredicate::new().and(NotForContentType::const_new("model/")))
+}
+
+// `?encoding=base64` returns the result inline as JSON instead of as raw bytes, for front-ends
+// that can't handle a binary body
+#[derive(Debug, Default, Deserialize)]
+pub struct EncodingQuery {
+    encoding: Option<String>,
+}
+
+impl EncodingQuery {
+    fn is_base64(&self) -> Result<bool, (StatusCode, String)> {
+        match self.encoding.as_deref() {
+            None | Some("binary") => Ok(false),
+            Some("base64") => Ok(true),
+            Some(other) => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Invalid encoding '{}': expected binary or base64", other),
+            )),
+        }
+    }
+}
+
+pub async fn proxy_model_handler(
+    Path(task_id): Path<String>,
+    Query(query): Query<EncodingQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    info!("Proxying 3D model for task: {}", task_id);
+
+    let inline = query.is_base64()?;
+
+    // A finished task's model never changes, so the task id and representation
+    // are enough to identify the bytes without downloading them again
+    let etag = model_etag(&task_id, if inline { "glb-base64" } else { "glb" });
+    if if_none_match(&headers, &etag) {
+        info!("Model for task {} not modified", task_id);
+        return Ok(with_model_cache_headers(StatusCode::NOT_MODIFIED.into_response(), &etag));
+    }
+
+    let body = fetch_model(&task_id, &state).await?;
+    if !inline {
+        return Ok(with_model_cache_headers(glb_response(&task_id, body), &etag));
+    }
+
+    // WebGL viewers that embed the model want it in the page rather than as a file
+    let bytes = axum::body::to_bytes(body, usize::MAX).await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Failed to read model bytes: {}", e)))?;
+    let response = Json(json!({
+        "mime": GLB_MIME,
+        "data": general_purpose::STANDARD.encode(&bytes),
+    })).into_response();
+    Ok(with_model_cache_headers(response, &etag))
+}
+
+// Strong validator for a task's model in one representation
+fn model_etag(task_id: &str, format: &str) -> String {
+    format!("\"{}-{}\"", task_id, format)
+}
+
+// True when If-None-Match lists `etag` (or is `*`); W/ prefixes are ignored as the
+// weak comparison RFC 9110 asks for here
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn with_model_cache_headers(mut response: Response, etag: &str) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(MODEL_CACHE_CONTROL));
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, value);
+    }
+    response
+}
+
+// Completed models are immutable, so clients and shared caches can keep them for a year
+const MODEL_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+// Binary glTF; the `model/` type is also what keeps it out of response compression
+const GLB_MIME: &str = "model/gltf-binary";
+
+// GLB for a finished task, from the model cache when possible, otherwise from Meshy's CDN
+async fn fetch_model(task_id: &str, state: &AppState) -> Result<Body, (StatusCode, String)> {
+    if let Some(cache) = &state.model_cache {
+        match cache.get(task_id, "glb").await {
+            Ok(Some(body)) => {
+                info!("Serving model for task {} from cache", task_id);
+                return Ok(body);
+            }
+            Ok(None) => info!("Model cache miss for task {}", task_id),
+            Err(e) => warn!("Model cache lookup failed for task {}: {}", task_id, e),
+        }
+    }
+    
+    let status = state.meshy_client.get_task_status(task_id).await
+        .map_err(|e| {
+            error!("Failed to get task status: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get task status: {}", e))
+        })?;
+
+    let Some(model_url) = status.model_url else {
+        error!("No model URL available for task: {}", task_id);
+        return Err((StatusCode::NOT_FOUND, format!("No model available for task {}", task_id)));
+    };
+
+    info!("Fetching model from: {}", model_url);
+
+    let client = Client::builder()
+        .timeout(state.config.upstream_timeout)
+        .build()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to build HTTP client: {}", e)))?;
+    let bytes = download_with_resume(&client, &model_url, state.config.model_download_retries).await?;
+
+    // Don't hand an HTML error page or truncated download to the client as a .glb
+    if !is_glb(sniff_header(&bytes)) {
+        error!("Upstream model for task {} is not a GLB file ({} bytes)", task_id, bytes.len());
+        return Err((
+            StatusCode::BAD_GATEWAY,
+            format!("Upstream returned an invalid GLB file for task {}", task_id),
+        ));
+    }
+
+    info!("Successfully fetched model: {} bytes", bytes.len());
+
+    // A failed store only costs a refetch next time
+    if let Some(cache) = &state.model_cache
+        && let Err(e) = cache.put(task_id, "glb", bytes.clone()).await
+    {
+        warn!("Failed to cache model for task {}: {}", task_id, e);
+    }
+
+    Ok(Body::from(bytes))
+}
+
+// Download `url`, resuming with a Range request when the connection drops midway.
+// Gives up after `max_retries` retries; a server that ignores Range restarts from zero.
+async fn download_with_resume(
+    client: &Client,
+    url: &str,
+    max_retries: u32,
+) -> Result<Bytes, (StatusCode, String)> {
+    let mut data: Vec<u8> = Vec::new();
+    let mut attempt = 0;
+
+    loop {
+        let mut request = client.get(url);
+        if !data.is_empty() {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", data.len()));
+        }
+
+        let failure = match request.send().await {
+            Err(e) => format!("Failed to download model: {}", e),
+            Ok(mut response) => {
+                let status = response.status();
+                if !status.is_success() {
+                    error!("Failed to fetch model: {}", status);
+                    return Err((StatusCode::BAD_GATEWAY, format!("Model download failed with status {}", status)));
+                }
+                if !data.is_empty() && status != reqwest::StatusCode::PARTIAL_CONTENT {
+                    warn!("Model server ignored the Range request, restarting download");
+                    data.clear();
+                }
+
+                loop {
+                    match response.chunk().await {
+                        Ok(Some(chunk)) => data.extend_from_slice(&chunk),
+                        Ok(None) => return Ok(Bytes::from(data)),
+                        Err(e) => break format!("Model download interrupted after {} bytes: {}", data.len(), e),
+                    }
+                }
+            }
+        };
+
+        if attempt >= max_retries {
+            error!("{}", failure);
+            return Err((StatusCode::BAD_GATEWAY, failure));
+        }
+        attempt += 1;
+        warn!("{}; retrying ({}/{})", failure, attempt, max_retries);
+    }
+}
+
+fn glb_response(task_id: &str, body: Body) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, GLB_MIME)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"motorcycle-3d-{}.glb\"", task_id)
+        )
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(body)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{bedrock_client, multipart_request, png_fixture, s3_client, spawn_mock};
+    use crate::util::temp::unique_temp_path;
+    use axum::http::Request;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn test_config() -> Config {
+        Config::from_lookup(|key| match key {
+            "GEMINI_API_KEY" | "MESHY_API_KEY" => Some("test-key".to_string()),
+            _ => None,
+        }).unwrap()
+    }
+
+    // App state wired to local mock upstreams
+    fn test_state(meshy_url: &str) -> AppState {
+        test_state_with_bedrock(meshy_url, "http://127.0.0.1:9")
+    }
+
+    fn test_state_with_bedrock(meshy_url: &str, bedrock_url: &str) -> AppState {
+        AppState {
+            config: Arc::new(test_config()),
+            gemini: Arc::new(GeminiClient::with_base_url("test-key", "http://127.0.0.1:9")),
+            meshy_client: Arc::new(MeshyClient::with_base_url("test-key", meshy_url)),
+            customizer: Arc::new(MotorcycleCustomizer::with_generator(
+                BedrockImageGenerator::from_client(bedrock_client(bedrock_url)),
+            )),
+            jobs: Arc::new(JobQueue::start(1, 4, stub_runner())),
+            idempotency: Arc::new(IdempotencyStore::new(Duration::from_secs(60))),
+            creating_3d: Arc::new(SingleFlight::default()),
+            model_cache: None,
+            s3_inputs: None,
+            breakers: Arc::new(ProviderBreakers::new(5, Duration::from_secs(30))),
+            parts: Arc::new(PartsManifest::default()),
+            post_process: Arc::new(PostProcess::default()),
+            moderation: None,
+            #[cfg(feature = "stats")]
+            stats: Arc::new(Stats::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn registry_cleanup_reaps_expired_entries() {
+        let mut state = test_state("http://127.0.0.1:9");
+        state.config = Arc::new(Config {
+            job_result_ttl: Duration::from_millis(400),
+            registry_cleanup_interval: Duration::from_millis(20),
+            ..test_config()
+        });
+        state.idempotency = Arc::new(IdempotencyStore::new(Duration::from_millis(400)));
+
+        let job_id = state.jobs.submit(GenerationJob { prompt: "p".to_string(), images: Vec::new() }).await.unwrap();
+        state.idempotency.insert("key-1".to_string(), "task-1".to_string());
+        let cleanup = spawn_registry_cleanup(state.clone());
+
+        // A few sweeps in but far inside the TTL, so a slow machine can't reap them early
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(state.jobs.status(&job_id).await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(800)).await;
+        assert!(state.jobs.status(&job_id).await.is_none());
+        assert_eq!(state.idempotency.evict_expired(), 0);
+        cleanup.abort();
+    }
+
+    #[tokio::test]
+    async fn gemini_and_meshy_clients_get_their_own_timeouts() {
+        // Both providers answer after the same delay; only Meshy's budget is shorter than it
+        let slow = || async {
+            sleep(Duration::from_millis(1500)).await;
+            Json(json!({}))
+        };
+        let mock = spawn_mock(
+            Router::new()
+                .route("/v1beta/models/{model}", get(slow))
+                .route("/openapi/v1/image-to-3d", get(slow)),
+        ).await;
+        let config = Config::from_lookup(|key| match key {
+            "GEMINI_API_KEY" | "MESHY_API_KEY" => Some("test-key".to_string()),
+            "GEMINI_TIMEOUT_SECS" => Some("10".to_string()),
+            "MESHY_TIMEOUT_SECS" => Some("1".to_string()),
+            _ => None,
+        }).unwrap();
+
+        let gemini = configured_gemini(GeminiClient::with_base_url("test-key", mock.as_str()), &config);
+        let meshy = configured_meshy(MeshyClient::with_base_url("test-key", mock.as_str()), &config);
+        let (gemini, meshy) = tokio::join!(gemini.probe(), meshy.probe());
+
+        assert!(gemini.is_ok(), "{:?}", gemini);
+        let err = meshy.unwrap_err().to_string();
+        assert!(err.contains("timed out") || err.contains("timeout"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn warm_up_calls_every_provider_and_tolerates_failures() {
+        let hits = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = |provider: &'static str| {
+            let hits = hits.clone();
+            move || async move { hits.lock().unwrap().push(provider) }
+        };
+
+        let gemini = spawn_mock(Router::new().route("/v1beta/models/{model}", get(record("gemini")))).await;
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d",
+            get({
+                let record = record("meshy");
+                move || async move {
+                    record().await;
+                    (StatusCode::UNAUTHORIZED, "Invalid API key")
+                }
+            }),
+        )).await;
+        // The empty warmup body is refused by validation, which still counts as warm
+        let bedrock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post({
+                let record = record("bedrock");
+                move || async move {
+                    record().await;
+                    (
+                        StatusCode::BAD_REQUEST,
+                        [("x-amzn-errortype", "ValidationException")],
+                        Json(json!({ "message": "Malformed input request" })),
+                    )
+                }
+            }),
+        )).await;
+        let mut state = test_state_with_bedrock(&meshy, &bedrock);
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+
+        let checks = warm_up(&state).await;
+
+        let mut called = hits.lock().unwrap().clone();
+        called.sort();
+        assert_eq!(called, ["bedrock", "gemini", "meshy"]);
+        let ok: Vec<_> = checks.iter().map(|(provider, check)| (*provider, check.ok)).collect();
+        assert_eq!(ok, [("gemini", true), ("meshy", false), ("bedrock", true)]);
+    }
+
+    #[tokio::test]
+    async fn ready_reports_whether_the_bedrock_model_is_invokable() {
+        // Bedrock checks model access before it validates the (empty) body
+        let bedrock_stub = |error_type: &'static str, status: StatusCode, calls: Arc<std::sync::atomic::AtomicUsize>| {
+            spawn_mock(Router::new().route(
+                "/model/{model_id}/invoke",
+                post(move || {
+                    calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    async move { (status, [("x-amzn-errortype", error_type)], Json(json!({ "message": error_type }))) }
+                }),
+            ))
+        };
+        let ready = |app: Router| async move {
+            let response = app
+                .oneshot(Request::get("/ready").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+        };
+
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let allowed = bedrock_stub("ValidationException", StatusCode::BAD_REQUEST, calls.clone()).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &allowed));
+        let (status, body) = ready(app.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["bedrock"]["invokable"], true);
+        assert_eq!(body["bedrock"]["model_id"], "stability.stable-diffusion-xl-v1");
+        // A second probe inside the cache window doesn't call AWS again
+        assert_eq!(ready(app).await.0, StatusCode::OK);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let denied = bedrock_stub("AccessDeniedException", StatusCode::FORBIDDEN, Arc::default()).await;
+        let (status, body) = ready(create_router(test_state_with_bedrock("http://127.0.0.1:9", &denied))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        let error = body["bedrock"]["error"].as_str().unwrap();
+        assert!(error.contains("AccessDeniedException"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn selftest_reports_each_provider() {
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            get(|| async { Json(json!({ "name": "models/gemini-2.5-flash-image" })) }),
+        )).await;
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d",
+            get(|| async { (StatusCode::UNAUTHORIZED, "Invalid API key") }),
+        )).await;
+        // Only the free empty-body invoke may reach Bedrock, never a real generation
+        let bedrock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(|body: Bytes| async move {
+                assert_eq!(body.as_ref(), b"{}");
+                (
+                    StatusCode::BAD_REQUEST,
+                    [("x-amzn-errortype", "ValidationException")],
+                    Json(json!({ "message": "Malformed input request" })),
+                )
+            }),
+        )).await;
+        let mut state = test_state_with_bedrock(&meshy, &bedrock);
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+
+        let response = create_router(state)
+            .oneshot(Request::builder().uri("/selftest").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["ok"], false);
+        for provider in ["gemini", "meshy", "bedrock"] {
+            assert!(report["providers"][provider]["latency_ms"].is_u64(), "{}", report);
+        }
+        assert_eq!(report["providers"]["gemini"]["ok"], true);
+        assert_eq!(report["providers"]["bedrock"]["ok"], true);
+        assert!(report["providers"]["gemini"].get("error").is_none());
+        assert_eq!(report["providers"]["meshy"]["ok"], false);
+        let error = report["providers"]["meshy"]["error"].as_str().unwrap();
+        assert!(error.contains("401") && error.contains("Invalid API key"), "{}", error);
+    }
+
+    // Bedrock mock that always returns the given image as its single artifact
+    async fn bedrock_mock(image: Vec<u8>) -> String {
+        let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, image);
+        spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(move || async move {
+                Json(json!({
+                    "artifacts": [{
+                        "base64": encoded,
+                        "finishReason": "SUCCESS"
+                    }]
+                }))
+            }),
+        )).await
+    }
+
+    // Job runner that "generates" after a short delay: a PNG one row taller than the number of inputs
+    fn stub_runner() -> JobRunner {
+        Arc::new(|job: GenerationJob| Box::pin(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(Bytes::from(png_fixture(8, 1 + job.images.len() as u32)))
+        }))
+    }
+
+    // Meshy mock whose task reports a finished model hosted at `model_url`
+    async fn meshy_mock_with_model(model_url: String) -> String {
+        let mock = Router::new().route(
+            "/openapi/v1/image-to-3d/{task_id}",
+            get(move |Path(task_id): Path<String>| {
+                let model_url = model_url.clone();
+                async move {
+                    Json(json!({
+                        "id": task_id,
+                        "status": "SUCCEEDED",
+                        "progress": 100,
+                        "model_urls": { "glb": model_url }
+                    }))
+                }
+            }),
+        );
+        spawn_mock(mock).await
+    }
+
+    #[tokio::test]
+    async fn ws_cancel_stops_polling_and_cancels_meshy_task() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let polls = Arc::new(AtomicUsize::new(0));
+        let deletes = Arc::new(AtomicUsize::new(0));
+        let (poll_count, delete_count) = (polls.clone(), deletes.clone());
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d/{task_id}",
+            get(move |Path(task_id): Path<String>| async move {
+                poll_count.fetch_add(1, Ordering::SeqCst);
+                Json(json!({ "id": task_id, "status": "IN_PROGRESS", "progress": 10 }))
+            })
+            .delete(move || async move {
+                delete_count.fetch_add(1, Ordering::SeqCst);
+                Json(json!({}))
+            }),
+        )).await;
+        let mut state = test_state(&meshy);
+        state.config = Arc::new(Config { poll_interval: Duration::from_secs(1), ..test_config() });
+        let server = spawn_mock(create_router(state)).await;
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(
+            format!("{}/api/3d/ws/task-1", server.replace("http://", "ws://")),
+        ).await.unwrap();
+
+        let first = ws.next().await.unwrap().unwrap();
+        assert!(first.to_text().unwrap().contains("IN_PROGRESS"));
+
+        ws.send(WsMessage::Text("cancel".into())).await.unwrap();
+        let reply: serde_json::Value = serde_json::from_str(ws.next().await.unwrap().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(reply, json!({ "id": "task-1", "status": "CANCELED" }));
+        assert!(matches!(ws.next().await, Some(Ok(WsMessage::Close(_))) | None));
+
+        // Outlast the one-second poll interval; an aborted poller never asks again
+        sleep(Duration::from_millis(1500)).await;
+        assert_eq!(polls.load(Ordering::SeqCst), 1);
+        assert_eq!(deletes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn delete_cancels_a_running_task_but_not_a_finished_one() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let deletes = Arc::new(AtomicUsize::new(0));
+        let delete_count = deletes.clone();
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d/{task_id}",
+            get(|Path(task_id): Path<String>| async move {
+                if task_id == "missing" {
+                    return (StatusCode::NOT_FOUND, Json(json!({ "message": "Task not found" }))).into_response();
+                }
+                let status = if task_id == "done" { "SUCCEEDED" } else { "IN_PROGRESS" };
+                Json(json!({ "id": task_id, "status": status, "progress": 40 })).into_response()
+            })
+            .delete(move || async move {
+                delete_count.fetch_add(1, Ordering::SeqCst);
+                Json(json!({}))
+            }),
+        )).await;
+        let app = create_router(test_state(&meshy));
+        let cancel = |task_id: &str| {
+            app.clone().oneshot(Request::delete(format!("/api/3d/task/{}", task_id)).body(Body::empty()).unwrap())
+        };
+
+        let response = cancel("task-1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({ "id": "task-1", "status": "CANCELED" }));
+        assert_eq!(deletes.load(Ordering::SeqCst), 1);
+
+        let response = cancel("done").await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"Task done already SUCCEEDED; only pending or running tasks can be canceled");
+        assert_eq!(deletes.load(Ordering::SeqCst), 1);
+
+        let response = cancel("missing").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"Unknown task: missing");
+        assert_eq!(deletes.load(Ordering::SeqCst), 1);
+    }
+
+    // A client that never reads: every send stays pending
+    struct StalledSink;
+
+    impl Sink<Message> for StalledSink {
+        type Error = axum::Error;
+
+        fn poll_ready(self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Pending
+        }
+
+        fn start_send(self: std::pin::Pin<&mut Self>, _: Message) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn poll_flush(self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Pending
+        }
+
+        fn poll_close(self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Pending
+        }
+    }
+
+    #[tokio::test]
+    async fn ws_poller_drops_a_client_that_stops_reading() {
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d/{task_id}",
+            get(|Path(task_id): Path<String>| async move {
+                Json(json!({ "id": task_id, "status": "IN_PROGRESS", "progress": 10 }))
+            }),
+        )).await;
+        let mut state = test_state(&meshy);
+        state.config = Arc::new(Config {
+            poll_interval: Duration::from_secs(60),
+            ws_send_timeout: Duration::from_millis(100),
+            ..test_config()
+        });
+
+        let started = Instant::now();
+        let poller = poll_task_status(
+            StalledSink,
+            "task-1".to_string(),
+            state,
+            CancellationToken::new(),
+            Arc::new(AtomicBool::new(false)),
+        );
+        // Handing the sink back is what lets handle_socket drop the connection
+        assert!(tokio::time::timeout(Duration::from_secs(5), poller).await.is_ok(), "poller stuck on a stalled client");
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn ws_pings_a_slow_task_and_stays_open_while_pongs_come_back() {
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d/{task_id}",
+            get(|Path(task_id): Path<String>| async move {
+                Json(json!({ "id": task_id, "status": "IN_PROGRESS", "progress": 10 }))
+            }),
+        )).await;
+        let mut state = test_state(&meshy);
+        state.config = Arc::new(Config {
+            poll_interval: Duration::from_secs(60),
+            ws_ping_interval: Duration::from_millis(100),
+            ..test_config()
+        });
+        let server = spawn_mock(create_router(state)).await;
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(
+            format!("{}/api/3d/ws/task-1", server.replace("http://", "ws://")),
+        ).await.unwrap();
+        let first = ws.next().await.unwrap().unwrap();
+        assert!(first.to_text().unwrap().contains("IN_PROGRESS"));
+
+        // No status is due for a minute, yet pings keep coming; reading answers each with a pong
+        for _ in 0..3 {
+            let frame = tokio::time::timeout(Duration::from_secs(2), ws.next()).await.unwrap();
+            assert!(matches!(frame, Some(Ok(WsMessage::Ping(_)))), "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn unserializable_status_becomes_an_error_frame() {
+        struct Unserializable;
+        impl Serialize for Unserializable {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("progress out of range"))
+            }
+        }
+
+        let frame: serde_json::Value = serde_json::from_str(&status_message(&Unserializable).unwrap_err()).unwrap();
+        assert_eq!(frame, json!({ "error": "Failed to serialize status", "details": "progress out of range" }));
+
+        let ok = status_message(&json!({ "id": "task-1", "status": "PENDING" })).unwrap();
+        assert_eq!(ok, r#"{"id":"task-1","status":"PENDING"}"#);
+    }
+
+    #[tokio::test]
+    async fn sse_streams_status_until_task_finishes() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let polls = Arc::new(AtomicUsize::new(0));
+        let poll_count = polls.clone();
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d/{task_id}",
+            get(move |Path(task_id): Path<String>| async move {
+                let (status, progress) = match poll_count.fetch_add(1, Ordering::SeqCst) {
+                    0 => ("IN_PROGRESS", 40),
+                    _ => ("SUCCEEDED", 100),
+                };
+                Json(json!({ "id": task_id, "status": status, "progress": progress }))
+            }),
+        )).await;
+        let mut state = test_state(&meshy);
+        state.config = Arc::new(Config { poll_interval: Duration::from_secs(1), ..test_config() });
+
+        let response = create_router(state)
+            .oneshot(Request::builder().uri("/api/3d/sse/task-1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+
+        // The stream ends on SUCCEEDED, so the whole body can be read
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let events: Vec<serde_json::Value> = std::str::from_utf8(&body).unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["status"], "IN_PROGRESS");
+        assert_eq!(events[0]["progress"], 40);
+        assert_eq!(events[1]["status"], "SUCCEEDED");
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn ws_stays_open_after_preview_until_refine_succeeds() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let polls = Arc::new(AtomicUsize::new(0));
+        let poll_count = polls.clone();
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d/{task_id}",
+            get(move |Path(task_id): Path<String>| async move {
+                let (status, mode, progress) = match poll_count.fetch_add(1, Ordering::SeqCst) {
+                    0 => ("IN_PROGRESS", "preview", 50),
+                    1 => ("SUCCEEDED", "preview", 100),
+                    2 => ("IN_PROGRESS", "refine", 30),
+                    _ => ("SUCCEEDED", "refine", 100),
+                };
+                Json(json!({ "id": task_id, "status": status, "mode": mode, "progress": progress }))
+            }),
+        )).await;
+        let mut state = test_state(&meshy);
+        state.config = Arc::new(Config { poll_interval: Duration::from_millis(10), ..test_config() });
+        let server = spawn_mock(create_router(state)).await;
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(
+            format!("{}/api/3d/ws/task-1", server.replace("http://", "ws://")),
+        ).await.unwrap();
+        let mut updates = Vec::new();
+        while let Some(frame) = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap() {
+            match frame.unwrap() {
+                WsMessage::Text(text) => updates.push(serde_json::from_str::<serde_json::Value>(&text).unwrap()),
+                WsMessage::Close(_) => break,
+                _ => {}
+            }
+        }
+
+        let stages: Vec<(&str, &str)> = updates
+            .iter()
+            .map(|u| (u["status"].as_str().unwrap(), u["stage"].as_str().unwrap()))
+            .collect();
+        assert_eq!(stages, vec![
+            ("IN_PROGRESS", "preview"),
+            ("SUCCEEDED", "preview"),
+            ("IN_PROGRESS", "refine"),
+            ("SUCCEEDED", "refine"),
+        ]);
+        assert_eq!(polls.load(Ordering::SeqCst), 4);
+    }
+
+    // Transparent 4x4 part with an opaque red 2x2 square in the middle
+    fn transparent_square_png() -> Vec<u8> {
+        let part = image::RgbaImage::from_fn(4, 4, |x, y| {
+            if (1..3).contains(&x) && (1..3).contains(&y) {
+                image::Rgba([255, 0, 0, 255])
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        });
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(part)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[tokio::test]
+    async fn compose_overlays_transparent_part_at_position() {
+        let response = create_router(test_state("http://127.0.0.1:9"))
+            .oneshot(multipart_request(
+                "/compose",
+                &[
+                    ("frame", Some("frame.png"), &png_fixture(16, 16)),
+                    ("part", Some("part.png"), &transparent_square_png()),
+                    ("x", None, b"5"),
+                    ("y", None, b"6"),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let output = image::load_from_memory(&body).unwrap().to_rgba8();
+        assert_eq!(output.dimensions(), (16, 16));
+        // Part placed at (5, 6), so its square covers (6..8, 7..9)
+        assert_eq!(output.get_pixel(6, 7).0, [255, 0, 0, 255]);
+        assert_eq!(output.get_pixel(7, 8).0, [255, 0, 0, 255]);
+        assert_eq!(output.get_pixel(5, 6).0, [40, 80, 120, 255], "transparent pixels keep the frame");
+        assert_eq!(output.get_pixel(0, 0).0, [40, 80, 120, 255]);
+    }
+
+    #[tokio::test]
+    async fn compose_refuses_to_scale_the_part_past_the_frame() {
+        let compose = |scale: &'static [u8]| multipart_request(
+            "/compose",
+            &[
+                ("frame", Some("frame.png"), &png_fixture(16, 16)),
+                ("part", Some("part.png"), &transparent_square_png()),
+                ("scale", None, scale),
+            ],
+        );
+        let app = create_router(test_state("http://127.0.0.1:9"));
+
+        let response = app.clone().oneshot(compose(b"2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.oneshot(compose(b"10")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("larger than the frame (16x16)"), "{:?}", body);
+    }
+
+    #[tokio::test]
+    async fn compose_requires_part_alpha() {
+        let response = create_router(test_state("http://127.0.0.1:9"))
+            .oneshot(multipart_request(
+                "/compose",
+                &[
+                    ("frame", Some("frame.png"), &png_fixture(16, 16)),
+                    ("part", Some("part.png"), &png_fixture(4, 4)),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("alpha channel"));
+    }
+
+    #[tokio::test]
+    async fn mask_preview_keeps_input_dimensions() {
+        let app = Router::new()
+            .route("/mask/preview", post(mask_preview))
+            .with_state(test_state("http://127.0.0.1:9"));
+        let input = png_fixture(96, 64);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/mask/preview",
+                &[
+                    ("image", Some("bike.png"), &input),
+                    ("part", None, b"exhaust"),
+                    ("intensity", None, b"aggressive"),
+                ],
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let preview = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(image::GenericImageView::dimensions(&preview), (96, 64));
+    }
+
+    #[tokio::test]
+    async fn mask_preview_rejects_unknown_part() {
+        let app = Router::new()
+            .route("/mask/preview", post(mask_preview))
+            .with_state(test_state("http://127.0.0.1:9"));
+        let input = png_fixture(32, 32);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/mask/preview",
+                &[("image", Some("bike.png"), &input), ("part", None, b"wheel")],
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn proxy_rejects_non_glb_model() {
+        let cdn = spawn_mock(Router::new().route(
+            "/model.glb",
+            get(|| async { "<html><body>502 Bad Gateway</body></html>" }),
+        )).await;
+        let meshy = meshy_mock_with_model(format!("{}/model.glb", cdn)).await;
+        let app = create_router(test_state(&meshy));
+
+        let response = app
+            .oneshot(Request::get("/api/3d/model/task-1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("invalid GLB"));
+    }
+
+    #[tokio::test]
+    async fn proxy_forwards_valid_glb_model() {
+        let cdn = spawn_mock(Router::new().route(
+            "/model.glb",
+            get(|| async { b"glTF\x02\x00\x00\x00model".to_vec() }),
+        )).await;
+        let meshy = meshy_mock_with_model(format!("{}/model.glb", cdn)).await;
+        let app = create_router(test_state(&meshy));
+
+        let response = app
+            .oneshot(Request::get("/api/3d/model/task-1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.starts_with(b"glTF"));
+    }
+
+    #[tokio::test]
+    async fn proxy_answers_matching_if_none_match_with_304() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let counter = downloads.clone();
+        let cdn = spawn_mock(Router::new().route(
+            "/model.glb",
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { b"glTF\x02\x00\x00\x00model".to_vec() }
+            }),
+        )).await;
+        let meshy = meshy_mock_with_model(format!("{}/model.glb", cdn)).await;
+        let app = create_router(test_state(&meshy));
+
+        let first = app.clone()
+            .oneshot(Request::get("/api/3d/model/task-1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.headers()[header::CACHE_CONTROL], MODEL_CACHE_CONTROL);
+        let etag = first.headers()[header::ETAG].clone();
+        assert_eq!(etag, "\"task-1-glb\"");
+
+        let second = app.clone()
+            .oneshot(
+                Request::get("/api/3d/model/task-1")
+                    .header(header::IF_NONE_MATCH, etag.clone())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(second.headers()[header::ETAG], etag);
+        let body = axum::body::to_bytes(second.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+
+        // The base64 representation has its own tag, so the binary one doesn't match it
+        let inline = app
+            .oneshot(
+                Request::get("/api/3d/model/task-1?encoding=base64")
+                    .header(header::IF_NONE_MATCH, etag)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(inline.status(), StatusCode::OK);
+        assert_eq!(inline.headers()[header::ETAG], "\"task-1-glb-base64\"");
+    }
+
+    // CDN that cuts the first download off halfway, then honours a Range request for the rest
+    async fn flaky_cdn(model: Vec<u8>, ranges: Arc<std::sync::Mutex<Vec<String>>>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for attempt in 0.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let n = socket.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                let range = request.lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .map(|r| r.trim_end_matches('-').to_string());
+
+                if attempt == 0 {
+                    let head = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", model.len());
+                    socket.write_all(head.as_bytes()).await.unwrap();
+                    socket.write_all(&model[..model.len() / 2]).await.unwrap();
+                    continue; // dropping the socket cuts the body short
+                }
+
+                let start: usize = range.clone().unwrap_or_default().parse().unwrap_or(0);
+                ranges.lock().unwrap().push(range.unwrap_or_default());
+                let rest = &model[start..];
+                let head = format!(
+                    "HTTP/1.1 206 Partial Content\r\ncontent-length: {}\r\ncontent-range: bytes {}-{}/{}\r\n\r\n",
+                    rest.len(), start, model.len() - 1, model.len()
+                );
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(rest).await.unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn proxy_resumes_interrupted_model_download() {
+        let mut model = b"glTF\x02\x00\x00\x00".to_vec();
+        model.extend((0..4096u32).map(|i| (i % 251) as u8));
+        let ranges = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cdn = flaky_cdn(model.clone(), ranges.clone()).await;
+        let meshy = meshy_mock_with_model(format!("{}/model.glb", cdn)).await;
+        let app = create_router(test_state(&meshy));
+
+        let response = app
+            .oneshot(Request::get("/api/3d/model/task-1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), model.as_slice());
+        assert_eq!(*ranges.lock().unwrap(), vec![(model.len() / 2).to_string()]);
+    }
+
+    #[tokio::test]
+    async fn proxied_model_is_not_compressed() {
+        let mut model = b"glTF\x02\x00\x00\x00".to_vec();
+        model.extend(std::iter::repeat_n(0u8, 4096));
+        let served = model.clone();
+        let cdn = spawn_mock(Router::new().route(
+            "/model.glb",
+            get(move || async move { served }),
+        )).await;
+        let meshy = meshy_mock_with_model(format!("{}/model.glb", cdn)).await;
+        let app = create_router(test_state(&meshy));
+
+        let response = app
+            .oneshot(
+                Request::get("/api/3d/model/task-1")
+                    .header(header::ACCEPT_ENCODING, "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "model/gltf-binary");
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), model.as_slice());
+    }
+
+    #[tokio::test]
+    async fn proxy_inlines_model_as_base64() {
+        let model = b"glTF\x02\x00\x00\x00model".to_vec();
+        let served = model.clone();
+        let cdn = spawn_mock(Router::new().route(
+            "/model.glb",
+            get(move || async move { served }),
+        )).await;
+        let meshy = meshy_mock_with_model(format!("{}/model.glb", cdn)).await;
+        let app = create_router(test_state(&meshy));
+
+        let response = app
+            .oneshot(Request::get("/api/3d/model/task-1?encoding=base64").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["mime"], "model/gltf-binary");
+        assert_eq!(general_purpose::STANDARD.decode(body["data"].as_str().unwrap()).unwrap(), model);
+    }
+
+    // Poll /generate/result until the job leaves the queue
+    async fn finished_job_result(app: &Router, job_id: &str, query: &str) -> Response {
+        for _ in 0..50 {
+            let response = app.clone()
+                .oneshot(Request::get(format!("/generate/result/{}{}", job_id, query)).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            if response.status() != StatusCode::ACCEPTED {
+                return response;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        panic!("job {} never completed", job_id);
+    }
+
+    #[tokio::test]
+    async fn async_generation_job_completes() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+        let input = png_fixture(8, 8);
+
+        let response = app.clone()
+            .oneshot(multipart_request("/generate/async", &[("image_base", Some("bike.png"), &input)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let submitted: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let job_id = submitted["job_id"].as_str().unwrap().to_string();
+
+        let response = finished_job_result(&app, &job_id, "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(response.headers()["x-image-height"], "2");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), png_fixture(8, 2).as_slice());
+    }
+
+    #[tokio::test]
+    async fn async_generation_result_follows_post_format() {
+        let mut state = test_state("http://127.0.0.1:9");
+        state.post_process = Arc::new(PostProcess::default().with_format("jpeg".parse().unwrap()));
+        let app = create_router(state);
+
+        let response = app.clone()
+            .oneshot(multipart_request("/generate/async", &[("image_base", Some("bike.png"), &png_fixture(8, 8))]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let submitted: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let job_id = submitted["job_id"].as_str().unwrap();
+
+        let response = finished_job_result(&app, job_id, "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+        let length: usize = response.headers()[header::CONTENT_LENGTH].to_str().unwrap().parse().unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.len(), length);
+        assert_eq!(image::guess_format(&body).unwrap(), image::ImageFormat::Jpeg);
+
+        // An explicit ?format still wins over the configured one
+        let response = finished_job_result(&app, job_id, "?format=webp").await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/webp");
+    }
+
+    #[tokio::test]
+    async fn unknown_job_is_not_found() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+
+        let response = app
+            .oneshot(Request::get("/generate/result/missing").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_upload_reports_detected_types() {
+        let dir = unique_temp_path("zephyr_uploads", "d");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut state = test_state("http://127.0.0.1:9");
+        state.config = Arc::new(Config { upload_dir: dir.clone(), ..test_config() });
+
+        let response = create_router(state)
+            .oneshot(multipart_request(
+                "/test",
+                &[
+                    ("image", Some("bike.png"), &png_fixture(4, 4)),
+                    ("notes", Some("notes.txt"), b"not an image"),
+                ],
+            ))
+            .await
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["detected"], json!({
+            "bike.png": "image/png",
+            "notes.txt": "application/octet-stream",
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_upload_keeps_uploads_inside_the_upload_dir() {
+        let root = unique_temp_path("zephyr_uploads", "d");
+        let dir = root.join("uploads");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut state = test_state("http://127.0.0.1:9");
+        state.config = Arc::new(Config { upload_dir: dir.clone(), ..test_config() });
+
+        let response = create_router(state)
+            .oneshot(multipart_request(
+                "/test",
+                &[
+                    ("image", Some("../escape.png"), &png_fixture(4, 4)),
+                    ("image", Some("side.png"), &png_fixture(4, 4)),
+                ],
+            ))
+            .await
+            .unwrap();
+        let escaped = root.join("escape.png").exists();
+        let saved = (dir.join("escape.png").exists(), dir.join("side.png").exists());
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!escaped);
+        assert_eq!(saved, (true, true));
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["files"], json!(["escape.png", "side.png"]));
+    }
+
+    // A directory writes are refused in: read-only permission bits, or sysfs when running
+    // as root, which ignores them
+    #[cfg(unix)]
+    fn read_only_dir() -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = unique_temp_path("zephyr_read_only", "d");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+        match check_writable(&dir) {
+            Ok(()) => {
+                let _ = std::fs::remove_dir(&dir);
+                std::path::PathBuf::from("/sys")
+            }
+            Err(_) => dir,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unwritable_upload_and_temp_dirs_are_named_in_the_error() {
+        let dir = read_only_dir();
+        let mut state = test_state("http://127.0.0.1:9");
+        state.config = Arc::new(Config { upload_dir: dir.clone(), temp_dir: dir.join("staging"), ..test_config() });
+        let app = create_router(state);
+
+        let response = app.clone()
+            .oneshot(multipart_request("/test", &[("image", Some("bike.png"), &png_fixture(4, 4))]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&body), format!("upload directory is not writable: {}", dir.display()));
+
+        let response = app
+            .oneshot(multipart_request("/customize", &[("image", Some("bike.png"), &png_fixture(16, 12)), ("part", None, b"seat")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            format!("temp directory is not writable: {}", dir.join("staging").display()),
+        );
+        let _ = std::fs::remove_dir(&dir);
+    }
+
+    #[tokio::test]
+    async fn animated_gif_uploads_reach_meshy_as_one_png_frame() {
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let gif = crate::test_support::animated_gif_fixture(64, 64, &[[200, 30, 30], [30, 30, 200]]);
+
+        let response = create_router(test_state(&meshy))
+            .oneshot(multipart_request("/api/3d/create", &[("image", Some("spin.gif"), &gif)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let payload = received.lock().await.take().expect("Meshy was called");
+        let data = payload["image_url"].as_str().unwrap().strip_prefix("data:image/png;base64,").unwrap();
+        let frame = image::load_from_memory(&general_purpose::STANDARD.decode(data).unwrap()).unwrap().to_rgba8();
+        assert_eq!(frame.dimensions(), (64, 64));
+        assert_eq!(frame.get_pixel(32, 32).0, [200, 30, 30, 255]);
+    }
+
+    #[tokio::test]
+    async fn empty_multipart_gets_the_same_400_everywhere() {
+        let app = Router::new()
+            .route("/", post(handler))
+            .merge(create_router(test_state("http://127.0.0.1:9")));
+
+        for uri in ["/", "/test", "/extract_exhaust", "/gen_image", "/api/3d/create"] {
+            let response = app.clone().oneshot(multipart_request(uri, &[])).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(body.as_ref(), b"expected multipart form data with image fields", "{}", uri);
+        }
+    }
+
+    #[cfg(feature = "stats")]
+    #[tokio::test]
+    async fn stats_count_requests_per_route() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+        let stats = || async {
+            let response = app.clone()
+                .oneshot(Request::get("/stats").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let before = stats().await;
+        assert_eq!(before["requests_total"], 0);
+        assert_eq!(before["upstream_errors"]["total"], 0);
+
+        for _ in 0..2 {
+            app.clone().oneshot(Request::get("/version").body(Body::empty()).unwrap()).await.unwrap();
+        }
+        // Gemini isn't reachable, so this one also counts as an upstream error
+        app.clone()
+            .oneshot(multipart_request("/extract_seat", &[("image_motorcycle", Some("bike.png"), &png_fixture(8, 8))]))
+            .await
+            .unwrap();
+
+        let after = stats().await;
+        assert_eq!(after["requests_total"], 3);
+        assert_eq!(after["routes"]["/version"], 2);
+        assert_eq!(after["routes"]["/extract_seat"], 1);
+        assert!(after["routes"].get("/stats").is_none());
+        assert_eq!(after["upstream_errors"]["gemini"], 1);
+        assert_eq!(after["upstream_errors"]["total"], 1);
+        assert!(after["uptime_secs"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn meta_options_lists_all_variants() {
+        let app = Router::new().route("/meta/options", get(meta_options));
+
+        let response = app
+            .oneshot(Request::get("/meta/options").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let options: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(options["parts"], json!(["exhaust", "seat", "handlebar"]));
+        assert_eq!(options["intensities"], json!(["minimal", "medium", "aggressive"]));
+        assert_eq!(options["art_styles"], json!(["realistic", "sculpture"]));
+    }
+
+    // Meshy mock that records the payload it was sent
+    async fn meshy_create_mock(received: Arc<tokio::sync::Mutex<Option<serde_json::Value>>>) -> String {
+        spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d",
+            post(move |Json(payload): Json<serde_json::Value>| {
+                let received = received.clone();
+                async move {
+                    *received.lock().await = Some(payload);
+                    Json(json!({ "result": "task-123" }))
+                }
+            }),
+        )).await
+    }
+
+    #[tokio::test]
+    async fn extract_to_3d_feeds_the_extracted_part_to_meshy() {
+        let extracted = png_fixture(64, 64);
+        let encoded = general_purpose::STANDARD.encode(&extracted);
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || async move {
+                Json(json!({ "candidates": [{ "content": { "parts": [{ "inlineData": { "data": encoded } }] } }] }))
+            }),
+        )).await;
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let mut state = test_state(&meshy);
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+        let bike = png_fixture(32, 32);
+        let request = || multipart_request(
+            "/extract_to_3d",
+            &[("image_motorcycle", Some("bike.png"), &bike), ("part", None, b"exhaust")],
+        );
+
+        let response = create_router(state.clone()).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[IMAGE_PROVIDER_HEADER], "gemini");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), json!({ "task_id": "task-123" }));
+        let payload = received.lock().await.clone().unwrap();
+        let image_url = payload["image_url"].as_str().unwrap();
+        assert_eq!(image_url, format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(&extracted)));
+
+        // Meshy down: the extraction worked, so the error names the 3D step
+        let mut meshy_down = state.clone();
+        meshy_down.meshy_client = Arc::new(MeshyClient::with_base_url("test-key", "http://127.0.0.1:9"));
+        let response = create_router(meshy_down).oneshot(request()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).starts_with("3D creation failed: "), "{:?}", body);
+
+        // Gemini down too (and no Bedrock fallback): the extraction step fails first
+        let mut gemini_down = state;
+        gemini_down.gemini = Arc::new(GeminiClient::with_base_url("test-key", "http://127.0.0.1:9"));
+        let response = create_router(gemini_down).oneshot(request()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).starts_with("Extraction failed: "), "{:?}", body);
+    }
+
+    #[tokio::test]
+    async fn create_3d_skips_corrupt_image_and_uses_valid_one() {
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let app = create_router(test_state(&meshy));
+        let valid = png_fixture(64, 64);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[
+                    ("image_broken", Some("broken.png"), b"definitely not a png"),
+                    ("image_ok", Some("ok.png"), &valid),
+                ],
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(created["task_id"], "task-123");
+
+        let payload = received.lock().await.clone().unwrap();
+        assert!(payload["image_url"].as_str().unwrap().starts_with("data:image/png;base64,"));
+    }
+
+    #[tokio::test]
+    async fn create_3d_lists_reasons_when_no_image_is_valid() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+
+        let response = app
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[
+                    ("image_broken", Some("broken.png"), b"definitely not a png"),
+                    ("image_empty", Some("empty.png"), b""),
+                ],
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let message = String::from_utf8_lossy(&body);
+        assert!(message.contains("image_broken: not a recognized image"));
+        assert!(message.contains("image field 'image_empty' was empty"));
+    }
+
+    #[tokio::test]
+    async fn create_3d_forwards_pbr_toggle() {
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let app = create_router(test_state(&meshy));
+        let valid = png_fixture(64, 64);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("enable_pbr", None, b"false")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let payload = received.lock().await.clone().unwrap();
+        assert_eq!(payload["enable_pbr"], false);
+        assert_eq!(payload["should_remesh"], true);
+    }
+
+    #[tokio::test]
+    async fn create_3d_forwards_untextured_request_and_rejects_unsupported_options() {
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let app = create_router(test_state(&meshy));
+        let valid = png_fixture(64, 64);
+
+        let response = app.clone()
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[
+                    ("image", Some("ok.png"), &valid),
+                    ("should_texture", None, b"false"),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let payload = received.lock().await.take().unwrap();
+        assert_eq!(payload["should_texture"], false);
+        assert_eq!(payload["enable_pbr"], false);
+        assert!(payload.get("negative_prompt").is_none());
+
+        let response = app.clone()
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("negative_prompt", None, b"rider, luggage")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"negative_prompt is not supported for image-to-3D");
+
+        let response = app
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("should_texture", None, b"false"), ("enable_pbr", None, b"true")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"enable_pbr requires should_texture=true");
+        assert!(received.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn create_3d_refuses_uploads_the_moderation_hook_flags() {
+        let hook = spawn_mock(
+            Router::new()
+                .route("/allow", post(|| async { Json(json!({ "allowed": true, "labels": [] })) }))
+                .route("/block", post(|| async { Json(json!({ "allowed": false, "labels": ["weapons"] })) })),
+        ).await;
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let valid = png_fixture(64, 64);
+        let request = || multipart_request("/api/3d/create", &[("image", Some("ok.png"), &valid)]);
+        let with_hook = |url: String| {
+            let mut state = test_state(&meshy);
+            state.moderation = Some(Arc::new(ModerationClient::new(url)));
+            create_router(state)
+        };
+
+        let response = with_hook(format!("{}/block", hook)).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"Upload rejected by content moderation: weapons");
+        assert!(received.lock().await.is_none());
+
+        // Fails closed by default when the hook is down
+        let response = with_hook("http://127.0.0.1:9/classify".to_string()).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(received.lock().await.is_none());
+
+        let response = with_hook(format!("{}/allow", hook)).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(received.lock().await.take().is_some());
+    }
+
+    #[tokio::test]
+    async fn create_3d_screens_the_texture_image_too() {
+        let texture = png_fixture(8, 8);
+        let texture_b64 = general_purpose::STANDARD.encode(&texture);
+        // Flags only the texture, so a refusal proves it was screened separately
+        let hook = spawn_mock(Router::new().route(
+            "/classify",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let flagged = body["image"] == texture_b64;
+                async move { Json(json!({ "allowed": !flagged, "labels": ["graphic"] })) }
+            }),
+        )).await;
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let mut state = test_state(&meshy);
+        state.moderation = Some(Arc::new(ModerationClient::new(format!("{}/classify", hook))));
+        let valid = png_fixture(64, 64);
+
+        let response = create_router(state)
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("texture_image", Some("texture.png"), &texture)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"Upload rejected by content moderation: graphic");
+        assert!(received.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn create_3d_forwards_texture_guidance() {
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let app = create_router(test_state(&meshy));
+        let valid = png_fixture(64, 64);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[
+                    ("image", Some("ok.png"), &valid),
+                    ("texture_prompt", None, b"brushed aluminium"),
+                    ("texture_image", Some("texture.png"), &valid),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let payload = received.lock().await.clone().unwrap();
+        assert_eq!(payload["texture_prompt"], "brushed aluminium");
+        assert!(payload["texture_image_url"].as_str().unwrap().starts_with("data:image/png;base64,"));
+    }
+
+    #[tokio::test]
+    async fn create_3d_enforces_model_allow_list() {
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let mut state = test_state(&meshy);
+        state.config = Arc::new(Config {
+            allowed_models: vec!["meshy-4".to_string(), "latest".to_string()],
+            ..test_config()
+        });
+        let app = create_router(state);
+        let valid = png_fixture(64, 64);
+
+        let response = app.clone()
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("ai_model", None, b"meshy-5")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            "Model 'meshy-5' is not allowed here; allowed models: meshy-4, latest"
+        );
+        assert!(received.lock().await.is_none(), "a rejected model must not reach Meshy");
+
+        let response = app
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("ai_model", None, b"meshy-4")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(received.lock().await.clone().unwrap()["ai_model"], "meshy-4");
+    }
+
+    #[tokio::test]
+    async fn create_3d_rejects_slivers_but_accepts_square_images() {
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let app = create_router(test_state(&meshy));
+
+        let sliver = png_fixture(50, 2000);
+        let response = app.clone()
+            .oneshot(multipart_request("/api/3d/create", &[("image", Some("sliver.png"), &sliver)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            "image is 50x2000; 3D reconstruction needs both sides at least 64 px and an aspect ratio \
+            no more extreme than 4:1 (this one is 40.0:1)"
+        );
+        assert!(received.lock().await.is_none(), "a rejected image must not reach Meshy");
+
+        let square = png_fixture(256, 256);
+        let response = app
+            .oneshot(multipart_request("/api/3d/create", &[("image", Some("square.png"), &square)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(received.lock().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn create_3d_forwards_art_style_and_rejects_unknown_ones() {
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let app = create_router(test_state(&meshy));
+        let valid = png_fixture(64, 64);
+
+        let response = app.clone()
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("art_style", None, b"cartoon")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            "Invalid art_style: expected one of realistic|sculpture, got 'cartoon'"
+        );
+        assert!(received.lock().await.is_none());
+
+        let response = app
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("art_style", None, b"Sculpture")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(received.lock().await.clone().unwrap()["art_style"], "sculpture");
+    }
+
+    #[tokio::test]
+    async fn create_3d_rejects_non_boolean_toggle() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+        let valid = png_fixture(64, 64);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/api/3d/create",
+                &[("image", Some("ok.png"), &valid), ("should_remesh", None, b"maybe")],
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn version_reports_crate_version() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+
+        let response = app
+            .oneshot(Request::get("/version").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let version: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert!(version["git_hash"].is_string());
+        assert!(version["build_timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn customize_with_mask_accepts_matching_dimensions() {
+        let inpainted = png_fixture(16, 12);
+        let bedrock = bedrock_mock(inpainted.clone()).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+        let image = png_fixture(16, 12);
+        let mask = png_fixture(16, 12);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize/with_mask",
+                &[
+                    ("image", Some("bike.png"), &image),
+                    ("mask", Some("mask.png"), &mask),
+                    ("part", None, b"exhaust"),
+                    ("bike_desc", None, b"cafe racer"),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), inpainted.as_slice());
+    }
+
+    #[tokio::test]
+    async fn customize_with_mask_rejects_mismatched_dimensions() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+        let image = png_fixture(16, 12);
+        let mask = png_fixture(8, 8);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize/with_mask",
+                &[("image", Some("bike.png"), &image), ("mask", Some("mask.png"), &mask)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let message = String::from_utf8(body.to_vec()).unwrap();
+        assert!(message.contains("Mask is 8x8 but image is 16x12"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn create_3d_reuses_task_for_repeated_idempotency_key() {
+        let creates = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = creates.clone();
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d",
+            post(move || {
+                let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async move { Json(json!({ "result": format!("task-{}", n) })) }
+            }),
+        )).await;
+        let app = create_router(test_state(&meshy));
+        let valid = png_fixture(64, 64);
+
+        let mut task_ids = Vec::new();
+        for _ in 0..2 {
+            let mut request = multipart_request("/api/3d/create", &[("image", Some("ok.png"), &valid)]);
+            request.headers_mut().insert("Idempotency-Key", "retry-me".parse().unwrap());
+
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            task_ids.push(created["task_id"].as_str().unwrap().to_string());
+        }
+
+        assert_eq!(creates.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(task_ids, vec!["task-0", "task-0"]);
+    }
+
+    #[tokio::test]
+    async fn concurrent_retries_with_one_idempotency_key_create_one_task() {
+        let creates = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = creates.clone();
+        // Slow enough that the retry lands while the first create is still waiting on Meshy
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d",
+            post(move || {
+                let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async move {
+                    sleep(Duration::from_millis(200)).await;
+                    Json(json!({ "result": format!("task-{}", n) }))
+                }
+            }),
+        )).await;
+        let app = create_router(test_state(&meshy));
+        let valid = png_fixture(64, 64);
+        let create = || {
+            let mut request = multipart_request("/api/3d/create", &[("image", Some("ok.png"), &valid)]);
+            request.headers_mut().insert("Idempotency-Key", "retry-me".parse().unwrap());
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                created["task_id"].as_str().unwrap().to_string()
+            }
+        };
+
+        let (first, second) = tokio::join!(create(), create());
+
+        assert_eq!(creates.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!((first.as_str(), second.as_str()), ("task-0", "task-0"));
+    }
+
+    #[tokio::test]
+    async fn customize_with_mask_reencodes_to_requested_jpeg() {
+        let bedrock = bedrock_mock(png_fixture(16, 12)).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+        let image = png_fixture(16, 12);
+        let mask = png_fixture(16, 12);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize/with_mask?format=jpeg&quality=75",
+                &[("image", Some("bike.png"), &image), ("mask", Some("mask.png"), &mask)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..2], &[0xFF, 0xD8]);
+    }
+
+    #[tokio::test]
+    async fn image_responses_report_dimensions_and_length() {
+        let response = customize_with_accept("image/png").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers().clone();
+        assert_eq!(headers["x-image-width"], "16");
+        assert_eq!(headers["x-image-height"], "12");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(headers[header::CONTENT_LENGTH], body.len().to_string().as_str());
+    }
+    // Gemini mock that is down: every call gets a 503
+    async fn gemini_outage_mock() -> String {
+        spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(|| async {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Json(json!({ "error": { "code": 503, "message": "The model is overloaded" } })),
+                )
+            }),
+        )).await
+    }
+
+    async fn extract_during_gemini_outage(bedrock_fallback: bool) -> Response {
+        let gemini = gemini_outage_mock().await;
+        let bedrock = bedrock_mock(png_fixture(16, 12)).await;
+        let mut state = test_state_with_bedrock("http://127.0.0.1:9", &bedrock);
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+        state.config = Arc::new(Config { bedrock_fallback, ..test_config() });
+        let image = png_fixture(16, 12);
+
+        create_router(state)
+            .oneshot(multipart_request("/extract_exhaust", &[("image_motorcycle", Some("bike.png"), &image)]))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn extract_falls_back_to_bedrock_when_gemini_is_down() {
+        let response = extract_during_gemini_outage(true).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[IMAGE_PROVIDER_HEADER], "bedrock");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(image::load_from_memory(&body).is_ok());
+    }
+
+    #[tokio::test]
+    async fn generate_and_extract_return_a_data_url_for_base64_encoding() {
+        let encoded = general_purpose::STANDARD.encode(png_fixture(16, 12));
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || async move {
+                Json(json!({ "candidates": [{ "content": { "parts": [{ "inlineData": { "data": encoded } }] } }] }))
+            }),
+        )).await;
+        let mut state = test_state("http://127.0.0.1:9");
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+        let app = create_router(state);
+        let image = png_fixture(16, 12);
+
+        for uri in ["/gen_image?encoding=base64", "/extract_seat?encoding=base64"] {
+            let response = app.clone()
+                .oneshot(multipart_request(uri, &[("image_motorcycle", Some("bike.png"), &image)]))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+            assert_eq!(response.headers()[IMAGE_PROVIDER_HEADER], "gemini");
+
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let data = json["image"].as_str().unwrap().strip_prefix("data:image/png;base64,").unwrap();
+            let png = general_purpose::STANDARD.decode(data).unwrap();
+            assert_eq!(json["size_bytes"], png.len());
+            assert_eq!(image::load_from_memory(&png).unwrap().width(), 16);
+        }
+
+        let response = app
+            .oneshot(multipart_request("/gen_image?encoding=hex", &[("image_motorcycle", Some("bike.png"), &image)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn open_circuit_fast_fails_without_calling_gemini() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post({
+                let calls = calls.clone();
+                move || async move {
+                    calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": { "code": 503, "message": "overloaded" } })))
+                }
+            }),
+        )).await;
+        let mut state = test_state("http://127.0.0.1:9");
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+        state.breakers = Arc::new(ProviderBreakers::new(2, Duration::from_secs(60)));
+        let app = create_router(state);
+        let image = png_fixture(16, 12);
+        let extract = || multipart_request("/extract_seat", &[("image_motorcycle", Some("bike.png"), &image)]);
+
+        for _ in 0..2 {
+            let response = app.clone().oneshot(extract()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        let response = app.oneshot(extract()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).starts_with("gemini is failing"), "{:?}", body);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2, "open circuit must not reach Gemini");
+    }
+
+    #[tokio::test]
+    async fn extract_fails_during_outage_when_fallback_is_off() {
+        let response = extract_during_gemini_outage(false).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Gemini API error (503)"));
+    }
+
+    #[tokio::test]
+    async fn provider_rate_limits_pass_through_as_429() {
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(|| async {
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, "17")],
+                    Json(json!({ "error": { "code": 429, "message": "Resource has been exhausted" } })),
+                )
+            }),
+        )).await;
+        let meshy = spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d",
+            post(|| async { (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "60")], "Too many tasks") }),
+        )).await;
+        let mut state = test_state(&meshy);
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+        let app = create_router(state);
+        let image = png_fixture(64, 64);
+
+        let response = app.clone()
+            .oneshot(multipart_request("/extract_exhaust", &[("image_motorcycle", Some("bike.png"), &image)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "17");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "rate_limited");
+        assert_eq!(body["provider"], "gemini");
+        assert_eq!(body["retry_after"], 17);
+        assert!(body["message"].as_str().unwrap().contains("Resource has been exhausted"), "{}", body);
+
+        let response = app
+            .oneshot(multipart_request("/api/3d/create", &[("image", Some("bike.png"), &image)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+    }
+
+    #[tokio::test]
+    async fn safety_blocks_come_back_as_422() {
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(|| async { Json(json!({ "promptFeedback": { "blockReason": "PROHIBITED_CONTENT" } })) }),
+        )).await;
+        let mut state = test_state("http://127.0.0.1:9");
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+
+        let response = create_router(state)
+            .oneshot(multipart_request("/extract_seat", &[("image_motorcycle", Some("bike.png"), &png_fixture(8, 8))]))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            "The image provider refused this input for safety reasons: PROHIBITED_CONTENT"
+        );
+    }
+
+    #[tokio::test]
+    async fn transparent_extract_returns_rgba_with_clear_corners() {
+        // Gemini's extraction: a dark part on a white background
+        let mut part = image::RgbImage::from_pixel(32, 32, image::Rgb([255, 255, 255]));
+        for y in 10..22 {
+            for x in 8..24 {
+                part.put_pixel(x, y, image::Rgb([60, 60, 70]));
+            }
+        }
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(part)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let encoded = general_purpose::STANDARD.encode(&png);
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || async move {
+                Json(json!({ "candidates": [{ "content": { "parts": [{ "inlineData": { "data": encoded } }] } }] }))
+            }),
+        )).await;
+        let mut state = test_state("http://127.0.0.1:9");
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+
+        let response = create_router(state)
+            .oneshot(multipart_request(
+                "/extract_exhaust?transparent=true",
+                &[("image_motorcycle", Some("bike.png"), &png_fixture(32, 32))],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let output = image::load_from_memory(&body).unwrap();
+        assert!(output.color().has_alpha());
+        let output = output.to_rgba8();
+        for (x, y) in [(0, 0), (31, 0), (0, 31), (31, 31)] {
+            assert_eq!(output.get_pixel(x, y)[3], 0, "corner ({}, {})", x, y);
+        }
+        assert_eq!(output.get_pixel(16, 16).0, [60, 60, 70, 255]);
+    }
+
+    #[tokio::test]
+    async fn match_input_letterboxes_the_extract_to_the_upload_size() {
+        // Gemini answers with a 40x20 part whatever the input size
+        let encoded = general_purpose::STANDARD.encode(png_fixture(40, 20));
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || async move {
+                Json(json!({ "candidates": [{ "content": { "parts": [{ "inlineData": { "data": encoded } }] } }] }))
+            }),
+        )).await;
+        let mut state = test_state("http://127.0.0.1:9");
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+        let app = create_router(state);
+        let extract = |uri: &'static str, input: Vec<u8>| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(multipart_request(uri, &[("image_motorcycle", Some("bike.png"), &input)]))
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let resized = response.headers().get(RESIZED_HEADER).cloned();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (image::load_from_memory(&body).unwrap(), resized)
+            }
+        };
+
+        let (plain, resized) = extract("/extract_exhaust", png_fixture(32, 32)).await;
+        assert_eq!((plain.width(), plain.height()), (40, 20));
+        assert!(resized.is_none());
+
+        let (matched, resized) = extract("/extract_exhaust?match_input=true", png_fixture(32, 32)).await;
+        assert_eq!((matched.width(), matched.height()), (32, 32));
+        assert_eq!(resized.unwrap(), "true");
+        // 40x20 scales to 32x16, leaving white bars above and below
+        let matched = matched.to_rgba8();
+        assert_eq!(matched.get_pixel(16, 0).0, [255, 255, 255, 255]);
+        assert_eq!(matched.get_pixel(16, 16).0, [40, 80, 120, 255]);
+
+        let (same, resized) = extract("/extract_exhaust?match_input=true", png_fixture(40, 20)).await;
+        assert_eq!((same.width(), same.height()), (40, 20));
+        assert_eq!(resized.unwrap(), "false");
+    }
+
+    #[tokio::test]
+    async fn transparent_extract_refuses_explicit_jpeg() {
+        let response = create_router(test_state("http://127.0.0.1:9"))
+            .oneshot(multipart_request(
+                "/extract_seat?transparent=true&format=jpeg",
+                &[("image_motorcycle", Some("bike.png"), &png_fixture(8, 8))],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn generate_with_refs_sends_the_prompt_and_every_image() {
+        let generated = png_fixture(12, 12);
+        let encoded = general_purpose::STANDARD.encode(&generated);
+        let request = Arc::new(std::sync::Mutex::new(None));
+        let recorded = request.clone();
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                *recorded.lock().unwrap() = Some(body);
+                Json(json!({ "candidates": [{ "content": { "parts": [{ "inlineData": { "data": encoded } }] } }] }))
+            }),
+        )).await;
+        let mut state = test_state("http://127.0.0.1:9");
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+        let app = create_router(state);
+        let (first, second) = (png_fixture(8, 8), png_fixture(9, 9));
+
+        let response = app.clone()
+            .oneshot(multipart_request(
+                "/generate/with_refs",
+                &[
+                    ("prompt", None, b"Put the tank from the second image on the first bike"),
+                    ("image_base", Some("bike.png"), &first),
+                    ("image_tank", Some("tank.png"), &second),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[IMAGE_PROVIDER_HEADER], "gemini");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), generated.as_slice());
+
+        let sent = request.lock().unwrap().take().unwrap();
+        let parts = sent["contents"][0]["parts"].as_array().unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0]["text"], "Put the tank from the second image on the first bike");
+        assert_eq!(parts[1]["inline_data"]["data"], general_purpose::STANDARD.encode(&first));
+        assert_eq!(parts[2]["inline_data"]["data"], general_purpose::STANDARD.encode(&second));
+
+        // A blank prompt or no images never reach the provider
+        for (fields, message) in [
+            (vec![("prompt", None, b"  ".as_slice()), ("image", Some("bike.png"), first.as_slice())], Some("field 'prompt' was empty")),
+            (vec![("image", Some("bike.png"), first.as_slice())], Some("missing required field 'prompt'")),
+            (vec![("prompt", None, b"a bike".as_slice())], None),
+        ] {
+            let response = app.clone().oneshot(multipart_request("/generate/with_refs", &fields)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            if let Some(message) = message {
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                assert_eq!(String::from_utf8_lossy(&body), message);
+            }
+        }
+        assert!(request.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn extract_batch_reports_failures_in_input_order() {
+        let (good_a, bad, good_b) = (png_fixture(8, 8), png_fixture(9, 9), png_fixture(10, 10));
+        let bad_encoded = general_purpose::STANDARD.encode(&bad);
+        // Gemini rejects exactly one of the three images
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                let data = body["contents"][0]["parts"][1]["inline_data"]["data"].as_str().unwrap().to_string();
+                if data == bad_encoded {
+                    return Json(json!({ "error": { "code": 400, "message": "Image could not be processed" } }));
+                }
+                Json(json!({
+                    "candidates": [{ "content": { "parts": [{ "inlineData": { "data": data } }] } }]
+                }))
+            }),
+        )).await;
+        let mut state = test_state("http://127.0.0.1:9");
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+
+        let response = create_router(state)
+            .oneshot(multipart_request(
+                "/extract/batch",
+                &[
+                    ("part", None, b"exhaust"),
+                    ("image", Some("a.png"), &good_a),
+                    ("image", Some("b.png"), &bad),
+                    ("image", Some("c.png"), &good_b),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let items: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let items = items.as_array().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0]["index"], 0);
+        assert_eq!(items[0]["image"], general_purpose::STANDARD.encode(&good_a));
+        assert_eq!(items[1]["index"], 1);
+        assert!(items[1]["image"].is_null());
+        assert!(items[1]["error"].as_str().unwrap().contains("Image could not be processed"));
+        assert_eq!(items[2]["index"], 2);
+        assert_eq!(items[2]["image"], general_purpose::STANDARD.encode(&good_b));
+        assert!(items[2].get("error").is_none());
+    }
+
+    #[tokio::test]
+    async fn extract_batch_rejects_unknown_part() {
+        let image = png_fixture(8, 8);
+
+        let response = create_router(test_state("http://127.0.0.1:9"))
+            .oneshot(multipart_request(
+                "/extract/batch",
+                &[("part", None, b"wheel"), ("image", Some("a.png"), &image)],
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"Invalid part: expected one of exhaust|seat|frame, got 'wheel'");
+    }
+
+    #[tokio::test]
+    async fn extract_reports_missing_and_empty_image_fields() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+
+        let response = app
+            .clone()
+            .oneshot(multipart_request("/extract_seat", &[("other", None, b"x")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"missing required field 'image_motorcycle'");
+
+        let response = app
+            .oneshot(multipart_request("/extract_seat", &[("image_motorcycle", Some("bike.png"), b"")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"image field 'image_motorcycle' was empty");
+    }
+
+    #[tokio::test]
+    async fn create_3d_reports_missing_and_empty_image_fields() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+
+        let response = app
+            .clone()
+            .oneshot(multipart_request("/api/3d/create", &[("enable_pbr", None, b"true")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"missing required field 'image'");
+
+        let response = app
+            .oneshot(multipart_request("/api/3d/create", &[("image", Some("bike.png"), b"")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"image field 'image' was empty");
+    }
+
+    #[tokio::test]
+    async fn customize_reports_every_invalid_field() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+        let image = png_fixture(16, 12);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize",
+                &[
+                    ("image", Some("bike.png"), &image),
+                    ("part", None, b"wheel"),
+                    ("intensity", None, b"extreme"),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({
+            "errors": [
+                { "field": "part", "message": "expected one of exhaust|seat|handlebar, got 'wheel'" },
+                { "field": "intensity", "message": "expected one of minimal|medium|aggressive, got 'extreme'" },
+            ]
+        }));
+    }
+
+    #[tokio::test]
+    async fn customize_can_return_the_mask_it_used() {
+        let bedrock = bedrock_mock(png_fixture(16, 12)).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+        let image = png_fixture(16, 12);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize?include_mask=true",
+                &[("image", Some("bike.png"), &image), ("part", None, b"exhaust")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let image = general_purpose::STANDARD.decode(body["image"].as_str().unwrap()).unwrap();
+        let mask = general_purpose::STANDARD.decode(body["mask"].as_str().unwrap()).unwrap();
+        assert!(!image.is_empty());
+        assert!(!mask.is_empty());
+        assert_eq!(body["content_type"], "image/png");
+        let mask = image::load_from_memory(&mask).unwrap();
+        assert_eq!((mask.width(), mask.height()), (16, 12));
+    }
+
+    #[tokio::test]
+    async fn customize_uses_gemini_when_configured_as_the_backend() {
+        let edited = general_purpose::STANDARD.encode(png_fixture(16, 12));
+        let gemini = spawn_mock(Router::new().route(
+            "/v1beta/models/{model}",
+            post(move || async move {
+                Json(json!({ "candidates": [{ "content": { "parts": [{ "inlineData": { "data": edited } }] } }] }))
+            }),
+        )).await;
+        // Bedrock is unreachable, so only the Gemini backend can succeed
+        let mut state = test_state("http://127.0.0.1:9");
+        state.gemini = Arc::new(GeminiClient::with_base_url("test-key", gemini));
+        state.config = Arc::new(Config { customize_backend: CustomizeBackend::Gemini, ..test_config() });
+        let image = png_fixture(16, 12);
+
+        let response = create_router(state)
+            .oneshot(multipart_request(
+                "/customize?include_mask=true",
+                &[("image", Some("bike.png"), &image), ("part", None, b"exhaust")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let image = general_purpose::STANDARD.decode(body["image"].as_str().unwrap()).unwrap();
+        let mask = general_purpose::STANDARD.decode(body["mask"].as_str().unwrap()).unwrap();
+        assert_eq!(image::load_from_memory(&image).unwrap().width(), 16);
+        assert_eq!(image::load_from_memory(&mask).unwrap().height(), 12);
+    }
+
+    #[tokio::test]
+    async fn customize_stages_uploads_in_temp_dir_and_cleans_up() {
+        let dir = unique_temp_path("zephyr_temp_dir", "d");
+        let staged = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let encoded = general_purpose::STANDARD.encode(png_fixture(16, 12));
+        // Bedrock runs while the upload is staged, so look at the temp dir from inside the mock
+        let bedrock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post({
+                let (dir, staged) = (dir.clone(), staged.clone());
+                move || async move {
+                    let files = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name());
+                    staged.lock().unwrap().extend(files);
+                    Json(json!({ "artifacts": [{ "base64": encoded, "finishReason": "SUCCESS" }] }))
+                }
+            }),
+        )).await;
+        let mut state = test_state_with_bedrock("http://127.0.0.1:9", &bedrock);
+        state.config = Arc::new(Config { temp_dir: dir.clone(), ..test_config() });
+
+        let response = create_router(state)
+            .oneshot(multipart_request(
+                "/customize",
+                &[("image", Some("bike.png"), &png_fixture(16, 12)), ("part", None, b"exhaust")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let staged = staged.lock().unwrap().clone();
+        assert_eq!(staged.len(), 1, "{:?}", staged);
+        assert!(staged[0].to_string_lossy().starts_with("customize_base_"), "{:?}", staged);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0, "staged upload must be removed");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    async fn customize_with_accept(accept: &str) -> Response {
+        let bedrock = bedrock_mock(png_fixture(16, 12)).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+        let image = png_fixture(16, 12);
+
+        let mut request = multipart_request(
+            "/customize/with_mask",
+            &[("image", Some("bike.png"), &image), ("mask", Some("mask.png"), &image)],
+        );
+        request.headers_mut().insert(header::ACCEPT, accept.parse().unwrap());
+
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn accept_webp_returns_webp() {
+        let response = customize_with_accept("image/webp").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/webp");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[8..12], b"WEBP");
+    }
+
+    #[tokio::test]
+    async fn accept_any_falls_back_to_png() {
+        let response = customize_with_accept("*/*").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..4], &[0x89, b'P', b'N', b'G']);
+    }
+    // In-memory S3 mock serving path-style GET/PUT object requests
+    async fn s3_mock() -> String {
+        let objects: Arc<tokio::sync::Mutex<HashMap<String, Bytes>>> = Arc::default();
+        let stored = objects.clone();
+
+        spawn_mock(Router::new().route(
+            "/{bucket}/{*key}",
+            get(move |Path((_, key)): Path<(String, String)>| {
+                let objects = objects.clone();
+                async move {
+                    match objects.lock().await.get(&key) {
+                        Some(data) => data.clone().into_response(),
+                        None => (
+                            StatusCode::NOT_FOUND,
+                            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                            <Error><Code>NoSuchKey</Code><Message>missing</Message></Error>",
+                        ).into_response(),
+                    }
+                }
+            })
+            .put(move |Path((_, key)): Path<(String, String)>, body: Bytes| {
+                let stored = stored.clone();
+                async move {
+                    stored.lock().await.insert(key, body);
+                    StatusCode::OK
+                }
+            }),
+        )).await
+    }
+
+    #[tokio::test]
+    async fn create_3d_accepts_an_s3_input_image() {
+        let s3 = s3_mock().await;
+        let image = png_fixture(64, 64);
+        s3_client(&s3).put_object()
+            .bucket("inputs")
+            .key("bikes/cafe.png")
+            .body(image.clone().into())
+            .send()
+            .await
+            .unwrap();
+
+        let received = Arc::new(tokio::sync::Mutex::new(None));
+        let meshy = meshy_create_mock(received.clone()).await;
+        let mut state = test_state(&meshy);
+        state.s3_inputs = Some(Arc::new(S3Inputs::new(s3_client(&s3), vec!["inputs".to_string()])));
+        let app = create_router(state);
+        let create = |body: serde_json::Value| {
+            app.clone().oneshot(
+                Request::post("/api/3d/create")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+
+        let response = create(json!({ "s3_uri": "s3://inputs/bikes/cafe.png", "enable_pbr": false })).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["task_id"], "task-123");
+
+        let payload = received.lock().await.take().expect("Meshy was called");
+        let data_url = payload["image_url"].as_str().unwrap();
+        assert_eq!(data_url, format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(&image)));
+        assert_eq!(payload["enable_pbr"], false);
+
+        let response = create(json!({ "s3_uri": "s3://private/keys.png" })).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = create(json!({ "s3_uri": "s3://inputs/missing.png" })).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(received.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn s3_inputs_are_refused_when_not_configured() {
+        let response = create_router(test_state("http://127.0.0.1:9"))
+            .oneshot(
+                Request::post("/extract_seat")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(json!({ "s3_uri": "s3://inputs/bike.png" }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.as_ref(), b"S3 inputs are not enabled on this server");
+    }
+
+    #[tokio::test]
+    async fn proxy_serves_repeat_downloads_from_model_cache() {
+        let cdn_hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = cdn_hits.clone();
+        let cdn = spawn_mock(Router::new().route(
+            "/model.glb",
+            get(move || {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async { b"glTF\x02\x00\x00\x00model".to_vec() }
+            }),
+        )).await;
+        let meshy = meshy_mock_with_model(format!("{}/model.glb", cdn)).await;
+        let s3 = s3_mock().await;
+
+        let mut state = test_state(&meshy);
+        state.model_cache = Some(Arc::new(ModelCache::new(s3_client(&s3), "models-bucket")));
+        let app = create_router(state);
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(Request::get("/api/3d/model/task-1").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(body.as_ref(), b"glTF\x02\x00\x00\x00model");
+        }
+
+        assert_eq!(cdn_hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn customize_options_returns_every_intensity() {
+        let generated = png_fixture(16, 12);
+        let bedrock = bedrock_mock(generated.clone()).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+        let image = png_fixture(16, 12);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize/options",
+                &[("image", Some("bike.png"), &image), ("part", None, b"seat")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let options: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let intensities: Vec<&str> = options.iter().map(|o| o["intensity"].as_str().unwrap()).collect();
+
+        assert_eq!(intensities, vec!["minimal", "medium", "aggressive"]);
+        for option in &options {
+            let image = general_purpose::STANDARD.decode(option["image"].as_str().unwrap()).unwrap();
+            assert_eq!(image, generated);
+        }
+    }
+
+    #[tokio::test]
+    async fn customize_reports_the_random_seed_it_sent_to_bedrock() {
+        let image = png_fixture(16, 12);
+        let encoded = general_purpose::STANDARD.encode(&image);
+        let seeds = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = seeds.clone();
+        let bedrock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(move |body: Bytes| async move {
+                let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                recorded.lock().unwrap().push(request["seed"].clone());
+                Json(json!({ "artifacts": [{ "base64": encoded, "finishReason": "SUCCESS" }] }))
+            }),
+        )).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+
+        let response = app.clone()
+            .oneshot(multipart_request("/customize", &[("image", Some("bike.png"), &image), ("part", None, b"seat")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let seed: u32 = response.headers()[GENERATION_SEED_HEADER].to_str().unwrap().parse().unwrap();
+        assert_eq!(seeds.lock().unwrap().as_slice(), &[json!(seed)]);
+
+        // A seed the caller supplies is used and echoed as-is, in JSON bodies too
+        let response = app
+            .oneshot(multipart_request(
+                "/customize?include_mask=true",
+                &[("image", Some("bike.png"), &image), ("part", None, b"seat"), ("seed", None, b"4294967295")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[GENERATION_SEED_HEADER], "4294967295");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["seed"], json!(u32::MAX));
+        assert_eq!(seeds.lock().unwrap()[1], json!(u32::MAX));
+    }
+
+    #[tokio::test]
+    async fn customize_output_carries_the_configured_watermark() {
+        use crate::util::post_process::Corner;
+
+        let bedrock = bedrock_mock(png_fixture(40, 30)).await;
+        let mut state = test_state_with_bedrock("http://127.0.0.1:9", &bedrock);
+        let logo = image::RgbaImage::from_pixel(6, 6, image::Rgba([250, 20, 20, 255]));
+        state.post_process = Arc::new(PostProcess::default().with_watermark(Watermark::new(logo, Corner::TopLeft)));
+
+        let response = create_router(state)
+            .oneshot(multipart_request(
+                "/customize",
+                &[("image", Some("bike.png"), &png_fixture(40, 30)), ("part", None, b"seat")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let output = image::load_from_memory(&body).unwrap().to_rgba8();
+        assert_eq!(output.dimensions(), (40, 30));
+        // Inset by 2% of the 30 px side, i.e. none
+        assert_eq!(output.get_pixel(0, 0).0, [250, 20, 20, 255]);
+        assert_eq!(output.get_pixel(5, 5).0, [250, 20, 20, 255]);
+        assert_eq!(output.get_pixel(6, 6).0, [40, 80, 120, 255]);
+    }
+
+    #[tokio::test]
+    async fn customize_options_ws_streams_each_intensity_as_it_finishes() {
+        use std::sync::atomic::AtomicUsize;
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        // The first call to arrive is the slowest, so results complete in reverse. The calls
+        // wait for each other first, since fitting the input to an SDXL size spaces them out
+        let calls = Arc::new(AtomicUsize::new(0));
+        let arrived = Arc::new(tokio::sync::Barrier::new(3));
+        let bedrock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(move || async move {
+                let call = calls.fetch_add(1, Ordering::SeqCst) as u32;
+                arrived.wait().await;
+                tokio::time::sleep(Duration::from_millis(300 - 150 * call as u64)).await;
+                Json(json!({
+                    "artifacts": [{
+                        "base64": general_purpose::STANDARD.encode(png_fixture(16, 10 + call)),
+                        "finishReason": "SUCCESS"
+                    }]
+                }))
+            }),
+        )).await;
+        let server = spawn_mock(create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock))).await;
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(
+            format!("{}/customize/options/ws", server.replace("http://", "ws://")),
+        ).await.unwrap();
+        let request = json!({ "image": general_purpose::STANDARD.encode(png_fixture(16, 12)), "part": "seat" });
+        ws.send(WsMessage::Text(request.to_string().into())).await.unwrap();
+
+        let mut messages = Vec::new();
+        while let Some(frame) = tokio::time::timeout(Duration::from_secs(30), ws.next()).await.unwrap() {
+            match frame.unwrap() {
+                WsMessage::Text(text) => messages.push(serde_json::from_str::<serde_json::Value>(&text).unwrap()),
+                WsMessage::Close(_) => break,
+                _ => {}
+            }
+        }
+
+        assert_eq!(messages.len(), 4, "{:?}", messages);
+        let mut intensities: Vec<&str> = messages[..3].iter().map(|m| m["intensity"].as_str().unwrap()).collect();
+        let images: Vec<Vec<u8>> = messages[..3]
+            .iter()
+            .map(|m| general_purpose::STANDARD.decode(m["image"].as_str().unwrap()).unwrap())
+            .collect();
+        assert_eq!(images, vec![png_fixture(16, 12), png_fixture(16, 11), png_fixture(16, 10)]);
+        intensities.sort();
+        assert_eq!(intensities, vec!["aggressive", "medium", "minimal"]);
+        let seed = messages[0]["seed"].clone();
+        assert!(messages.iter().all(|m| m["seed"] == seed));
+        assert_eq!(messages[3], json!({ "status": "done", "succeeded": 3, "failed": 0, "seed": seed }));
+    }
+
+    #[tokio::test]
+    async fn customize_options_ws_reports_invalid_parameters() {
+        let server = spawn_mock(create_router(test_state("http://127.0.0.1:9"))).await;
+        let (mut ws, _) = tokio_tungstenite::connect_async(
+            format!("{}/customize/options/ws", server.replace("http://", "ws://")),
+        ).await.unwrap();
+        let request = json!({ "image": "not base64!", "part": "wheel" });
+        ws.send(tokio_tungstenite::tungstenite::Message::Text(request.to_string().into())).await.unwrap();
+
+        let reply = ws.next().await.unwrap().unwrap();
+        let reply: serde_json::Value = serde_json::from_str(reply.to_text().unwrap()).unwrap();
+        let fields: Vec<&str> = reply["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, vec!["image", "part"]);
+    }
+
+    #[tokio::test]
+    async fn customize_options_ws_holds_the_image_to_the_upload_limit() {
+        let mut state = test_state("http://127.0.0.1:9");
+        state.config = Arc::new(Config { max_upload_bytes: 1024, ..test_config() });
+        let server = spawn_mock(create_router(state)).await;
+        let (mut ws, _) = tokio_tungstenite::connect_async(
+            format!("{}/customize/options/ws", server.replace("http://", "ws://")),
+        ).await.unwrap();
+        let request = json!({ "image": general_purpose::STANDARD.encode(vec![0u8; 2048]), "part": "seat" });
+        ws.send(tokio_tungstenite::tungstenite::Message::Text(request.to_string().into())).await.unwrap();
+
+        let reply = ws.next().await.unwrap().unwrap();
+        let reply: serde_json::Value = serde_json::from_str(reply.to_text().unwrap()).unwrap();
+        assert_eq!(reply, json!({ "error": upload_too_large_error().1 }));
+    }
+
+    #[tokio::test]
+    async fn uploads_over_the_body_limit_are_payload_too_large() {
+        let mut state = test_state("http://127.0.0.1:9");
+        state.config = Arc::new(Config { max_upload_bytes: 1024, ..test_config() });
+
+        let response = create_router(state)
+            .oneshot(multipart_request("/extract_seat", &[("image_motorcycle", Some("bike.png"), &[0u8; 4096])]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&body), upload_too_large_error().1);
+    }
+
+    #[tokio::test]
+    async fn customize_sheet_lays_the_intensities_side_by_side() {
+        let bedrock = bedrock_mock(png_fixture(200, 150)).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+        let image = png_fixture(64, 64);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize/sheet",
+                &[("image", Some("bike.png"), &image), ("part", None, b"exhaust")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let sheet = image::load_from_memory(&body).unwrap();
+        // Three 200px tiles plus the small gutters between them
+        assert!(sheet.width() >= 600 && sheet.width() <= 640, "width {}", sheet.width());
+        assert!(sheet.height() > 150);
+    }
+
+    #[tokio::test]
+    async fn customize_prompt_previews_without_generating() {
+        // Nothing listens at the Bedrock endpoint, so any generation call would fail
+        let app = create_router(test_state("http://127.0.0.1:9"));
+        let image = png_fixture(16, 12);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize/prompt",
+                &[
+                    ("image", Some("bike.png"), &image),
+                    ("part", None, b"exhaust"),
+                    ("bike_desc", None, b"cafe racer"),
+                    ("part_desc", None, b"matte black twin pipes"),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let prompt = body["prompt"].as_str().unwrap();
+        assert!(prompt.starts_with("cafe racer style motorcycle with custom exhaust system installed"), "{}", prompt);
+        assert!(prompt.contains("matte black twin pipes"), "{}", prompt);
+        assert!(body["negative_prompt"].as_str().unwrap().contains("different motorcycle model"));
+    }
+
+    #[tokio::test]
+    async fn customize_multi_combines_masks_and_prompts() {
+        let received = Arc::new(std::sync::Mutex::new(None::<serde_json::Value>));
+        let captured = received.clone();
+        let generated = general_purpose::STANDARD.encode(png_fixture(400, 400));
+        let bedrock = spawn_mock(Router::new().route(
+            "/model/{model_id}/invoke",
+            post(move |body: Bytes| {
+                *captured.lock().unwrap() = Some(serde_json::from_slice(&body).unwrap());
+                let generated = generated.clone();
+                async move { Json(json!({ "artifacts": [{ "base64": generated, "finishReason": "SUCCESS" }] })) }
+            }),
+        )).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+        let image = png_fixture(400, 400);
+        let parts = br#"[{ "part": "exhaust", "part_desc": "matte black twin pipes" }, { "part": "seat", "part_desc": "brown leather" }]"#;
+
+        let response = app.clone()
+            .oneshot(multipart_request(
+                "/customize/multi?include_mask=true",
+                &[
+                    ("image", Some("bike.png"), &image),
+                    ("parts", None, parts),
+                    ("bike_desc", None, b"cafe racer"),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let mask = general_purpose::STANDARD.decode(body["mask"].as_str().unwrap()).unwrap();
+        let mask = image::load_from_memory(&mask).unwrap().to_luma8();
+        // Both parts' regions are masked, the handlebars' is not
+        assert!(mask.get_pixel(200, 260)[0] > 200, "exhaust {}", mask.get_pixel(200, 260)[0]);
+        assert!(mask.get_pixel(200, 180)[0] > 200, "seat {}", mask.get_pixel(200, 180)[0]);
+        assert!(mask.get_pixel(160, 80)[0] < 20, "handlebar {}", mask.get_pixel(160, 80)[0]);
+
+        let sent = received.lock().unwrap().take().expect("Bedrock was called");
+        let prompt = sent["text_prompts"][0]["text"].as_str().unwrap();
+        assert!(prompt.starts_with("cafe racer style motorcycle with custom exhaust system and seat installed"), "{}", prompt);
+        assert!(prompt.contains("exhaust system: matte black twin pipes; seat: brown leather"), "{}", prompt);
+
+        let response = app
+            .oneshot(multipart_request(
+                "/customize/multi",
+                &[("image", Some("bike.png"), &image), ("parts", None, b"[]")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({ "errors": [{ "field": "parts", "message": "at least one part is required" }] }));
+    }
+
+    #[tokio::test]
+    async fn json_responses_are_gzipped_but_images_are_not() {
+        use std::io::Read;
+
+        let generated = png_fixture(64, 64);
+        let bedrock = bedrock_mock(generated.clone()).await;
+        let app = create_router(test_state_with_bedrock("http://127.0.0.1:9", &bedrock));
+        let image = png_fixture(64, 64);
+        let gzip_request = |uri: &str| {
+            let mut request = multipart_request(uri, &[("image", Some("bike.png"), &image), ("part", None, b"seat")]);
+            request.headers_mut().insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+            request
+        };
+
+        let response = app.clone().oneshot(gzip_request("/customize/options")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(body.as_ref()).read_to_string(&mut json).unwrap();
+        let options: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(options.len(), 3);
+
+        let response = app.oneshot(gzip_request("/customize")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn vector_uploads_are_rejected_with_415() {
+        let svg = br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#;
+        let pdf = b"%PDF-1.4\n1 0 obj\n<< /Type /Catalog >>\nendobj\n%%EOF";
+
+        for (filename, data) in [("bike.svg", svg.as_slice()), ("bike.pdf", pdf.as_slice())] {
+            let app = create_router(test_state("http://127.0.0.1:9"));
+            let response = app
+                .oneshot(multipart_request("/extract_seat", &[("image_motorcycle", Some(filename), data)]))
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE, "{}", filename);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("only raster images"), "{}", filename);
+        }
+    }
+
+    #[cfg(not(feature = "heic"))]
+    #[tokio::test]
+    async fn heic_upload_is_rejected_with_415() {
+        let app = create_router(test_state("http://127.0.0.1:9"));
+        let mut heic = vec![0x00, 0x00, 0x00, 0x18];
+        heic.extend_from_slice(b"ftypheic\0\0\0\0mif1heic");
+
+        let response = app
+            .oneshot(multipart_request("/extract_seat", &[("image_motorcycle", Some("IMG_0001.HEIC"), &heic)]))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("HEIC/HEIF"));
+    }
+}
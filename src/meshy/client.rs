@@ -1,9 +1,17 @@
-use base64::{Engine, engine::general_purpose};
-use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use tracing::info;
 use reqwest::Client;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderValue};
+
+use crate::util::encode::data_url;
+use crate::util::http::{StatusClass, UpstreamCall, UpstreamError, post_json_expect, send_expect, send_json_expect};
+use crate::util::image_mask::InvalidOptionError;
+use crate::util::mime::ImageBytes;
+use crate::util::rate_limit::RateLimited;
 
 #[derive(Debug, Serialize)]
 pub struct TaskCreatedResponse {
@@ -16,6 +24,23 @@ pub struct TaskStatusResponse {
     pub status: String,
     pub progress: Option<i32>,
     pub model_url: Option<String>,
+    // Previews Meshy publishes while the model is still being built
+    pub thumbnail_url: Option<String>,
+    pub video_url: Option<String>,
+    // "preview" or "refine" when Meshy reports the task's mode; the preview stage reaching
+    // SUCCEEDED only means refinement is about to start
+    pub stage: Option<String>,
+}
+
+impl TaskStatusResponse {
+    // Meshy won't change a task in one of these states again
+    pub fn is_finished(&self) -> bool {
+        match self.status.as_str() {
+            "SUCCEEDED" => self.stage.as_deref() != Some("preview"),
+            "FAILED" | "CANCELED" | "EXPIRED" => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,8 +56,15 @@ struct MeshyTaskStatus {
     model_urls: Option<ModelUrls>,
     #[serde(default)]
     progress: Option<i32>,
+    #[serde(default)]
+    thumbnail_url: Option<String>,
+    #[serde(default)]
+    video_url: Option<String>,
+    #[serde(default)]
+    mode: Option<String>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct ModelUrls {
     glb: Option<String>,
@@ -40,91 +72,317 @@ struct ModelUrls {
     usdz: Option<String>,
 }
 
+// Meshy style preset for the generated model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtStyle {
+    Realistic,
+    Sculpture,
+}
+
+impl ArtStyle {
+    pub fn all() -> &'static [ArtStyle] {
+        &[ArtStyle::Realistic, ArtStyle::Sculpture]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtStyle::Realistic => "realistic",
+            ArtStyle::Sculpture => "sculpture",
+        }
+    }
+}
+
+impl FromStr for ArtStyle {
+    type Err = InvalidOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().to_ascii_lowercase();
+
+        ArtStyle::all()
+            .iter()
+            .find(|style| style.as_str() == normalized)
+            .copied()
+            .ok_or_else(|| InvalidOptionError {
+                value: s.to_string(),
+                expected: ArtStyle::all().iter().map(|style| style.as_str()).collect(),
+            })
+    }
+}
+
+// Generation options for image-to-3D tasks
+#[derive(Debug, Clone)]
+pub struct Meshy3dOptions {
+    pub enable_pbr: bool,
+    pub should_remesh: bool,
+    // false returns bare geometry; PBR maps and texture guidance then make no sense
+    pub should_texture: bool,
+    // Optional guidance for the generated materials, separate from the shape input
+    pub texture_prompt: Option<String>,
+    pub texture_image: Option<ImageBytes>,
+    // Meshy `ai_model` (e.g. "meshy-4"); Meshy's own default when unset
+    pub ai_model: Option<String>,
+    // Meshy's default style when unset
+    pub art_style: Option<ArtStyle>,
+}
+
+impl Default for Meshy3dOptions {
+    fn default() -> Self {
+        Self {
+            enable_pbr: true,
+            should_remesh: true,
+            should_texture: true,
+            texture_prompt: None,
+            texture_image: None,
+            ai_model: None,
+            art_style: None,
+        }
+    }
+}
+
+impl Meshy3dOptions {
+    // Meshy accepts these combinations but silently ignores half of them
+    pub fn validate(&self) -> Result<(), String> {
+        if self.should_texture {
+            return Ok(());
+        }
+        let texturing = [
+            ("enable_pbr", self.enable_pbr),
+            ("texture_prompt", self.texture_prompt.is_some()),
+            ("texture_image", self.texture_image.is_some()),
+        ];
+        match texturing.iter().find(|(_, set)| *set) {
+            Some((field, _)) => Err(format!("{} requires should_texture=true", field)),
+            None => Ok(()),
+        }
+    }
+}
+
+// Why a task creation failed, split by whether Meshy might have created the task anyway.
+// Only `Uncertain` can leave a task behind, so it's the one case a caller must not
+// retry blindly: look the task up (or ask the user) first, or risk paying for a duplicate.
+#[derive(Debug)]
+pub enum CreateTaskError {
+    // Nothing reached Meshy (bad input, connection refused); retrying is safe
+    NotSent(String),
+    // Meshy answered with an error status, so no task exists; retrying is safe
+    Rejected(String),
+    // The request went out but no usable response came back; a task may exist
+    Uncertain(String),
+    // Meshy refused with a 429, so no task exists; retry once the limit resets
+    RateLimited(RateLimited),
+}
+
+impl fmt::Display for CreateTaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotSent(msg) => write!(f, "Task request was not sent: {}", msg),
+            Self::Rejected(msg) => write!(f, "Failed to create task: {}", msg),
+            Self::Uncertain(msg) => write!(
+                f,
+                "Task request was sent but the response was lost ({}); Meshy may have created the task",
+                msg
+            ),
+            Self::RateLimited(limit) => limit.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CreateTaskError {}
+
+// Meshy has no task with this id (a typo, or another account's task)
+#[derive(Debug)]
+pub struct TaskNotFound(pub String);
+
+impl fmt::Display for TaskNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown task: {}", self.0)
+    }
+}
+
+impl std::error::Error for TaskNotFound {}
+
 pub struct MeshyClient {
     api_key: String,
+    base_url: String,
     client: Client,
 }
 
 impl MeshyClient {
     const MESHY_API_BASE: &str = "https://api.meshy.ai";
     
-    pub fn new() -> Self {
-        let api_res = std::env::var("MESHY_API_KEY");
-        match api_res {
-            Ok(key) => MeshyClient { 
-                api_key: key,
-                client: Client::new(),
-            },
-            Err(_) => panic!("MESHY_API_KEY environment variable not set"),
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self::with_base_url(api_key, Self::MESHY_API_BASE)
+    }
+
+    // Point the client at a different API host (used by tests against a local mock)
+    pub fn with_base_url(api_key: impl Into<String>, base_url: impl Into<String>) -> Self {
+        MeshyClient {
+            api_key: api_key.into(),
+            base_url: base_url.into(),
+            client: Client::new(),
         }
     }
+
+    // Give up on requests that take longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+        self
+    }
     
-    pub async fn create_3d_task(
+    // Create an image-to-3D task, reporting whether a failed call may still have created one.
+    // Meshy has no client-supplied request id, so a lost response can't be reconciled by
+    // listing tasks without risking a match against someone else's upload; we surface
+    // `CreateTaskError::Uncertain` instead and leave the decision to the caller.
+    pub async fn create_3d_task_safe(
         &self,
-        images: Vec<Bytes>
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let request_url = format!("{}/openapi/v1/image-to-3d", Self::MESHY_API_BASE);
+        images: Vec<ImageBytes>,
+        options: &Meshy3dOptions,
+    ) -> Result<String, CreateTaskError> {
+        let request_url = format!("{}/openapi/v1/image-to-3d", self.base_url);
         
         // 첫 번째 이미지만 사용
         if images.is_empty() {
-            return Err("No images provided".into());
+            return Err(CreateTaskError::NotSent("No images provided".to_string()));
         }
         
         let image_bytes = &images[0];
         info!("Processing image: {} bytes", image_bytes.len());
         
-        let mime_type = if image_bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
-            "image/jpeg"
-        } else if image_bytes.starts_with(&[0x89, 0x50, 0x4E, 0x47]) {
-            "image/png"
-        } else {
-            "image/jpeg"
-        };
+        let image_url = Self::data_url(image_bytes);
         
-        let img_base64 = general_purpose::STANDARD.encode(&image_bytes);
-        let image_url = format!("data:{};base64,{}", mime_type, img_base64);
+        let payload = Self::build_payload(image_url, options);
         
-        let payload = json!({
+        let started = Instant::now();
+        let task_response: MeshyTaskResponse = post_json_expect(
+            &self.client,
+            Self::call("create_3d_task"),
+            &request_url,
+            self.auth_headers(),
+            &payload,
+        ).await.map_err(|e| match e {
+            // Only a failed connect or a request that couldn't be built is known not to have arrived
+            UpstreamError::Send(e) if e.is_connect() || e.is_builder() => CreateTaskError::NotSent(e.to_string()),
+            UpstreamError::Send(e) => CreateTaskError::Uncertain(e.to_string()),
+            UpstreamError::Status { class: StatusClass::Throttled, body, retry_after, .. } => {
+                CreateTaskError::RateLimited(RateLimited { provider: "meshy", retry_after, message: body })
+            }
+            e @ UpstreamError::Status { .. } => CreateTaskError::Rejected(e.to_string()),
+            // A 2xx means the task exists, so an unreadable body still leaves it behind
+            UpstreamError::Body(message) => CreateTaskError::Uncertain(message),
+        })?;
+        info!(
+            provider = "meshy",
+            op = "create_3d_task",
+            task_id = %task_response.result,
+            input_bytes = image_bytes.len(),
+            latency_ms = started.elapsed().as_millis() as u64,
+            "done"
+        );
+        Ok(task_response.result)
+    }
+    
+    fn build_payload(image_url: String, options: &Meshy3dOptions) -> serde_json::Value {
+        let mut payload = json!({
             "image_url": image_url,  // ✅ 단수형
-            "enable_pbr": true,
-            "should_remesh": true,
+            "enable_pbr": options.enable_pbr,
+            "should_remesh": options.should_remesh,
+            "should_texture": options.should_texture,
         });
-        
-        let response = self.client
-            .post(&request_url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&payload)
-            .send()
-            .await?;
-        
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(format!("Failed to create task: {}", error_text).into());
+
+        if let Some(prompt) = &options.texture_prompt {
+            payload["texture_prompt"] = json!(prompt);
         }
-        
-        let task_response: MeshyTaskResponse = response.json().await?;
-        Ok(task_response.result)
+        if let Some(texture) = &options.texture_image {
+            payload["texture_image_url"] = json!(Self::data_url(texture));
+        }
+        if let Some(model) = &options.ai_model {
+            payload["ai_model"] = json!(model);
+        }
+        if let Some(style) = options.art_style {
+            payload["art_style"] = json!(style.as_str());
+        }
+
+        payload
+    }
+
+    fn call(op: &'static str) -> UpstreamCall {
+        UpstreamCall { provider: "meshy", op }
+    }
+
+    // A key that isn't a valid header value is left out, and Meshy answers 401
+    fn auth_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", self.api_key)) {
+            headers.insert(AUTHORIZATION, value);
+        }
+        headers
+    }
+
+    fn data_url(image: &ImageBytes) -> String {
+        data_url(image.mime(), image)
     }
     
+    // Meshy has no separate cancel call; deleting a task that is still running stops it
+    pub async fn cancel_task(
+        &self,
+        task_id: &str
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let task_url = format!("{}/openapi/v1/image-to-3d/{}", self.base_url, task_id);
+
+        let started = Instant::now();
+        send_expect(Self::call("cancel_task"), self.client.delete(&task_url).headers(self.auth_headers()))
+            .await
+            .map_err(|e| format!("Failed to cancel task: {}", e))?;
+
+        info!(
+            provider = "meshy",
+            op = "cancel_task",
+            task_id,
+            latency_ms = started.elapsed().as_millis() as u64,
+            "done"
+        );
+        Ok(())
+    }
+
+    // List a single task, which checks the key without creating (and paying for) one
+    pub async fn probe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let list_url = format!("{}/openapi/v1/image-to-3d?page_size=1", self.base_url);
+
+        send_expect(Self::call("probe"), self.client.get(&list_url).headers(self.auth_headers()))
+            .await
+            .map_err(|e| format!("Meshy API error: {}", e))?;
+        Ok(())
+    }
+
     pub async fn get_task_status(
         &self,
         task_id: &str
     ) -> Result<TaskStatusResponse, Box<dyn std::error::Error + Send + Sync>> {
-        let status_url = format!("{}/openapi/v1/image-to-3d/{}", Self::MESHY_API_BASE, task_id);
+        let status_url = format!("{}/openapi/v1/image-to-3d/{}", self.base_url, task_id);
         
-        let response = self.client
-            .get(&status_url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .send()
-            .await?;
-        
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(format!("Failed to check status: {}", error_text).into());
-        }
-        
-        let status: MeshyTaskStatus = response.json().await?;
+        let started = Instant::now();
+        let status: MeshyTaskStatus = send_json_expect(
+            Self::call("get_task_status"),
+            self.client.get(&status_url).headers(self.auth_headers()),
+        ).await.map_err(|e| -> Box<dyn std::error::Error + Send + Sync> {
+            match e {
+                UpstreamError::Status { status, .. } if status == reqwest::StatusCode::NOT_FOUND => {
+                    Box::new(TaskNotFound(task_id.to_string()))
+                }
+                e => format!("Failed to check status: {}", e).into(),
+            }
+        })?;
+        info!(
+            provider = "meshy",
+            op = "get_task_status",
+            task_id,
+            status = %status.status,
+            latency_ms = started.elapsed().as_millis() as u64,
+            "done"
+        );
         
         let model_url = status.model_urls
             .and_then(|urls| urls.glb);
@@ -134,6 +392,175 @@ impl MeshyClient {
             status: status.status,
             progress: status.progress,
             model_url,
+            // Meshy sends "" rather than omitting previews it doesn't have yet
+            thumbnail_url: status.thumbnail_url.filter(|url| !url.is_empty()),
+            video_url: status.video_url.filter(|url| !url.is_empty()),
+            stage: status.mode
+                .map(|mode| mode.trim().to_ascii_lowercase())
+                .filter(|mode| !mode.is_empty()),
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn payload_defaults_match_previous_behavior() {
+        let payload = MeshyClient::build_payload("data:image/png;base64,AA==".to_string(), &Meshy3dOptions::default());
+
+        assert_eq!(payload["enable_pbr"], true);
+        assert_eq!(payload["should_remesh"], true);
+        assert_eq!(payload["should_texture"], true);
+        assert!(payload.get("texture_prompt").is_none());
+        assert!(payload.get("negative_prompt").is_none());
+        assert!(payload.get("texture_image_url").is_none());
+        assert!(payload.get("ai_model").is_none());
+        assert!(payload.get("art_style").is_none());
+    }
+
+    #[test]
+    fn payload_includes_texture_guidance_when_set() {
+        let options = Meshy3dOptions {
+            texture_prompt: Some("weathered red paint".to_string()),
+            texture_image: Some(ImageBytes::new(Bytes::from_static(&[0x89, 0x50, 0x4E, 0x47]))),
+            ..Meshy3dOptions::default()
+        };
+        let payload = MeshyClient::build_payload("data:image/png;base64,AA==".to_string(), &options);
+
+        assert_eq!(payload["texture_prompt"], "weathered red paint");
+        assert_eq!(payload["texture_image_url"], "data:image/png;base64,iVBORw==");
+    }
+
+    // Accepts one request, reads its headers, then hangs up without answering
+    async fn spawn_dropping_server() -> String {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !received.windows(4).any(|w| w == b"\r\n\r\n") {
+                match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => received.extend_from_slice(&buf[..n]),
+                }
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn lost_response_after_send_is_uncertain() {
+        let client = MeshyClient::with_base_url("test-key", spawn_dropping_server().await);
+
+        let err = client
+            .create_3d_task_safe(vec![ImageBytes::new(Bytes::from_static(b"\x89PNG"))], &Meshy3dOptions::default())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, CreateTaskError::Uncertain(_)), "{:?}", err);
+        assert!(err.to_string().contains("may have created the task"));
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_not_sent() {
+        // Nothing listens here, so the request can't have reached Meshy
+        let client = MeshyClient::with_base_url("test-key", "http://127.0.0.1:9");
+
+        let err = client
+            .create_3d_task_safe(vec![ImageBytes::new(Bytes::from_static(b"\x89PNG"))], &Meshy3dOptions::default())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, CreateTaskError::NotSent(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn status_exposes_preview_urls() {
+        use axum::{Json, Router, routing::get};
+
+        let base_url = crate::test_support::spawn_mock(Router::new().route(
+            "/openapi/v1/image-to-3d/{task_id}",
+            get(|| async {
+                Json(json!({
+                    "id": "task-1",
+                    "status": "IN_PROGRESS",
+                    "progress": 40,
+                    "model_urls": { "glb": "" },
+                    "thumbnail_url": "https://assets.meshy.ai/task-1/preview.png",
+                    "video_url": ""
+                }))
+            }),
+        )).await;
+
+        let status = MeshyClient::with_base_url("test-key", base_url)
+            .get_task_status("task-1")
+            .await
+            .unwrap();
+
+        assert_eq!(status.progress, Some(40));
+        assert_eq!(status.thumbnail_url.as_deref(), Some("https://assets.meshy.ai/task-1/preview.png"));
+        assert_eq!(status.video_url, None);
+    }
+
+    #[test]
+    fn preview_success_is_not_final() {
+        let status = |status: &str, stage: Option<&str>| TaskStatusResponse {
+            id: "task-1".to_string(),
+            status: status.to_string(),
+            progress: None,
+            model_url: None,
+            thumbnail_url: None,
+            video_url: None,
+            stage: stage.map(str::to_string),
+        };
+
+        assert!(!status("SUCCEEDED", Some("preview")).is_finished());
+        assert!(status("SUCCEEDED", Some("refine")).is_finished());
+        assert!(status("SUCCEEDED", None).is_finished());
+        assert!(status("FAILED", Some("preview")).is_finished());
+        assert!(!status("IN_PROGRESS", Some("refine")).is_finished());
+    }
+
+    #[test]
+    fn payload_reflects_disabled_pbr() {
+        let options = Meshy3dOptions { enable_pbr: false, ..Meshy3dOptions::default() };
+        let payload = MeshyClient::build_payload("data:image/png;base64,AA==".to_string(), &options);
+
+        assert_eq!(payload["enable_pbr"], false);
+        assert_eq!(payload["should_remesh"], true);
+    }
+
+    #[test]
+    fn payload_reflects_untextured_geometry() {
+        let options = Meshy3dOptions {
+            enable_pbr: false,
+            should_texture: false,
+            ..Meshy3dOptions::default()
+        };
+        assert_eq!(options.validate(), Ok(()));
+
+        let payload = MeshyClient::build_payload("data:image/png;base64,AA==".to_string(), &options);
+        assert_eq!(payload["should_texture"], false);
+        assert_eq!(payload["enable_pbr"], false);
+    }
+
+    #[test]
+    fn texturing_options_require_should_texture() {
+        let untextured = Meshy3dOptions { should_texture: false, ..Meshy3dOptions::default() };
+        assert_eq!(untextured.validate(), Err("enable_pbr requires should_texture=true".to_string()));
+
+        let with_prompt = Meshy3dOptions {
+            enable_pbr: false,
+            texture_prompt: Some("chrome".to_string()),
+            ..untextured
+        };
+        assert_eq!(with_prompt.validate(), Err("texture_prompt requires should_texture=true".to_string()));
+        assert_eq!(Meshy3dOptions::default().validate(), Ok(()));
+    }
+}
@@ -0,0 +1,141 @@
+use base64::{Engine, engine::general_purpose};
+use reqwest::{Client, header::HeaderMap};
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::util::http::{UpstreamCall, post_json_expect};
+use crate::util::mime::ImageBytes;
+
+const CLASSIFY: UpstreamCall = UpstreamCall { provider: "moderation", op: "classify" };
+
+// Why an upload can't be forwarded to a provider
+#[derive(Debug, PartialEq)]
+pub enum ModerationError {
+    // The hook classified it as disallowed; holds the labels it flagged
+    Rejected(Vec<String>),
+    // The hook gave no verdict and moderation fails closed
+    Unavailable(String),
+}
+
+impl fmt::Display for ModerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rejected(labels) if labels.is_empty() => f.write_str("Upload rejected by content moderation"),
+            Self::Rejected(labels) => write!(f, "Upload rejected by content moderation: {}", labels.join(", ")),
+            Self::Unavailable(message) => write!(f, "Content moderation is unavailable: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ModerationError {}
+
+// The hook's answer, e.g. `{"allowed": false, "labels": ["violence"]}`
+#[derive(Debug, Deserialize)]
+struct Verdict {
+    allowed: bool,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+// Classifies uploads through an external hook (a Rekognition wrapper, an in-house model)
+// before they go to Gemini or Meshy. Each image is POSTed as `{"image": <base64>, "mime_type": ..}`.
+pub struct ModerationClient {
+    client: Client,
+    url: String,
+    fail_open: bool,
+}
+
+impl ModerationClient {
+    pub fn new(url: String) -> Self {
+        Self { client: Client::new(), url, fail_open: false }
+    }
+
+    // Give up on requests that take longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+        self
+    }
+
+    // Let uploads through when the hook fails instead of refusing them
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    pub async fn check(&self, image: &ImageBytes) -> Result<(), ModerationError> {
+        let body = json!({
+            "image": general_purpose::STANDARD.encode(&image[..]),
+            "mime_type": image.mime(),
+        });
+
+        match post_json_expect::<Verdict>(&self.client, CLASSIFY, &self.url, HeaderMap::new(), &body).await {
+            Ok(verdict) if verdict.allowed => Ok(()),
+            Ok(verdict) => {
+                info!(labels = ?verdict.labels, "upload rejected by moderation");
+                Err(ModerationError::Rejected(verdict.labels))
+            }
+            Err(e) if self.fail_open => {
+                warn!("Moderation check failed, letting the upload through: {}", e);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Moderation check failed: {}", e);
+                Err(ModerationError::Unavailable(e.to_string()))
+            }
+        }
+    }
+
+    // Every image has to pass; stops at the first one that doesn't
+    pub async fn check_all(&self, images: &[ImageBytes]) -> Result<(), ModerationError> {
+        for image in images {
+            self.check(image).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{png_fixture, spawn_mock};
+    use axum::{Json, Router, routing::post};
+
+    #[tokio::test]
+    async fn passes_allowed_and_rejects_flagged_uploads() {
+        let base_url = spawn_mock(
+            Router::new()
+                .route("/allow", post(|Json(body): Json<serde_json::Value>| async move {
+                    assert_eq!(body["mime_type"], "image/png");
+                    assert!(body["image"].as_str().is_some_and(|image| !image.is_empty()));
+                    Json(json!({ "allowed": true }))
+                }))
+                .route("/block", post(|| async { Json(json!({ "allowed": false, "labels": ["violence"] })) })),
+        ).await;
+        let image = ImageBytes::new(png_fixture(4, 4).into());
+
+        let allow = ModerationClient::new(format!("{}/allow", base_url));
+        assert_eq!(allow.check(&image).await, Ok(()));
+
+        let block = ModerationClient::new(format!("{}/block", base_url));
+        let err = block.check_all(&[image]).await.unwrap_err();
+        assert_eq!(err, ModerationError::Rejected(vec!["violence".to_string()]));
+        assert_eq!(err.to_string(), "Upload rejected by content moderation: violence");
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_hook_fails_closed_unless_configured_open() {
+        let image = ImageBytes::new(png_fixture(4, 4).into());
+
+        let closed = ModerationClient::new("http://127.0.0.1:9/classify".to_string());
+        assert!(matches!(closed.check(&image).await, Err(ModerationError::Unavailable(_))));
+
+        let open = ModerationClient::new("http://127.0.0.1:9/classify".to_string()).with_fail_open(true);
+        assert_eq!(open.check(&image).await, Ok(()));
+    }
+}
@@ -0,0 +1 @@
+pub mod client;
\ No newline at end of file
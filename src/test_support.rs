@@ -0,0 +1,111 @@
+// Shared helpers for handler and client tests
+use axum::{Router, body::Body, http::{Request, header}};
+
+pub const BOUNDARY: &str = "zephyr-test-boundary";
+
+// Build a multipart/form-data body from (name, filename, data) triples
+pub fn multipart_body(fields: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    for (name, filename, data) in fields {
+        body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+        match filename {
+            Some(filename) => body.extend_from_slice(
+                format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\r\n",
+                    name, filename
+                ).as_bytes(),
+            ),
+            None => body.extend_from_slice(
+                format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes(),
+            ),
+        }
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+
+    body
+}
+
+pub fn multipart_request(uri: &str, fields: &[(&str, Option<&str>, &[u8])]) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .body(Body::from(multipart_body(fields)))
+        .unwrap()
+}
+
+// Encode a solid-colour PNG of the given size
+pub fn png_fixture(width: u32, height: u32) -> Vec<u8> {
+    let img = image::RgbImage::from_pixel(width, height, image::Rgb([40, 80, 120]));
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(img)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    png
+}
+
+// Encode an animated GIF with one solid-colour frame per entry of `colors`
+pub fn animated_gif_fixture(width: u32, height: u32, colors: &[[u8; 3]]) -> Vec<u8> {
+    use image::codecs::gif::{GifEncoder, Repeat};
+
+    let mut gif = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif);
+        encoder.set_repeat(Repeat::Infinite).unwrap();
+        let frames = colors.iter().map(|&[r, g, b]| {
+            image::Frame::new(image::RgbaImage::from_pixel(width, height, image::Rgba([r, g, b, 255])))
+        });
+        encoder.encode_frames(frames).unwrap();
+    }
+    gif
+}
+
+// Serve a mock upstream on an ephemeral local port and return its base URL
+pub async fn spawn_mock(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+
+    format!("http://{}", addr)
+}
+
+// Bedrock client with static credentials pointed at a local mock endpoint
+pub fn bedrock_client(endpoint_url: &str) -> aws_sdk_bedrockruntime::Client {
+    use aws_sdk_bedrockruntime::config::{BehaviorVersion, Credentials, Region};
+
+    let config = aws_sdk_bedrockruntime::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-west-2"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+        .retry_config(aws_smithy_types::retry::RetryConfig::disabled())
+        .endpoint_url(endpoint_url)
+        .build();
+
+    aws_sdk_bedrockruntime::Client::from_conf(config)
+}
+
+// Path-style S3 client with static credentials pointed at a local mock endpoint
+pub fn s3_client(endpoint_url: &str) -> aws_sdk_s3::Client {
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region, RequestChecksumCalculation};
+
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-west-2"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+        .retry_config(aws_smithy_types::retry::RetryConfig::disabled())
+        .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+        .endpoint_url(endpoint_url)
+        .force_path_style(true)
+        .build();
+
+    aws_sdk_s3::Client::from_conf(config)
+}
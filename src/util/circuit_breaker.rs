@@ -0,0 +1,192 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// A provider's circuit is open: it failed too often recently, so calls are refused
+// without trying until the cooldown is over
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitOpen {
+    pub provider: &'static str,
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is failing and has been taken out of rotation; retry in {}s",
+            self.provider,
+            self.retry_after_secs()
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+impl CircuitOpen {
+    // Whole seconds for a Retry-After header, never 0
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // One probe call is in flight; if it never reports back (the request was dropped)
+    // another probe is let through after a further cooldown
+    HalfOpen { since: Instant },
+}
+
+// Closed → Open after `failure_threshold` consecutive failures; Open → HalfOpen once
+// `cooldown` has passed, letting a single probe through; the probe closes or re-opens it
+pub struct CircuitBreaker {
+    provider: &'static str,
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+    // Every failed call, whether or not it counted towards opening the circuit
+    errors: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(provider: &'static str, failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            provider,
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    // Run `call` unless the circuit is open. `is_failure` decides which errors mean the
+    // provider itself is in trouble; other errors (a rejected input) leave the count alone.
+    pub async fn call<T, E>(
+        &self,
+        call: impl Future<Output = Result<T, E>>,
+        is_failure: impl FnOnce(&E) -> bool,
+    ) -> Result<Result<T, E>, CircuitOpen> {
+        self.acquire()?;
+        let result = call.await;
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        match &result {
+            Err(e) if is_failure(e) => self.record_failure(),
+            _ => self.record_success(),
+        }
+        Ok(result)
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    fn acquire(&self) -> Result<(), CircuitOpen> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let wait = match *state {
+            State::Closed { .. } => return Ok(()),
+            State::Open { until } if now >= until => None,
+            State::Open { until } => Some(until - now),
+            State::HalfOpen { since } if now >= since + self.cooldown => None,
+            State::HalfOpen { since } => Some(since + self.cooldown - now),
+        };
+
+        match wait {
+            None => {
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+            Some(retry_after) => Err(CircuitOpen { provider: self.provider, retry_after }),
+        }
+    }
+
+    fn record_success(&self) {
+        *self.state.lock().unwrap() = State::Closed { failures: 0 };
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            // A failed probe re-opens straight away
+            State::HalfOpen { .. } | State::Open { .. } => self.failure_threshold,
+        };
+
+        *state = if failures >= self.failure_threshold {
+            warn!(provider = self.provider, failures, cooldown_secs = self.cooldown.as_secs(), "circuit opened");
+            State::Open { until: Instant::now() + self.cooldown }
+        } else {
+            State::Closed { failures }
+        };
+    }
+}
+
+// One breaker per upstream provider
+pub struct ProviderBreakers {
+    pub gemini: CircuitBreaker,
+    pub meshy: CircuitBreaker,
+    pub bedrock: CircuitBreaker,
+}
+
+impl ProviderBreakers {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            gemini: CircuitBreaker::new("gemini", failure_threshold, cooldown),
+            meshy: CircuitBreaker::new("meshy", failure_threshold, cooldown),
+            bedrock: CircuitBreaker::new("bedrock", failure_threshold, cooldown),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn fail(breaker: &CircuitBreaker) -> Result<Result<(), &'static str>, CircuitOpen> {
+        breaker.call(async { Err("down") }, |_| true).await
+    }
+
+    async fn succeed(breaker: &CircuitBreaker) -> Result<Result<(), &'static str>, CircuitOpen> {
+        breaker.call(async { Ok(()) }, |_| true).await
+    }
+
+    #[tokio::test]
+    async fn opens_after_threshold_then_probes_after_cooldown() {
+        let breaker = CircuitBreaker::new("gemini", 3, Duration::from_millis(50));
+
+        for _ in 0..3 {
+            assert_eq!(fail(&breaker).await, Ok(Err("down")));
+        }
+        let open = succeed(&breaker).await.unwrap_err();
+        assert_eq!(open.provider, "gemini");
+        assert!(open.retry_after <= Duration::from_millis(50));
+        assert_eq!(open.retry_after_secs(), 1);
+
+        // After the cooldown one probe goes through; failing it re-opens immediately
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(fail(&breaker).await, Ok(Err("down")));
+        assert!(succeed(&breaker).await.is_err());
+
+        // A successful probe closes the circuit again
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(succeed(&breaker).await, Ok(Ok(())));
+        assert_eq!(fail(&breaker).await, Ok(Err("down")));
+        assert_eq!(succeed(&breaker).await, Ok(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn ignores_errors_that_are_not_provider_failures() {
+        let breaker = CircuitBreaker::new("meshy", 1, Duration::from_secs(60));
+
+        let result = breaker.call(async { Err::<(), _>("bad input") }, |_| false).await;
+        assert_eq!(result, Ok(Err("bad input")));
+        assert_eq!(succeed(&breaker).await, Ok(Ok(())));
+    }
+}
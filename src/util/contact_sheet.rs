@@ -0,0 +1,128 @@
+use image::{DynamicImage, GenericImage, Rgb, RgbImage, imageops::FilterType};
+use imageproc::drawing::draw_filled_rect_mut;
+use imageproc::rect::Rect;
+
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const GAP_FILL: Rgb<u8> = Rgb([220, 220, 220]);
+const INK: Rgb<u8> = Rgb([30, 30, 30]);
+// Space between tiles and around the label text
+const SPACING: u32 = 8;
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+// One cell of the sheet; `None` leaves a grey gap under the label
+pub struct Tile {
+    pub label: String,
+    pub image: Option<DynamicImage>,
+}
+
+// Lay `tiles` out left to right, each under its own label strip. Every cell takes the size
+// of the first image present (others are resized to it); None when there is no image at all.
+pub fn contact_sheet(tiles: &[Tile]) -> Option<RgbImage> {
+    let (width, height) = tiles.iter()
+        .find_map(|tile| tile.image.as_ref())
+        .map(|image| (image.width(), image.height()))?;
+
+    // Labels stay small but readable on the 1024px SDXL outputs
+    let scale = (width / 160).clamp(1, 4);
+    let label_height = GLYPH_HEIGHT * scale + 2 * SPACING;
+    let count = tiles.len() as u32;
+    let mut sheet = RgbImage::from_pixel(
+        count * width + (count - 1) * SPACING,
+        label_height + height,
+        BACKGROUND,
+    );
+
+    for (index, tile) in tiles.iter().enumerate() {
+        let x = index as u32 * (width + SPACING);
+        draw_label(&mut sheet, &tile.label, x + SPACING, SPACING, scale);
+
+        match &tile.image {
+            Some(image) => {
+                let cell = if image.width() == width && image.height() == height {
+                    image.to_rgb8()
+                } else {
+                    image.resize_exact(width, height, FilterType::Triangle).to_rgb8()
+                };
+                sheet.copy_from(&cell, x, label_height).expect("cell fits inside the sheet");
+            }
+            None => draw_filled_rect_mut(
+                &mut sheet,
+                Rect::at(x as i32, label_height as i32).of_size(width, height),
+                GAP_FILL,
+            ),
+        }
+    }
+
+    Some(sheet)
+}
+
+// Draw `text` in a built-in 5x7 block font; characters it doesn't know are left blank
+fn draw_label(sheet: &mut RgbImage, text: &str, x: u32, y: u32, scale: u32) {
+    for (index, ch) in text.chars().enumerate() {
+        let left = x + index as u32 * (GLYPH_WIDTH + 1) * scale;
+        for (row, bits) in glyph(ch).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                let px = left + col * scale;
+                let py = y + row as u32 * scale;
+                if px + scale > sheet.width() {
+                    return;
+                }
+                draw_filled_rect_mut(sheet, Rect::at(px as i32, py as i32).of_size(scale, scale), INK);
+            }
+        }
+    }
+}
+
+// Just the letters the intensity labels and "FAILED" need
+fn glyph(ch: char) -> [u8; 7] {
+    match ch.to_ascii_uppercase() {
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'C' => [0b01111, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b01111],
+        'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01111, 0b10000, 0b10000, 0b10111, 0b10001, 0b10001, 0b01110],
+        'I' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b11111],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001, 0b10001],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        '-' => [0, 0, 0, 0b11111, 0, 0, 0],
+        _ => [0; 7],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_tiles_side_by_side_and_fills_gaps() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(40, 30, Rgb([0, 0, 255])));
+        let tiles = vec![
+            Tile { label: "minimal".into(), image: Some(image.clone()) },
+            Tile { label: "medium - failed".into(), image: None },
+            Tile { label: "aggressive".into(), image: Some(image.resize_exact(20, 15, FilterType::Nearest)) },
+        ];
+
+        let sheet = contact_sheet(&tiles).unwrap();
+        assert_eq!(sheet.width(), 3 * 40 + 2 * SPACING);
+        assert_eq!(sheet.height(), 30 + GLYPH_HEIGHT + 2 * SPACING);
+
+        let bottom = sheet.height() - 1;
+        assert_eq!(*sheet.get_pixel(0, bottom), Rgb([0, 0, 255]));
+        assert_eq!(*sheet.get_pixel(40 + SPACING, bottom), GAP_FILL);
+        assert_eq!(*sheet.get_pixel(2 * (40 + SPACING), bottom), Rgb([0, 0, 255]));
+        // Some label ink landed in the strip above the first tile
+        assert!((0..40).any(|x| (0..GLYPH_HEIGHT + 2 * SPACING).any(|y| *sheet.get_pixel(x, y) == INK)));
+
+        assert!(contact_sheet(&[Tile { label: "none".into(), image: None }]).is_none());
+    }
+}
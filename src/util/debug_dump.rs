@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+static CALLS: AtomicU64 = AtomicU64::new(0);
+
+const REDACTED: &str = "[REDACTED]";
+
+// Writes raw provider traffic to DEBUG_DUMP_DIR for inspecting odd generations
+#[derive(Debug, Clone)]
+pub struct DebugDump {
+    dir: PathBuf,
+    // Values that must never reach disk, e.g. API keys
+    secrets: Vec<String>,
+}
+
+impl DebugDump {
+    pub fn new(dir: PathBuf, secrets: Vec<String>) -> Self {
+        let secrets = secrets.into_iter().filter(|s| !s.is_empty()).collect();
+        Self { dir, secrets }
+    }
+
+    // Start a dump for one provider call; request and response share a file stem
+    pub fn call(&self, provider: &str, op: &str) -> DumpCall<'_> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let seq = CALLS.fetch_add(1, Ordering::Relaxed);
+
+        DumpCall {
+            dump: self,
+            stem: format!("{}_{}_{}_{}", millis, seq, provider, op),
+        }
+    }
+
+    fn write(&self, file_name: String, body: &str) {
+        let path = self.dir.join(file_name);
+
+        let result = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&path, redact(body, &self.secrets)));
+        if let Err(e) = result {
+            warn!("Failed to write debug dump {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub struct DumpCall<'a> {
+    dump: &'a DebugDump,
+    stem: String,
+}
+
+impl DumpCall<'_> {
+    pub fn request(&self, body: &str) {
+        self.dump.write(format!("{}_request.json", self.stem), body);
+    }
+
+    pub fn response(&self, body: &str) {
+        self.dump.write(format!("{}_response.json", self.stem), body);
+    }
+}
+
+pub fn redact(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .fold(text.to_string(), |acc, secret| acc.replace(secret.as_str(), REDACTED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_every_secret_occurrence() {
+        let secrets = vec!["sk-123".to_string()];
+
+        assert_eq!(
+            redact("key=sk-123, again sk-123", &secrets),
+            "key=[REDACTED], again [REDACTED]"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use base64::{Engine, engine::general_purpose};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageResult, Rgba, RgbaImage};
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::util::image_mask::InvalidOptionError;
+
+// Encoding for images we hand back to callers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg { quality: u8 },
+    // Lossless only, the image crate has no pure-Rust lossy WebP encoder
+    WebP,
+}
+
+impl OutputFormat {
+    pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg { .. } => "image/jpeg",
+            OutputFormat::WebP => "image/webp",
+        }
+    }
+
+    // Apply a 1-100 quality to lossy formats; ignored for PNG and WebP
+    pub fn with_quality(self, quality: u8) -> Self {
+        match self {
+            OutputFormat::Jpeg { .. } => OutputFormat::Jpeg { quality: quality.clamp(1, 100) },
+            other => other,
+        }
+    }
+
+    // Pick the format from an output file name, PNG unless it looks like JPEG
+    #[allow(dead_code)] // used by the CLI and the demo test
+    pub fn from_path(path: &str) -> Self {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| ext.parse().ok())
+            .unwrap_or(OutputFormat::Png)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = InvalidOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg { quality: Self::DEFAULT_JPEG_QUALITY }),
+            "webp" => Ok(OutputFormat::WebP),
+            _ => Err(InvalidOptionError {
+                value: s.to_string(),
+                expected: vec!["png", "jpeg", "webp"],
+            }),
+        }
+    }
+}
+
+// Pick the best supported format from an Accept header, PNG when nothing matches.
+// Higher q wins; on a tie a concrete type beats a wildcard, then the earlier entry.
+pub fn negotiate(accept: &str) -> OutputFormat {
+    let mut best: Option<(f32, bool, OutputFormat)> = None;
+
+    for entry in accept.split(',') {
+        let mut params = entry.split(';');
+        let media_type = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|v| v.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let (format, specific) = match media_type.as_str() {
+            "image/png" => (OutputFormat::Png, true),
+            "image/jpeg" | "image/jpg" => (OutputFormat::Jpeg { quality: OutputFormat::DEFAULT_JPEG_QUALITY }, true),
+            "image/webp" => (OutputFormat::WebP, true),
+            "image/*" | "*/*" => (OutputFormat::Png, false),
+            _ => continue,
+        };
+
+        let better = match best {
+            None => q > 0.0,
+            Some((best_q, best_specific, _)) => q > best_q || (q == best_q && specific && !best_specific),
+        };
+        if better {
+            best = Some((q, specific, format));
+        }
+    }
+
+    best.map(|(_, _, format)| format).unwrap_or(OutputFormat::Png)
+}
+
+// Re-encode generated image bytes into the requested format
+pub fn encode_as(data: &[u8], format: OutputFormat) -> ImageResult<Vec<u8>> {
+    // Providers already return PNG, skip the round trip
+    if format == OutputFormat::Png && image::guess_format(data).ok() == Some(ImageFormat::Png) {
+        return Ok(data.to_vec());
+    }
+
+    let img = image::load_from_memory(data)?;
+    let mut out = Vec::new();
+
+    match format {
+        OutputFormat::Png => img.write_to(&mut Cursor::new(&mut out), ImageFormat::Png)?,
+        OutputFormat::Jpeg { quality } => {
+            // JPEG has no alpha channel
+            let rgb = img.to_rgb8();
+            JpegEncoder::new_with_quality(&mut out, quality)
+                .encode(rgb.as_raw(), rgb.width(), rgb.height(), image::ColorType::Rgb8)?;
+        }
+        OutputFormat::WebP => {
+            let rgba = img.to_rgba8();
+            WebPEncoder::new_lossless(&mut out)
+                .encode(rgba.as_raw(), rgba.width(), rgba.height(), image::ColorType::Rgba8)?;
+        }
+    }
+
+    Ok(out)
+}
+
+// `data:<mime>;base64,...` for inlining bytes in JSON
+pub fn data_url(mime: &str, data: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, general_purpose::STANDARD.encode(data))
+}
+
+// Scale an image to fit `width`x`height` keeping its aspect ratio, centred on a white canvas
+// of exactly that size; None when it already has those dimensions
+pub fn letterbox(data: &[u8], (width, height): (u32, u32)) -> ImageResult<Option<Vec<u8>>> {
+    let img = image::load_from_memory(data)?;
+    if img.dimensions() == (width, height) {
+        return Ok(None);
+    }
+
+    let fitted = img.resize(width, height, FilterType::Lanczos3);
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+    let x = (width - fitted.width()) / 2;
+    let y = (height - fitted.height()) / 2;
+    imageops::overlay(&mut canvas, &fitted.to_rgba8(), x as i64, y as i64);
+
+    let mut out = Vec::new();
+    DynamicImage::ImageRgba8(canvas).write_to(&mut Cursor::new(&mut out), ImageFormat::Png)?;
+    Ok(Some(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::png_fixture;
+
+    #[test]
+    fn jpeg_output_starts_with_soi_marker() {
+        let jpeg = encode_as(&png_fixture(8, 8), OutputFormat::Jpeg { quality: 80 }).unwrap();
+
+        assert_eq!(&jpeg[..2], &[0xFF, 0xD8]);
+    }
+
+    #[test]
+    fn png_output_passes_png_through() {
+        let png = png_fixture(8, 8);
+
+        assert_eq!(encode_as(&png, OutputFormat::Png).unwrap(), png);
+    }
+
+    #[test]
+    fn webp_output_has_riff_header() {
+        let webp = encode_as(&png_fixture(8, 8), OutputFormat::WebP).unwrap();
+
+        assert_eq!(&webp[..4], b"RIFF");
+        assert_eq!(&webp[8..12], b"WEBP");
+    }
+
+    #[test]
+    fn negotiate_prefers_highest_q_then_specific_types() {
+        assert_eq!(negotiate("image/webp"), OutputFormat::WebP);
+        assert_eq!(negotiate("*/*"), OutputFormat::Png);
+        assert_eq!(negotiate("*/*, image/webp"), OutputFormat::WebP);
+        assert_eq!(negotiate("image/webp;q=0.5, image/jpeg;q=0.8"), OutputFormat::Jpeg { quality: 90 });
+        assert_eq!(negotiate("text/html, image/avif"), OutputFormat::Png);
+        assert_eq!(negotiate("image/webp;q=0"), OutputFormat::Png);
+    }
+
+    #[test]
+    fn format_from_path_uses_extension() {
+        assert_eq!(OutputFormat::from_path("out/custom.JPG"), OutputFormat::Jpeg { quality: 90 });
+        assert_eq!(OutputFormat::from_path("custom.png"), OutputFormat::Png);
+        assert_eq!(OutputFormat::from_path("custom"), OutputFormat::Png);
+    }
+}
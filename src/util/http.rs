@@ -0,0 +1,202 @@
+use bytes::Bytes;
+use reqwest::{Client, RequestBuilder, StatusCode, header::HeaderMap};
+use serde::{Serialize, de::DeserializeOwned};
+use std::fmt;
+use std::time::Instant;
+use tracing::info;
+
+use crate::util::rate_limit::retry_after_secs;
+
+// Labels an upstream call in the logs
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamCall {
+    pub provider: &'static str,
+    pub op: &'static str,
+}
+
+// How an upstream status should be treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusClass {
+    Success,
+    // The request itself was wrong (bad key, bad input, unknown id); retrying won't help.
+    // Unfollowed redirects and other non-2xx codes land here too, nothing to retry there either
+    ClientError,
+    // The provider is struggling; another attempt (or provider) may succeed
+    ServerError,
+    // A 429: back off until the quota resets
+    Throttled,
+}
+
+impl StatusClass {
+    pub fn of(status: StatusCode) -> Self {
+        if status.is_success() {
+            StatusClass::Success
+        } else if status == StatusCode::TOO_MANY_REQUESTS {
+            StatusClass::Throttled
+        } else if status.is_server_error() {
+            StatusClass::ServerError
+        } else {
+            StatusClass::ClientError
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            StatusClass::Success => "success",
+            StatusClass::ClientError => "client_error",
+            StatusClass::ServerError => "server_error",
+            StatusClass::Throttled => "throttled",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UpstreamError {
+    // No response came back: refused connection, timeout, or a request that couldn't be built
+    Send(reqwest::Error),
+    // The provider answered with a non-2xx status
+    Status {
+        class: StatusClass,
+        status: StatusCode,
+        body: String,
+        // Seconds from Retry-After, when the provider sent one
+        retry_after: Option<u64>,
+    },
+    // A 2xx whose body couldn't be read or didn't deserialize
+    Body(String),
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamError::Send(e) => write!(f, "request failed: {}", e),
+            UpstreamError::Status { status, body, .. } => write!(f, "{} {}", status, body),
+            UpstreamError::Body(message) => write!(f, "unreadable response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for UpstreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UpstreamError::Send(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// POST `body` as JSON and deserialize a successful response as `T`
+pub async fn post_json_expect<T: DeserializeOwned>(
+    client: &Client,
+    call: UpstreamCall,
+    url: &str,
+    headers: HeaderMap,
+    body: &impl Serialize,
+) -> Result<T, UpstreamError> {
+    send_json_expect(call, client.post(url).headers(headers).json(body)).await
+}
+
+// Send `request` and deserialize a successful response as `T`
+pub async fn send_json_expect<T: DeserializeOwned>(call: UpstreamCall, request: RequestBuilder) -> Result<T, UpstreamError> {
+    let body = send_expect(call, request).await?;
+    serde_json::from_slice(&body).map_err(|e| UpstreamError::Body(e.to_string()))
+}
+
+// Send `request`, log its status class and latency, and return the body of a 2xx response
+pub async fn send_expect(call: UpstreamCall, request: RequestBuilder) -> Result<Bytes, UpstreamError> {
+    let started = Instant::now();
+    let response = request.send().await.map_err(UpstreamError::Send)?;
+
+    let status = response.status();
+    let class = StatusClass::of(status);
+    let retry_after = retry_after_secs(response.headers());
+    let body = response.bytes().await;
+
+    info!(
+        provider = call.provider,
+        op = call.op,
+        status = status.as_u16(),
+        class = class.as_str(),
+        latency_ms = started.elapsed().as_millis() as u64,
+        "upstream call"
+    );
+
+    match class {
+        StatusClass::Success => body.map_err(|e| UpstreamError::Body(e.to_string())),
+        _ => Err(UpstreamError::Status {
+            class,
+            status,
+            body: body.map(|b| String::from_utf8_lossy(&b).into_owned()).unwrap_or_default(),
+            retry_after,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::spawn_mock;
+    use axum::{Router, routing::post};
+    use serde_json::json;
+
+    #[test]
+    fn classifies_statuses() {
+        let cases = [
+            (200, StatusClass::Success),
+            (201, StatusClass::Success),
+            (202, StatusClass::Success),
+            (204, StatusClass::Success),
+            (304, StatusClass::ClientError),
+            (400, StatusClass::ClientError),
+            (401, StatusClass::ClientError),
+            (404, StatusClass::ClientError),
+            (429, StatusClass::Throttled),
+            (500, StatusClass::ServerError),
+            (502, StatusClass::ServerError),
+            (503, StatusClass::ServerError),
+        ];
+
+        for (code, expected) in cases {
+            assert_eq!(StatusClass::of(StatusCode::from_u16(code).unwrap()), expected, "{}", code);
+        }
+    }
+
+    #[tokio::test]
+    async fn post_json_expect_deserializes_or_reports_the_status() {
+        let base_url = spawn_mock(
+            Router::new()
+                .route("/ok", post(|| async { axum::Json(json!({ "result": "task-1" })) }))
+                .route("/garbled", post(|| async { "not json" }))
+                .route("/busy", post(|| async {
+                    (axum::http::StatusCode::TOO_MANY_REQUESTS, [("retry-after", "9")], "slow down")
+                })),
+        ).await;
+        let client = Client::new();
+        let call = UpstreamCall { provider: "test", op: "post" };
+        let post = |path: &str| {
+            let url = format!("{}{}", base_url, path);
+            let client = client.clone();
+            async move {
+                post_json_expect::<serde_json::Value>(&client, call, &url, HeaderMap::new(), &json!({})).await
+            }
+        };
+
+        assert_eq!(post("/ok").await.unwrap()["result"], "task-1");
+        assert!(matches!(post("/garbled").await, Err(UpstreamError::Body(_))));
+
+        match post("/busy").await {
+            Err(UpstreamError::Status { class, status, body, retry_after }) => {
+                assert_eq!(class, StatusClass::Throttled);
+                assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+                assert_eq!(body, "slow down");
+                assert_eq!(retry_after, Some(9));
+            }
+            other => panic!("expected a throttled status, got {:?}", other),
+        }
+
+        let refused = post_json_expect::<serde_json::Value>(
+            &client, call, "http://127.0.0.1:9/ok", HeaderMap::new(), &json!({}),
+        ).await;
+        assert!(matches!(refused, Err(UpstreamError::Send(e)) if e.is_connect()));
+    }
+}
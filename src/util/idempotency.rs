@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Remembers which task an Idempotency-Key produced, for a limited window
+pub struct IdempotencyStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Task id recorded for this key, unless it has expired
+    pub fn get(&self, key: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(_, created)| created.elapsed() < self.ttl)
+            .map(|(task_id, _)| task_id.clone())
+    }
+
+    pub fn insert(&self, key: String, task_id: String) {
+        self.entries.lock().unwrap().insert(key, (task_id, Instant::now()));
+    }
+
+    // Drop every expired key, returning how many went; run periodically by the cleanup task
+    pub fn evict_expired(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, (_, created)| created.elapsed() < self.ttl);
+        before - entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_stored_task_until_expiry() {
+        let store = IdempotencyStore::new(Duration::from_millis(50));
+        store.insert("key-1".to_string(), "task-1".to_string());
+
+        assert_eq!(store.get("key-1").as_deref(), Some("task-1"));
+        assert_eq!(store.get("key-2"), None);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(store.get("key-1"), None);
+    }
+
+    #[test]
+    fn evicts_only_expired_keys() {
+        let store = IdempotencyStore::new(Duration::from_millis(50));
+        store.insert("old".to_string(), "task-1".to_string());
+        std::thread::sleep(Duration::from_millis(60));
+        store.insert("new".to_string(), "task-2".to_string());
+
+        assert_eq!(store.evict_expired(), 1);
+        assert_eq!(store.evict_expired(), 0);
+        assert_eq!(store.get("new").as_deref(), Some("task-2"));
+    }
+}
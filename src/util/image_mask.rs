@@ -1,8 +1,11 @@
-use image::{GenericImageView, GrayImage, ImageBuffer, Luma, Rgb, RgbImage};
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
 use imageproc::drawing::{draw_filled_ellipse_mut, draw_filled_rect_mut};
 use imageproc::rect::Rect;
 use imageproc::filter::gaussian_blur_f32;
 use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+use tracing::debug;
 
 pub struct MaskGenerator;
 
@@ -20,6 +23,168 @@ pub enum MaskIntensity {
     Aggressive,
 }
 
+// Elliptical mask region as fractions of the image size (radii at scale 1.0)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PartRegion {
+    pub center_x: f32,
+    pub center_y: f32,
+    pub radius_x: f32,
+    pub radius_y: f32,
+}
+
+// Outline drawn for a part region before feathering
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MaskShape {
+    #[default]
+    Ellipse,
+    // Fills the region's bounding box, for boxy parts (top boxes, crash bars)
+    Rectangle,
+}
+
+// Feather radius for each side of a mask; 0.0 keeps that edge hard
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EdgeFeather {
+    pub top: f32,
+    pub bottom: f32,
+    pub left: f32,
+    pub right: f32,
+}
+
+// How mask edges are feathered
+#[allow(dead_code)] // `Box` is opt-in and nothing selects it yet
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BlurMethod {
+    // imageproc's Gaussian, cost grows with the radius
+    #[default]
+    Exact,
+    // Three running-sum box passes per axis, close to Gaussian and independent of the radius
+    Box,
+}
+
+// Tunable mask geometry, the defaults match the original hardcoded values
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaskConfig {
+    pub exhaust: PartRegion,   // 배기 파츠 영역 (우측 하단)
+    pub seat: PartRegion,      // 시트 영역 (중앙 상단)
+    pub handlebar: PartRegion, // 핸들바 영역 (전면 상단)
+    pub minimal_scale: f32,
+    pub medium_scale: f32,
+    pub aggressive_scale: f32,
+    pub blur_radius: f32,
+    pub blur_method: BlurMethod,
+}
+
+impl Default for MaskConfig {
+    fn default() -> Self {
+        Self {
+            exhaust: PartRegion { center_x: 0.5, center_y: 0.65, radius_x: 0.175, radius_y: 0.125 },
+            seat: PartRegion { center_x: 0.5, center_y: 0.45, radius_x: 0.15, radius_y: 0.12 },
+            handlebar: PartRegion { center_x: 0.4, center_y: 0.25, radius_x: 0.2, radius_y: 0.12 },
+            minimal_scale: 0.8,
+            medium_scale: 1.0,
+            aggressive_scale: 1.2,
+            blur_radius: 15.0,
+            blur_method: BlurMethod::Exact,
+        }
+    }
+}
+
+impl MaskConfig {
+    pub fn region(&self, part_type: PartType) -> PartRegion {
+        match part_type {
+            PartType::Exhaust => self.exhaust,
+            PartType::Seat => self.seat,
+            PartType::Handlebar => self.handlebar,
+        }
+    }
+
+    pub fn scale(&self, intensity: MaskIntensity) -> f32 {
+        match intensity {
+            MaskIntensity::Minimal => self.minimal_scale,
+            MaskIntensity::Medium => self.medium_scale,
+            MaskIntensity::Aggressive => self.aggressive_scale,
+        }
+    }
+}
+
+// Returned when a string doesn't name a known variant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidOptionError {
+    pub value: String,
+    pub expected: Vec<&'static str>,
+}
+
+impl fmt::Display for InvalidOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected one of {}, got '{}'", self.expected.join("|"), self.value)
+    }
+}
+
+impl std::error::Error for InvalidOptionError {}
+
+impl FromStr for PartType {
+    type Err = InvalidOptionError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let normalized = s.trim().to_ascii_lowercase();
+
+        PartType::all()
+            .iter()
+            .find(|part| part.as_str() == normalized)
+            .copied()
+            .ok_or_else(|| InvalidOptionError {
+                value: s.to_string(),
+                expected: PartType::all().iter().map(|p| p.as_str()).collect(),
+            })
+    }
+}
+
+impl FromStr for MaskIntensity {
+    type Err = InvalidOptionError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let normalized = s.trim().to_ascii_lowercase();
+
+        MaskIntensity::all()
+            .iter()
+            .find(|intensity| intensity.as_str() == normalized)
+            .copied()
+            .ok_or_else(|| InvalidOptionError {
+                value: s.to_string(),
+                expected: MaskIntensity::all().iter().map(|i| i.as_str()).collect(),
+            })
+    }
+}
+
+impl PartType {
+    pub fn all() -> &'static [PartType] {
+        &[PartType::Exhaust, PartType::Seat, PartType::Handlebar]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PartType::Exhaust => "exhaust",
+            PartType::Seat => "seat",
+            PartType::Handlebar => "handlebar",
+        }
+    }
+}
+
+impl MaskIntensity {
+    pub fn all() -> &'static [MaskIntensity] {
+        &[MaskIntensity::Minimal, MaskIntensity::Medium, MaskIntensity::Aggressive]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MaskIntensity::Minimal => "minimal",
+            MaskIntensity::Medium => "medium",
+            MaskIntensity::Aggressive => "aggressive",
+        }
+    }
+}
+
 impl MaskGenerator {
     // Create a mask for the specified motorcycle part
     pub fn create_part_mask(
@@ -27,69 +192,59 @@ impl MaskGenerator {
         image_height: u32,
         part_type: PartType,
         intensity: MaskIntensity,
+        config: &MaskConfig,
+    ) -> Result<GrayImage> {
+        debug!("Creating {:?} mask", part_type);
+        Self::create_region_mask(
+            image_width,
+            image_height,
+            config.region(part_type),
+            MaskShape::Ellipse,
+            intensity,
+            config,
+        )
+    }
+
+    // Mask for any region, scaled by the intensity and feathered like the built-in parts
+    pub fn create_region_mask(
+        image_width: u32,
+        image_height: u32,
+        region: PartRegion,
+        shape: MaskShape,
+        intensity: MaskIntensity,
+        config: &MaskConfig,
     ) -> Result<GrayImage> {
         let mut mask = GrayImage::new(image_width, image_height);
         
-        let scale = match intensity {
-            MaskIntensity::Minimal => 0.8,
-            MaskIntensity::Medium => 1.0,
-            MaskIntensity::Aggressive => 1.2,
-        };
+        let scale = config.scale(intensity);
         
         let white = Luma([255u8]);
         
-        match part_type {
-            PartType::Exhaust => {
-                // 배기 파츠 영역 (우측 하단)
-                let x = (image_width as f32 * 0.5) as i32;
-                let y = (image_height as f32 * 0.65) as i32;
-                let width = (image_width as f32 * 0.35 * scale / 2.0) as i32;
-                let height = (image_height as f32 * 0.25 * scale / 2.0) as i32;
-                
-                println!("Creating exhaust mask at ({}, {}) with size ({}, {})", x, y, width, height);
-
-                draw_filled_ellipse_mut(
-                    &mut mask,
-                    (x, y),
-                    width,
-                    height,
-                    white,
-                );
-            }
-            PartType::Seat => {
-                // 시트 영역 (중앙 상단)
-                let x = (image_width as f32 * 0.5) as i32;
-                let y = (image_height as f32 * 0.45) as i32;
-                let width = (image_width as f32 * 0.15 * scale) as i32;
-                let height = (image_height as f32 * 0.12 * scale) as i32;
-                
-                draw_filled_ellipse_mut(
-                    &mut mask,
-                    (x, y),
-                    width,
-                    height,
-                    white,
-                );
-            }
-            PartType::Handlebar => {
-                // 핸들바 영역 (전면 상단)
-                let x = (image_width as f32 * 0.4) as i32;
-                let y = (image_height as f32 * 0.25) as i32;
-                let width = (image_width as f32 * 0.2 * scale) as i32;
-                let height = (image_height as f32 * 0.12 * scale) as i32;
-                
-                draw_filled_ellipse_mut(
-                    &mut mask,
-                    (x, y),
-                    width,
-                    height,
-                    white,
-                );
-            }
+        let x = (image_width as f32 * region.center_x) as i32;
+        let y = (image_height as f32 * region.center_y) as i32;
+        let width = (image_width as f32 * region.radius_x * scale) as i32;
+        let height = (image_height as f32 * region.radius_y * scale) as i32;
+        
+        debug!("Creating {:?} mask at ({}, {}) with size ({}, {})", shape, x, y, width, height);
+
+        match shape {
+            MaskShape::Ellipse => draw_filled_ellipse_mut(
+                &mut mask,
+                (x, y),
+                width,
+                height,
+                white,
+            ),
+            MaskShape::Rectangle if width > 0 && height > 0 => draw_filled_rect_mut(
+                &mut mask,
+                Rect::at(x - width, y - height).of_size(2 * width as u32, 2 * height as u32),
+                white,
+            ),
+            MaskShape::Rectangle => {}
         }
 
         // Soft border (Gaussian Blur)
-        let blurred_mask = gaussian_blur_f32(&mask, 15.0);
+        let blurred_mask = Self::feather(&mask, config.blur_radius, config.blur_method);
         
         Ok(blurred_mask)
     }
@@ -104,7 +259,7 @@ impl MaskGenerator {
         let (width, height) = img.dimensions();
         
         println!("  🖼️  Generating mask for {:?} with {:?} intensity...", part_type, intensity);
-        Self::create_part_mask(width, height, part_type, intensity)
+        Self::create_part_mask(width, height, part_type, intensity, &MaskConfig::default())
     }
     
     // Convert GrayImage mask to RgbImage mask
@@ -120,7 +275,31 @@ impl MaskGenerator {
         rgb_mask
     }
 
+    // Overlay the mask on the base image as a semi-transparent red region (for previews)
+    pub fn overlay_preview(base: &DynamicImage, mask: &GrayImage) -> RgbaImage {
+        let mut preview = base.to_rgba8();
+
+        for (x, y, pixel) in preview.enumerate_pixels_mut() {
+            let coverage = match mask.get_pixel_checked(x, y) {
+                Some(value) => value[0] as f32 / 255.0,
+                None => continue,
+            };
+            let alpha = coverage * 0.5;
+            let Rgba([r, g, b, a]) = *pixel;
+
+            *pixel = Rgba([
+                (r as f32 * (1.0 - alpha) + 255.0 * alpha) as u8,
+                (g as f32 * (1.0 - alpha)) as u8,
+                (b as f32 * (1.0 - alpha)) as u8,
+                a,
+            ]);
+        }
+
+        preview
+    }
+
     // Gernerate a custom elliptical mask
+    #[allow(dead_code)]
     pub fn create_custom_mask(
         image_width: u32,
         image_height: u32,
@@ -154,4 +333,301 @@ impl MaskGenerator {
             Ok(mask)
         }
     }
-}
\ No newline at end of file
+
+    // Like `create_custom_mask`, but each edge gets its own feather radius
+    // (e.g. a hard edge along the frame, a soft one into open air)
+    #[allow(dead_code)]
+    pub fn create_custom_mask_directional(
+        image_width: u32,
+        image_height: u32,
+        region_x: f32,
+        region_y: f32,
+        region_width: f32,
+        region_height: f32,
+        feather: EdgeFeather,
+    ) -> Result<GrayImage> {
+        let hard = Self::create_custom_mask(
+            image_width, image_height, region_x, region_y, region_width, region_height, 0.0,
+        )?;
+
+        // Blur each axis on its own, picking the radius by which side of the center a pixel is on
+        let center_x = (image_width as f32 * region_x) as u32;
+        let center_y = (image_height as f32 * region_y) as u32;
+        let horizontal = Self::blur_sides(&hard, center_x, feather.left, feather.right, true);
+
+        Ok(Self::blur_sides(&horizontal, center_y, feather.top, feather.bottom, false))
+    }
+
+    fn feather(mask: &GrayImage, sigma: f32, method: BlurMethod) -> GrayImage {
+        match method {
+            BlurMethod::Exact => gaussian_blur_f32(mask, sigma),
+            BlurMethod::Box => Self::box_blur(mask, sigma),
+        }
+    }
+
+    // Gaussian approximated by three box blurs whose widths add up to the same variance
+    // (see Kutskir, "Fastest Gaussian Blur"); each pass is a running sum, O(1) per pixel
+    fn box_blur(mask: &GrayImage, sigma: f32) -> GrayImage {
+        if sigma <= 0.0 {
+            return mask.clone();
+        }
+
+        // imageproc cuts its kernel off at 2 sigma without renormalizing, which both narrows and
+        // dims the blur; match its spread and weight so switching methods keeps masks comparable
+        let reach = (2.0 * sigma).ceil() as i32;
+        let weights: Vec<(f32, f32)> = (-reach..=reach)
+            .map(|i| (i as f32, (-((i * i) as f32) / (2.0 * sigma * sigma)).exp()))
+            .collect();
+        let total: f32 = weights.iter().map(|(_, w)| w).sum();
+        let spread = (weights.iter().map(|(i, w)| i * i * w).sum::<f32>() / total).sqrt();
+        let mass = total / ((2.0 * std::f32::consts::PI).sqrt() * sigma);
+        let gain = mass * mass;
+
+        let (width, height) = (mask.width() as usize, mask.height() as usize);
+        let mut pixels: Vec<f32> = mask.as_raw().iter().map(|&v| v as f32).collect();
+        let mut scratch = vec![0.0; pixels.len()];
+
+        for radius in Self::box_radii(spread) {
+            // Rows, then columns
+            for y in 0..height {
+                Self::box_pass(&pixels, &mut scratch, y * width, 1, width, radius);
+            }
+            for x in 0..width {
+                Self::box_pass(&scratch, &mut pixels, x, width, height, radius);
+            }
+        }
+
+        GrayImage::from_raw(
+            width as u32,
+            height as u32,
+            pixels.iter().map(|v| (v * gain).round().clamp(0.0, 255.0) as u8).collect(),
+        ).expect("buffer matches mask size")
+    }
+
+    // Radii of the three boxes approximating a Gaussian with the given sigma
+    fn box_radii(sigma: f32) -> [usize; 3] {
+        const PASSES: f32 = 3.0;
+        let ideal = (12.0 * sigma * sigma / PASSES + 1.0).sqrt();
+        let mut lower = ideal.floor() as i32;
+        if lower % 2 == 0 {
+            lower -= 1;
+        }
+        let lower_f = lower as f32;
+        let smaller = ((12.0 * sigma * sigma - PASSES * lower_f * lower_f - 4.0 * PASSES * lower_f - 3.0 * PASSES)
+            / (-4.0 * lower_f - 4.0)).round() as i32;
+
+        std::array::from_fn(|pass| {
+            let width = if (pass as i32) < smaller { lower } else { lower + 2 };
+            (width.max(1) as usize - 1) / 2
+        })
+    }
+
+    // Box blur one row or column of `len` values spaced `stride` apart, clamping at the ends
+    fn box_pass(src: &[f32], dst: &mut [f32], start: usize, stride: usize, len: usize, radius: usize) {
+        let at = |i: isize| src[start + i.clamp(0, len as isize - 1) as usize * stride];
+        let r = radius as isize;
+        let window = (2 * radius + 1) as f32;
+
+        let mut sum: f32 = (-r..=r).map(at).sum();
+        for i in 0..len as isize {
+            dst[start + i as usize * stride] = sum / window;
+            sum += at(i + r + 1) - at(i - r);
+        }
+    }
+
+    // Blur along one axis with `before` up to `split` and `after` from there on
+    fn blur_sides(mask: &GrayImage, split: u32, before: f32, after: f32, horizontal: bool) -> GrayImage {
+        let near = Self::blur_axis(mask, before, horizontal);
+        let far = Self::blur_axis(mask, after, horizontal);
+
+        GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+            let position = if horizontal { x } else { y };
+            if position < split { *near.get_pixel(x, y) } else { *far.get_pixel(x, y) }
+        })
+    }
+
+    // 1-D Gaussian blur along one axis, clamping at the image border
+    fn blur_axis(mask: &GrayImage, sigma: f32, horizontal: bool) -> GrayImage {
+        if sigma <= 0.0 {
+            return mask.clone();
+        }
+
+        let radius = (sigma * 3.0).ceil() as i32;
+        let kernel: Vec<f32> = (-radius..=radius)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let total: f32 = kernel.iter().sum();
+        let (width, height) = mask.dimensions();
+
+        GrayImage::from_fn(width, height, |x, y| {
+            let sum: f32 = kernel.iter().zip(-radius..=radius)
+                .map(|(weight, offset)| {
+                    let (sx, sy) = if horizontal {
+                        ((x as i32 + offset).clamp(0, width as i32 - 1) as u32, y)
+                    } else {
+                        (x, (y as i32 + offset).clamp(0, height as i32 - 1) as u32)
+                    };
+                    weight * mask.get_pixel(sx, sy)[0] as f32
+                })
+                .sum();
+            Luma([(sum / total).round() as u8])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_valid_part_types() {
+        assert!(matches!("exhaust".parse::<PartType>(), Ok(PartType::Exhaust)));
+        assert!(matches!("seat".parse::<PartType>(), Ok(PartType::Seat)));
+        assert!(matches!("handlebar".parse::<PartType>(), Ok(PartType::Handlebar)));
+    }
+
+    #[test]
+    fn parses_case_insensitively() {
+        assert!(matches!(" Exhaust ".parse::<PartType>(), Ok(PartType::Exhaust)));
+        assert!(matches!("AGGRESSIVE".parse::<MaskIntensity>(), Ok(MaskIntensity::Aggressive)));
+    }
+
+    #[test]
+    fn invalid_values_list_accepted_options() {
+        let err = "wheel".parse::<PartType>().unwrap_err();
+        assert_eq!(err.to_string(), "expected one of exhaust|seat|handlebar, got 'wheel'");
+
+        let err = "extreme".parse::<MaskIntensity>().unwrap_err();
+        assert_eq!(err.to_string(), "expected one of minimal|medium|aggressive, got 'extreme'");
+    }
+
+    // Number of columns on the given row that are mostly white
+    fn white_extent(mask: &GrayImage, row: u32) -> usize {
+        (0..mask.width()).filter(|&x| mask.get_pixel(x, row)[0] > 127).count()
+    }
+
+    #[test]
+    fn custom_config_changes_white_region_extent() {
+        let default_mask = MaskGenerator::create_part_mask(
+            400, 300, PartType::Exhaust, MaskIntensity::Medium, &MaskConfig::default(),
+        ).unwrap();
+
+        let mut config = MaskConfig::default();
+        config.exhaust.radius_x *= 1.5;
+        config.blur_radius = 2.0;
+        let wider_mask = MaskGenerator::create_part_mask(
+            400, 300, PartType::Exhaust, MaskIntensity::Medium, &config,
+        ).unwrap();
+
+        let row = (300.0 * config.exhaust.center_y) as u32;
+        assert!(white_extent(&wider_mask, row) > white_extent(&default_mask, row));
+    }
+
+    #[test]
+    fn intensity_scale_comes_from_config() {
+        let config = MaskConfig { aggressive_scale: 0.5, ..MaskConfig::default() };
+        let mask = MaskGenerator::create_part_mask(
+            400, 300, PartType::Seat, MaskIntensity::Aggressive, &config,
+        ).unwrap();
+        let medium = MaskGenerator::create_part_mask(
+            400, 300, PartType::Seat, MaskIntensity::Medium, &config,
+        ).unwrap();
+
+        let row = (300.0 * config.seat.center_y) as u32;
+        assert!(white_extent(&mask, row) < white_extent(&medium, row));
+    }
+
+    // Partially white pixels on a row, i.e. how wide the feathered transition is
+    fn soft_pixels(mask: &GrayImage, row: u32, columns: std::ops::Range<u32>) -> usize {
+        columns.filter(|&x| (1..255).contains(&mask.get_pixel(x, row)[0])).count()
+    }
+
+    // Largest per-pixel difference between two masks of the same size
+    fn max_difference(a: &GrayImage, b: &GrayImage) -> u8 {
+        a.pixels().zip(b.pixels()).map(|(p, q)| p[0].abs_diff(q[0])).max().unwrap()
+    }
+
+    fn part_mask_with(width: u32, height: u32, blur_method: BlurMethod) -> GrayImage {
+        let config = MaskConfig { blur_method, ..MaskConfig::default() };
+        MaskGenerator::create_part_mask(width, height, PartType::Exhaust, MaskIntensity::Medium, &config).unwrap()
+    }
+
+    #[test]
+    fn box_blur_stays_close_to_exact_blur() {
+        let exact = part_mask_with(400, 300, BlurMethod::Exact);
+        let approx = part_mask_with(400, 300, BlurMethod::Box);
+
+        assert_eq!(approx.dimensions(), exact.dimensions());
+        assert!(max_difference(&exact, &approx) <= 8, "max difference {}", max_difference(&exact, &approx));
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with: cargo test --release -- --ignored box_blur_is_faster"]
+    fn box_blur_is_faster_on_4k_masks() {
+        let started = std::time::Instant::now();
+        let exact = part_mask_with(3840, 2160, BlurMethod::Exact);
+        let exact_time = started.elapsed();
+
+        let started = std::time::Instant::now();
+        let approx = part_mask_with(3840, 2160, BlurMethod::Box);
+        let box_time = started.elapsed();
+
+        println!("4K mask: exact {:?}, box {:?}", exact_time, box_time);
+        assert!(box_time < exact_time);
+        assert!(max_difference(&exact, &approx) <= 8);
+    }
+
+    #[test]
+    fn directional_feather_softens_only_the_requested_edges() {
+        let feather = EdgeFeather { right: 6.0, ..EdgeFeather::default() };
+        let mask = MaskGenerator::create_custom_mask_directional(200, 100, 0.5, 0.5, 0.3, 0.3, feather).unwrap();
+
+        assert!(soft_pixels(&mask, 50, 0..100) <= 1, "left edge should stay hard");
+        assert!(soft_pixels(&mask, 50, 100..200) > 5, "right edge should be feathered");
+        // Untouched vertical edges stay hard along the center column
+        let soft_in_column = (0..100).filter(|&y| (1..255).contains(&mask.get_pixel(100, y)[0])).count();
+        assert!(soft_in_column <= 2);
+    }
+
+    // Golden masks live in testdata/masks; rerun with UPDATE_GOLDEN_MASKS=1 to accept a geometry change
+    const GOLDEN_SIZE: (u32, u32) = (160, 120);
+    // Per-pixel slack so blur rounding differences don't fail the comparison
+    const GOLDEN_TOLERANCE: u8 = 2;
+
+    #[test]
+    fn masks_match_golden_images() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/masks");
+        let update = std::env::var_os("UPDATE_GOLDEN_MASKS").is_some();
+        let mut mismatches = Vec::new();
+
+        for &part_type in PartType::all() {
+            for &intensity in MaskIntensity::all() {
+                let mask = MaskGenerator::create_part_mask(
+                    GOLDEN_SIZE.0, GOLDEN_SIZE.1, part_type, intensity, &MaskConfig::default(),
+                ).unwrap();
+                let path = dir.join(format!("{}_{}.png", part_type.as_str(), intensity.as_str()));
+
+                if update {
+                    std::fs::create_dir_all(&dir).unwrap();
+                    mask.save(&path).unwrap();
+                    continue;
+                }
+
+                let golden = image::open(&path)
+                    .unwrap_or_else(|e| panic!("missing golden {}: {}", path.display(), e))
+                    .to_luma8();
+                assert_eq!(golden.dimensions(), mask.dimensions(), "{}", path.display());
+
+                let worst = mask.pixels().zip(golden.pixels())
+                    .map(|(a, b)| a[0].abs_diff(b[0]))
+                    .max()
+                    .unwrap_or(0);
+                if worst > GOLDEN_TOLERANCE {
+                    mismatches.push(format!("{} (max diff {})", path.display(), worst));
+                }
+            }
+        }
+
+        assert!(mismatches.is_empty(), "masks differ from golden images: {:?}", mismatches);
+    }
+}
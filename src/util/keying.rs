@@ -0,0 +1,95 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+
+// How far from pure white (255 minus the darkest channel) still counts as background
+pub const DEFAULT_WHITE_TOLERANCE: u8 = 24;
+
+// Turn the white background around an extracted part transparent.
+// Only white connected to the border is removed, so white areas inside the part
+// (chrome highlights, a white seat) stay opaque. Pixels right next to the removed
+// background get a partial alpha from how white they are, keeping anti-aliased edges smooth.
+pub fn white_to_alpha(image: &DynamicImage, tolerance: u8) -> RgbaImage {
+    let mut rgba = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let index = |x: u32, y: u32| (y * width + x) as usize;
+    let gap = |pixel: &Rgba<u8>| 255 - pixel[0].min(pixel[1]).min(pixel[2]);
+
+    // Flood fill from every border pixel through near-white pixels
+    let mut background = vec![false; (width * height) as usize];
+    let mut stack: Vec<(u32, u32)> = (0..width)
+        .flat_map(|x| [(x, 0), (x, height.saturating_sub(1))])
+        .chain((0..height).flat_map(|y| [(0, y), (width.saturating_sub(1), y)]))
+        .collect();
+
+    while let Some((x, y)) = stack.pop() {
+        if background[index(x, y)] || gap(rgba.get_pixel(x, y)) > tolerance {
+            continue;
+        }
+        background[index(x, y)] = true;
+
+        if x > 0 { stack.push((x - 1, y)); }
+        if y > 0 { stack.push((x, y - 1)); }
+        if x + 1 < width { stack.push((x + 1, y)); }
+        if y + 1 < height { stack.push((x, y + 1)); }
+    }
+
+    // Edge pixels fade from transparent at `tolerance` to opaque at `soft_limit`
+    let soft_limit = tolerance.saturating_mul(4).max(tolerance.saturating_add(1));
+    let touches_background = |x: u32, y: u32| {
+        (x.saturating_sub(1)..=(x + 1).min(width - 1))
+            .any(|nx| (y.saturating_sub(1)..=(y + 1).min(height - 1)).any(|ny| background[index(nx, ny)]))
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            if background[index(x, y)] {
+                rgba.get_pixel_mut(x, y)[3] = 0;
+                continue;
+            }
+
+            let pixel_gap = gap(rgba.get_pixel(x, y));
+            if pixel_gap >= soft_limit || !touches_background(x, y) {
+                continue;
+            }
+
+            let alpha = (pixel_gap - tolerance) as f32 / (soft_limit - tolerance) as f32;
+            let pixel = rgba.get_pixel_mut(x, y);
+            // Take the white back out of the blended colour so edges don't halo
+            for channel in 0..3 {
+                let unblended = (pixel[channel] as f32 - 255.0 * (1.0 - alpha)) / alpha;
+                pixel[channel] = unblended.round().clamp(0.0, 255.0) as u8;
+            }
+            pixel[3] = (alpha * pixel[3] as f32).round() as u8;
+        }
+    }
+
+    rgba
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn keys_border_white_but_keeps_enclosed_white_and_softens_edges() {
+        // White canvas, dark ring around a white core, one light-grey anti-aliased pixel outside
+        let mut img = RgbImage::from_pixel(20, 20, image::Rgb([255, 255, 255]));
+        for y in 5..15 {
+            for x in 5..15 {
+                let ring = x == 5 || x == 14 || y == 5 || y == 14;
+                if ring {
+                    img.put_pixel(x, y, image::Rgb([30, 30, 30]));
+                }
+            }
+        }
+        img.put_pixel(4, 10, image::Rgb([200, 200, 200]));
+
+        let keyed = white_to_alpha(&DynamicImage::ImageRgb8(img), DEFAULT_WHITE_TOLERANCE);
+
+        assert_eq!(keyed.get_pixel(0, 0)[3], 0);
+        assert_eq!(keyed.get_pixel(5, 5)[3], 255);
+        assert_eq!(keyed.get_pixel(10, 10)[3], 255, "white inside the part must stay");
+        let edge = keyed.get_pixel(4, 10)[3];
+        assert!(edge > 0 && edge < 255, "edge alpha {}", edge);
+    }
+}
@@ -0,0 +1,299 @@
+use bytes::Bytes;
+use std::ops::Deref;
+use tracing::info;
+
+// Every magic number checked here sits within the first 16 bytes (WebP's fourCC ends at 12)
+pub const SNIFF_LEN: usize = 16;
+
+// The leading bytes the sniffers look at, however short the input is
+pub fn sniff_header(bytes: &[u8]) -> &[u8] {
+    &bytes[..bytes.len().min(SNIFF_LEN)]
+}
+
+// Sniff the image MIME type from its magic bytes; the `sniff_header` of the data is enough
+pub fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(&[0x89, 0x50, 0x4E, 0x47]) {
+        Some("image/png")
+    } else if bytes.starts_with(&[0x47, 0x49, 0x46]) {
+        Some("image/gif")
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP".as_slice()) {
+        // RIFF is shared with WAV/AVI, only the WEBP fourCC makes it an image
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+// Vector documents are recognised only to refuse them: no provider takes anything but raster
+// images. SVG may start with a UTF-8 BOM or whitespace, then an XML prolog or the `<svg` tag.
+pub fn sniff_vector(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"%PDF") {
+        return Some("application/pdf");
+    }
+    let text = bytes.strip_prefix(b"\xEF\xBB\xBF".as_slice()).unwrap_or(bytes).trim_ascii_start();
+    (text.starts_with(b"<?xml") || text.starts_with(b"<svg")).then_some("image/svg+xml")
+}
+
+// Refuse SVG and PDF uploads by name, rather than letting them pass as a mislabelled JPEG
+pub fn check_raster(bytes: &[u8]) -> Result<(), String> {
+    match sniff_vector(bytes) {
+        Some(mime) => Err(format!(
+            "{} is a vector format; only raster images (JPEG, PNG, GIF or WebP) are supported",
+            mime
+        )),
+        None => Ok(()),
+    }
+}
+
+// Detect the MIME type sent to upstream APIs, defaulting to JPEG for unknown formats
+pub fn detect_mime(bytes: &[u8]) -> &'static str {
+    match sniff_mime(bytes) {
+        Some(mime) => mime,
+        None => {
+            info!("Unknown image format, defaulting to image/jpeg");
+            "image/jpeg"
+        }
+    }
+}
+
+// Check that an upload is non-empty and looks like a supported image
+pub fn validate_image(bytes: &[u8]) -> Result<&'static str, String> {
+    if bytes.is_empty() {
+        return Err("file is empty".to_string());
+    }
+    check_raster(bytes)?;
+
+    sniff_mime(bytes)
+        .ok_or_else(|| "not a recognized image (expected JPEG, PNG, GIF or WebP)".to_string())
+}
+
+// Image data together with its format, sniffed once where the upload comes in
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImageBytes {
+    data: Bytes,
+    mime: &'static str,
+}
+
+impl ImageBytes {
+    // Accept anything, labelling unknown formats as JPEG like `detect_mime`
+    pub fn new(data: Bytes) -> Self {
+        let mime = detect_mime(sniff_header(&data));
+        Self { data, mime }
+    }
+
+    // Only accept data that is a supported image, see `validate_image`
+    pub fn validated(data: Bytes) -> Result<Self, String> {
+        let mime = validate_image(sniff_header(&data))?;
+        Ok(Self { data, mime })
+    }
+
+    pub fn mime(&self) -> &'static str {
+        self.mime
+    }
+}
+
+impl Deref for ImageBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+// HEIC/HEIF is an ISO-BMFF container: an `ftyp` box whose major brand names the HEIF family
+pub fn is_heic(bytes: &[u8]) -> bool {
+    const BRANDS: [&[u8]; 8] = [b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1"];
+
+    bytes.get(4..8) == Some(b"ftyp".as_slice())
+        && bytes.get(8..12).is_some_and(|brand| BRANDS.contains(&brand))
+}
+
+// Decode a HEIC image and re-encode it as PNG
+#[cfg(feature = "heic")]
+pub fn heic_to_png(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(bytes).map_err(|e| e.to_string())?;
+    let handle = context.primary_image_handle().map_err(|e| e.to_string())?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .map_err(|e| e.to_string())?;
+
+    let plane = decoded.planes().interleaved.ok_or("decoded HEIC has no interleaved RGB plane")?;
+    let row_bytes = plane.width as usize * 3;
+    let mut pixels = Vec::with_capacity(row_bytes * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_bytes]);
+    }
+
+    let rgb = image::RgbImage::from_raw(plane.width, plane.height, pixels)
+        .ok_or("decoded HEIC has an unexpected buffer size")?;
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(rgb)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+
+    Ok(png)
+}
+
+// Providers expect a still, and an animated GIF gives unpredictable results; keep only
+// the first frame (composited onto the full canvas) as PNG
+pub fn gif_first_frame_png(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    use image::AnimationDecoder;
+    use image::codecs::gif::GifDecoder;
+
+    let decoder = GifDecoder::new(std::io::Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    let frame = decoder.into_frames().next()
+        .ok_or("GIF has no frames")?
+        .map_err(|e| e.to_string())?;
+
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(frame.into_buffer())
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+// Binary glTF (GLB) files start with the ASCII magic "glTF" (0x46546C67 little-endian)
+pub fn is_glb(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"glTF")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_bytes_records_png_mime() {
+        let png = crate::test_support::png_fixture(4, 4);
+        let image = ImageBytes::validated(Bytes::from(png.clone())).unwrap();
+
+        assert_eq!(image.mime(), "image/png");
+        assert_eq!(&*image, png.as_slice());
+    }
+
+    #[test]
+    fn image_bytes_validation_rejects_non_images() {
+        assert!(ImageBytes::validated(Bytes::from_static(b"not an image")).is_err());
+        assert_eq!(ImageBytes::new(Bytes::from_static(b"not an image")).mime(), "image/jpeg");
+    }
+
+    #[test]
+    fn detects_genuine_webp() {
+        let header = b"RIFF\x24\x00\x00\x00WEBPVP8 ";
+        assert_eq!(detect_mime(header), "image/webp");
+    }
+
+    #[test]
+    fn non_webp_riff_falls_through_to_default() {
+        let wav = b"RIFF\x24\x00\x00\x00WAVEfmt ";
+        let avi = b"RIFF\x24\x00\x00\x00AVI LIST";
+        assert_eq!(sniff_mime(wav), None);
+        assert_eq!(sniff_mime(avi), None);
+        assert_eq!(detect_mime(wav), "image/jpeg");
+    }
+
+    #[test]
+    fn truncated_riff_is_not_webp() {
+        assert_eq!(sniff_mime(b"RIFF\x24\x00"), None);
+    }
+
+    #[test]
+    fn validates_uploads() {
+        assert_eq!(validate_image(&[0x89, 0x50, 0x4E, 0x47]), Ok("image/png"));
+        assert_eq!(validate_image(&[]), Err("file is empty".to_string()));
+        assert!(validate_image(b"not an image").is_err());
+    }
+
+    #[test]
+    fn recognizes_vector_formats() {
+        assert_eq!(sniff_vector(b"%PDF-1.7\n%\xE2\xE3"), Some("application/pdf"));
+        assert_eq!(sniff_vector(b"<svg xmlns=\"http"), Some("image/svg+xml"));
+        assert_eq!(sniff_vector(b"\xEF\xBB\xBF  <?xml vers"), Some("image/svg+xml"));
+        assert_eq!(sniff_vector(&[0x89, 0x50, 0x4E, 0x47]), None);
+        assert_eq!(sniff_vector(b"<html>"), None);
+
+        let reason = validate_image(b"%PDF-1.7").unwrap_err();
+        assert!(reason.contains("only raster images"), "{}", reason);
+        assert_eq!(check_raster(b"GIF89a"), Ok(()));
+    }
+
+    #[test]
+    fn recognizes_glb_magic() {
+        assert!(is_glb(b"glTF\x02\x00\x00\x00"));
+        assert!(!is_glb(b"<html><body>error</body></html>"));
+        assert!(!is_glb(b"glT"));
+    }
+
+    #[test]
+    fn detects_common_formats() {
+        assert_eq!(detect_mime(&[0xFF, 0xD8, 0xFF, 0xE0]), "image/jpeg");
+        assert_eq!(detect_mime(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A]), "image/png");
+        assert_eq!(detect_mime(b"GIF89a"), "image/gif");
+    }
+
+    #[test]
+    fn sniffs_the_header_the_same_as_the_whole_file() {
+        let png = crate::test_support::png_fixture(4, 4);
+        assert_eq!(sniff_header(&png).len(), SNIFF_LEN);
+        assert_eq!(sniff_mime(sniff_header(&png)), sniff_mime(&png));
+
+        let webp = b"RIFF\x24\x00\x00\x00WEBPVP8 \x00\x00\x00\x00trailing data";
+        assert_eq!(sniff_mime(sniff_header(webp)), Some("image/webp"));
+        assert_eq!(sniff_header(b"GIF"), b"GIF");
+    }
+
+    #[test]
+    fn truncated_headers_do_not_panic() {
+        let mut heic = b"\0\0\0\x18ftypheic".to_vec();
+        heic.extend_from_slice(b"\0\0\0\0");
+        let magics: [&[u8]; 6] = [
+            &[0xFF, 0xD8, 0xFF, 0xE0],
+            &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A],
+            b"GIF89a",
+            b"RIFF\x24\x00\x00\x00WEBPVP8 ",
+            &heic,
+            b"glTF\x02\x00\x00\x00",
+        ];
+
+        for magic in magics {
+            for len in 0..12.min(magic.len()) {
+                let truncated = sniff_header(&magic[..len]);
+                let _ = sniff_mime(truncated);
+                let _ = detect_mime(truncated);
+                let _ = validate_image(truncated);
+                let _ = sniff_vector(truncated);
+                let _ = is_heic(truncated);
+                let _ = is_glb(truncated);
+            }
+        }
+        assert_eq!(sniff_mime(b"RIFF\x24\x00\x00\x00WEB"), None);
+        assert!(!is_heic(b"\0\0\0\x18ftyphei"));
+    }
+
+    #[test]
+    fn keeps_the_first_frame_of_an_animated_gif() {
+        let gif = crate::test_support::animated_gif_fixture(8, 6, &[[255, 0, 0], [0, 0, 255], [0, 255, 0]]);
+        assert_eq!(sniff_mime(&gif), Some("image/gif"));
+
+        let png = gif_first_frame_png(&gif).unwrap();
+        assert_eq!(sniff_mime(&png), Some("image/png"));
+        let frame = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(frame.dimensions(), (8, 6));
+        assert_eq!(frame.get_pixel(4, 3).0, [255, 0, 0, 255]);
+
+        assert!(gif_first_frame_png(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn detects_heic_brands() {
+        let mut heic = vec![0x00, 0x00, 0x00, 0x18];
+        heic.extend_from_slice(b"ftypheic\0\0\0\0mif1heic");
+
+        assert!(is_heic(&heic));
+        assert!(!is_heic(b"\0\0\0\x18ftypisom"));
+        assert!(!is_heic(&[0xFF, 0xD8, 0xFF, 0xE0]));
+    }
+}
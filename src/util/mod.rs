@@ -1 +1,18 @@
-pub mod image_mask;
\ No newline at end of file
+pub mod circuit_breaker;
+pub mod contact_sheet;
+pub mod debug_dump;
+pub mod encode;
+pub mod http;
+pub mod idempotency;
+pub mod image_mask;
+pub mod keying;
+pub mod mime;
+pub mod multipart;
+pub mod post_process;
+pub mod prompt;
+pub mod rate_limit;
+pub mod sdxl;
+pub mod single_flight;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod temp;
\ No newline at end of file
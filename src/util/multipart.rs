@@ -0,0 +1,147 @@
+use axum::extract::Multipart;
+use axum::extract::multipart::MultipartError;
+use axum::http::StatusCode;
+use bytes::Bytes;
+use std::collections::HashMap;
+use tracing::info;
+
+// Everything in a drained form, split by what the caller treats as images
+#[derive(Debug, Default)]
+pub struct FormParts {
+    // Fields picked by the predicate, in arrival order
+    pub images: Vec<(String, Bytes)>,
+    // Client file names of those fields, by position in `images`, when one was sent
+    pub file_names: Vec<Option<String>>,
+    // Other uploads (e.g. `texture_image`)
+    pub files: HashMap<String, Bytes>,
+    // Plain text fields
+    pub fields: HashMap<String, String>,
+}
+
+// The usual image field names: `image`, `image_motorcycle`, `image2`, ... or `file`
+pub fn is_image_field(name: &str) -> bool {
+    name.starts_with("image") || name == "file"
+}
+
+// A form with no fields at all gets the same answer everywhere, rather than whichever
+// field a handler happens to look for first
+pub fn empty_form_error() -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, "expected multipart form data with image fields".to_string())
+}
+
+// An upload over MAX_UPLOAD_BYTES, the same answer however it was sent
+pub fn upload_too_large_error() -> (StatusCode, String) {
+    (StatusCode::PAYLOAD_TOO_LARGE, "upload exceeds the maximum upload size".to_string())
+}
+
+// A field that couldn't be read; running into the body limit is reported as such
+fn field_error(e: MultipartError, context: String) -> (StatusCode, String) {
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => upload_too_large_error(),
+        _ => (StatusCode::BAD_REQUEST, format!("{}: {}", context, e)),
+    }
+}
+
+// Drain the form, keeping fields whose name passes `is_image` as raw bytes.
+// Fails on unreadable fields, or when the form has no fields at all.
+pub async fn collect_form(
+    multipart: &mut Multipart,
+    is_image: impl Fn(&str) -> bool,
+) -> Result<FormParts, (StatusCode, String)> {
+    let mut form = FormParts::default();
+    let mut field_count = 0;
+
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| field_error(e, "Failed to read field".to_string()))?
+    {
+        field_count += 1;
+        let name = field.name().unwrap_or("unknown").to_string();
+        let file_name = field.file_name().map(str::to_string);
+
+        if is_image(&name) || file_name.is_some() {
+            let data = field.bytes().await
+                .map_err(|e| field_error(e, format!("Failed to read bytes of '{}'", name)))?;
+            if !is_image(&name) {
+                form.files.insert(name, data);
+                continue;
+            }
+            info!("Received '{}': {} bytes", name, data.len());
+            form.file_names.push(file_name);
+            form.images.push((name, data));
+        } else {
+            let value = field.text().await
+                .map_err(|e| field_error(e, format!("Failed to read field '{}'", name)))?;
+            form.fields.insert(name, value);
+        }
+    }
+
+    if field_count == 0 {
+        return Err(empty_form_error());
+    }
+    Ok(form)
+}
+
+// Just the image fields of `collect_form`, as (field name, bytes)
+pub async fn collect_images(
+    multipart: &mut Multipart,
+    is_image: impl Fn(&str) -> bool,
+) -> Result<Vec<(String, Bytes)>, (StatusCode, String)> {
+    Ok(collect_form(multipart, is_image).await?.images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::multipart_request;
+    use axum::extract::FromRequest;
+
+    async fn multipart(fields: &[(&str, Option<&str>, &[u8])]) -> Multipart {
+        Multipart::from_request(multipart_request("/", fields), &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn splits_images_files_and_text_fields() {
+        let mut body = multipart(&[
+            ("image", Some("bike.png"), b"first"),
+            ("prompt", None, b"chrome"),
+            ("texture_image", Some("tex.png"), b"texture"),
+            ("image2", None, b"second"),
+        ]).await;
+
+        let form = collect_form(&mut body, is_image_field).await.unwrap();
+
+        assert_eq!(form.images, vec![
+            ("image".to_string(), Bytes::from_static(b"first")),
+            ("image2".to_string(), Bytes::from_static(b"second")),
+        ]);
+        assert_eq!(form.file_names, vec![Some("bike.png".to_string()), None]);
+        assert_eq!(form.files["texture_image"], Bytes::from_static(b"texture"));
+        assert_eq!(form.fields["prompt"], "chrome");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_file_name_of_each_repeated_field() {
+        let mut body = multipart(&[
+            ("image", Some("front.png"), b"front"),
+            ("image", Some("side.png"), b"side"),
+        ]).await;
+
+        let form = collect_form(&mut body, is_image_field).await.unwrap();
+
+        assert_eq!(form.images.len(), 2);
+        assert_eq!(form.file_names, vec![Some("front.png".to_string()), Some("side.png".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn collect_images_applies_the_predicate_and_rejects_empty_forms() {
+        let mut body = multipart(&[
+            ("image_motorcycle", Some("bike.png"), b"bike"),
+            ("image", Some("other.png"), b"other"),
+        ]).await;
+        let images = collect_images(&mut body, |name| name == "image_motorcycle").await.unwrap();
+        assert_eq!(images, vec![("image_motorcycle".to_string(), Bytes::from_static(b"bike"))]);
+
+        let err = collect_images(&mut multipart(&[]).await, is_image_field).await.unwrap_err();
+        assert_eq!(err, empty_form_error());
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, ImageResult, RgbaImage};
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::util::encode::OutputFormat;
+use crate::util::image_mask::InvalidOptionError;
+
+// Where the watermark is stamped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for Corner {
+    type Err = InvalidOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            _ => Err(InvalidOptionError {
+                value: s.to_string(),
+                expected: vec!["top-left", "top-right", "bottom-left", "bottom-right"],
+            }),
+        }
+    }
+}
+
+// A logo drawn at its own size in one corner, inset by a margin of 2% of the shorter side
+pub struct Watermark {
+    logo: RgbaImage,
+    corner: Corner,
+}
+
+impl Watermark {
+    pub fn new(logo: RgbaImage, corner: Corner) -> Self {
+        Self { logo, corner }
+    }
+
+    pub fn load(path: &Path, corner: Corner) -> Result<Self> {
+        let logo = image::open(path)
+            .with_context(|| format!("Failed to load watermark {}", path.display()))?
+            .to_rgba8();
+        Ok(Self::new(logo, corner))
+    }
+
+    fn stamp(&self, canvas: &mut RgbaImage) {
+        let (width, height) = canvas.dimensions();
+        let margin = width.min(height) as i64 / 50;
+        let right = width as i64 - self.logo.width() as i64 - margin;
+        let bottom = height as i64 - self.logo.height() as i64 - margin;
+
+        let (x, y) = match self.corner {
+            Corner::TopLeft => (margin, margin),
+            Corner::TopRight => (right, margin),
+            Corner::BottomLeft => (margin, bottom),
+            Corner::BottomRight => (right, bottom),
+        };
+        imageops::overlay(canvas, &self.logo, x, y);
+    }
+}
+
+// Steps applied to every generated image before it goes back to the caller, in order:
+// shrink to `max_dimension`, then stamp the watermark. Each step is optional; `format`
+// replaces Accept negotiation, though an explicit `?format=` still wins.
+#[derive(Default)]
+pub struct PostProcess {
+    max_dimension: Option<u32>,
+    watermark: Option<Watermark>,
+    format: Option<OutputFormat>,
+}
+
+impl PostProcess {
+    pub fn with_max_dimension(mut self, max_dimension: u32) -> Self {
+        self.max_dimension = Some(max_dimension);
+        self
+    }
+
+    pub fn with_watermark(mut self, watermark: Watermark) -> Self {
+        self.watermark = Some(watermark);
+        self
+    }
+
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn format(&self) -> Option<OutputFormat> {
+        self.format
+    }
+
+    // The processed image as PNG; the input is passed through when no step applies to it
+    pub fn apply<'a>(&self, data: &'a [u8]) -> ImageResult<Cow<'a, [u8]>> {
+        if self.max_dimension.is_none() && self.watermark.is_none() {
+            return Ok(Cow::Borrowed(data));
+        }
+
+        let mut img = image::load_from_memory(data)?;
+        let oversized = self.max_dimension.filter(|&max| img.width().max(img.height()) > max);
+        if oversized.is_none() && self.watermark.is_none() {
+            return Ok(Cow::Borrowed(data));
+        }
+        if let Some(max) = oversized {
+            img = img.resize(max, max, FilterType::Lanczos3);
+        }
+
+        let img = match &self.watermark {
+            Some(watermark) => {
+                let mut canvas = img.to_rgba8();
+                watermark.stamp(&mut canvas);
+                DynamicImage::ImageRgba8(canvas)
+            }
+            None => img,
+        };
+
+        let mut png = Vec::new();
+        img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        Ok(Cow::Owned(png))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::png_fixture;
+    use image::GenericImageView;
+
+    #[test]
+    fn without_steps_passes_the_image_through() {
+        let png = png_fixture(8, 8);
+        assert!(matches!(PostProcess::default().apply(&png).unwrap(), Cow::Borrowed(_)));
+
+        let small_enough = PostProcess::default().with_max_dimension(8);
+        assert!(matches!(small_enough.apply(&png).unwrap(), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn shrinks_to_max_dimension_then_stamps_the_corner() {
+        let logo = RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+        let post = PostProcess::default()
+            .with_max_dimension(100)
+            .with_watermark(Watermark::new(logo, Corner::BottomRight));
+
+        let out = image::load_from_memory(&post.apply(&png_fixture(200, 100)).unwrap()).unwrap();
+        assert_eq!(out.dimensions(), (100, 50));
+        // Margin is 2% of the shorter side: 1 px
+        assert_eq!(out.get_pixel(98, 48).0, [255, 0, 0, 255]);
+        assert_eq!(out.get_pixel(95, 45).0, [255, 0, 0, 255]);
+        assert_eq!(out.get_pixel(99, 49).0, [40, 80, 120, 255]);
+        assert_eq!(out.get_pixel(0, 0).0, [40, 80, 120, 255]);
+    }
+
+    #[test]
+    fn parses_corners() {
+        assert_eq!("Top_Left".parse::<Corner>().unwrap(), Corner::TopLeft);
+        assert_eq!("bottom-right".parse::<Corner>().unwrap(), Corner::BottomRight);
+        assert!("middle".parse::<Corner>().is_err());
+    }
+}
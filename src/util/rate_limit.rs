@@ -0,0 +1,47 @@
+use std::fmt;
+
+// An upstream provider turned the request away with a 429 because a quota or rate limit was hit
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimited {
+    pub provider: &'static str,
+    // Seconds the provider asked us to wait, when it said
+    pub retry_after: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rate limit reached: {}", self.provider, self.message)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+// Retry-After in its delay-seconds form; HTTP-date values are ignored
+pub fn retry_after_secs(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+    #[test]
+    fn reads_delay_seconds_only() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after_secs(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static(" 30 "));
+        assert_eq!(retry_after_secs(&headers), Some(30));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(retry_after_secs(&headers), None);
+    }
+}
@@ -0,0 +1,51 @@
+// Output sizes SDXL 1.0 was trained on, the API rejects anything else
+pub const SDXL_SIZES: &[(u32, u32, &str)] = &[
+    (1024, 1024, "1024x1024"),
+    (1152, 896, "1152x896"),
+    (896, 1152, "896x1152"),
+    (1216, 832, "1216x832"),
+    (832, 1216, "832x1216"),
+    (1344, 768, "1344x768"),
+    (768, 1344, "768x1344"),
+    (1536, 640, "1536x640"),
+    (640, 1536, "640x1536"),
+];
+
+// The allowed size whose aspect ratio is closest to `width`:`height`, compared on a log
+// scale so 2:1 and 1:2 are equally far from square
+pub fn nearest_sdxl_size(width: u32, height: u32) -> (u32, u32) {
+    let ratio = |w: u32, h: u32| (w.max(1) as f64 / h.max(1) as f64).ln();
+    let target = ratio(width, height);
+
+    let distance = |&(w, h): &(u32, u32)| (ratio(w, h) - target).abs();
+
+    SDXL_SIZES
+        .iter()
+        .map(|&(w, h, _)| (w, h))
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap_or((1024, 1024))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_input_ratios_to_the_closest_allowed_size() {
+        let cases = [
+            ((1024, 1024), (1024, 1024)),
+            ((500, 480), (1024, 1024)),
+            ((1600, 1200), (1152, 896)),  // 4:3
+            ((1920, 1080), (1344, 768)),  // 16:9
+            ((1080, 1920), (768, 1344)),
+            ((1500, 1000), (1216, 832)),  // 3:2
+            ((4000, 1000), (1536, 640)),  // wider than anything allowed
+            ((600, 800), (896, 1152)),    // 3:4
+            ((0, 0), (1024, 1024)),
+        ];
+
+        for ((width, height), expected) in cases {
+            assert_eq!(nearest_sdxl_size(width, height), expected, "{}x{}", width, height);
+        }
+    }
+}
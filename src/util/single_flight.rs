@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+// Coalesces identical concurrent calls: while one is in flight, later callers with the
+// same key wait for its result instead of making their own. Nothing is kept once it lands.
+pub struct SingleFlight<K, V> {
+    in_flight: Mutex<HashMap<K, watch::Receiver<Option<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self { in_flight: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    // Run `call`, or share the result of an identical call already in flight.
+    // A waiter whose leader is dropped half-way (its client went away) runs its own call.
+    pub async fn run(&self, key: K, call: impl Future<Output = V>) -> V {
+        let leader = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(result) => Err(result.clone()),
+                None => {
+                    let (sender, result) = watch::channel(None);
+                    in_flight.insert(key.clone(), result);
+                    Ok(sender)
+                }
+            }
+        };
+
+        match leader {
+            Ok(sender) => {
+                let _flight = Flight { owner: self, key };
+                let value = call.await;
+                sender.send_replace(Some(value.clone()));
+                value
+            }
+            Err(mut result) => {
+                if let Ok(value) = result.wait_for(Option::is_some).await {
+                    return Option::clone(&value).expect("waited for a value");
+                }
+                call.await
+            }
+        }
+    }
+}
+
+// Clears the key when the leading call finishes or is dropped
+struct Flight<'a, K: Eq + Hash, V> {
+    owner: &'a SingleFlight<K, V>,
+    key: K,
+}
+
+impl<K: Eq + Hash, V> Drop for Flight<'_, K, V> {
+    fn drop(&mut self) {
+        self.owner.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn waiter_runs_its_own_call_when_the_leader_is_dropped() {
+        let flights = SingleFlight::<&str, u32>::default();
+        let calls = AtomicUsize::new(0);
+        let call = |value| {
+            let calls = &calls;
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                value
+            }
+        };
+
+        // The leader is abandoned half-way; the waiter must not hang on it
+        let leader = tokio::time::timeout(Duration::from_millis(10), flights.run("key", call(1)));
+        let (abandoned, waited) = tokio::join!(leader, flights.run("key", call(2)));
+        assert!(abandoned.is_err());
+        assert_eq!(waited, 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Nothing lingers: a later call runs again
+        assert_eq!(flights.run("key", call(3)).await, 3);
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+// In-process request counters for GET /stats; reset on restart
+pub struct Stats {
+    started: Instant,
+    requests: AtomicU64,
+    // Keyed by route pattern (`/api/3d/ws/{task_id}`), not the concrete path
+    routes: RwLock<HashMap<String, AtomicU64>>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            routes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, route: &str) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(count) = self.routes.read().unwrap().get(route) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.routes.write().unwrap()
+            .entry(route.to_string())
+            .or_insert_with(|| AtomicU64::new(0))
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn routes(&self) -> HashMap<String, u64> {
+        self.routes.read().unwrap()
+            .iter()
+            .map(|(route, count)| (route.clone(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_total_and_per_route() {
+        let stats = Stats::new();
+        stats.record("/extract_seat");
+        stats.record("/extract_seat");
+        stats.record("/api/3d/ws/{task_id}");
+
+        assert_eq!(stats.requests(), 3);
+        let routes = stats.routes();
+        assert_eq!(routes["/extract_seat"], 2);
+        assert_eq!(routes["/api/3d/ws/{task_id}"], 1);
+    }
+}
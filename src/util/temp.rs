@@ -0,0 +1,43 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Build a unique file path in the system temp directory
+#[cfg(test)] // the server stages under TEMP_DIR; only tests use the system temp dir
+pub fn unique_temp_path(prefix: &str, extension: &str) -> PathBuf {
+    unique_path_in(&std::env::temp_dir(), prefix, extension)
+}
+
+// Build a unique file path in `dir`
+pub fn unique_path_in(dir: &Path, prefix: &str, extension: &str) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    dir.join(format!(
+        "{}_{}_{}_{}.{}",
+        prefix,
+        std::process::id(),
+        nanos,
+        seq,
+        extension
+    ))
+}
+
+// Refused rather than failed: in containers the working directory is often mounted read-only
+pub fn is_not_writable(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem)
+}
+
+// Create `dir` if needed, then write and remove a probe file in it
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let probe = unique_path_in(dir, ".write_probe", "tmp");
+    std::fs::write(&probe, b"")?;
+    std::fs::remove_file(&probe)
+}